@@ -2,19 +2,24 @@ use crate::{
     data::log_record::{LogRecord, LogRecordType},
     db::Engine,
     errors::{Errors, Result},
-    options::WriteBatchOptions,
+    options::{IteratorOptions, WriteBatchOptions},
+    watch::WatchEvent,
 };
 use bytes::{BufMut, Bytes, BytesMut};
 use parking_lot::Mutex;
 use prost::{decode_length_delimiter, encode_length_delimiter};
 use std::{
-    collections::HashMap,
+    cmp,
+    collections::{BTreeMap, HashMap},
     sync::{atomic::Ordering, Arc},
 };
 
 const TXN_FIN_KEY: &[u8] = "txn_finish".as_bytes();
+const TXN_BEGIN_KEY: &[u8] = "txn_begin".as_bytes();
 pub(crate) const NON_TRANSACTION_SEQ_NO: usize = 0;
 /// 实现批量写操作
+///
+/// commit 或 rollback 之后同一个 WriteBatch 可以继续使用，已分配的内存会被保留
 pub struct WriteBatch<'a> {
     pending_writes: Arc<Mutex<HashMap<Vec<u8>, LogRecord>>>,
     engine: &'a Engine,
@@ -22,66 +27,126 @@ pub struct WriteBatch<'a> {
 }
 impl Engine {
     /// 初始化
-    pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch> {
+    pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch<'_>> {
         Ok(WriteBatch {
             pending_writes: Arc::new(Mutex::new(HashMap::new())),
             engine: self,
             options,
         })
     }
+    /// 创建写满 max 条数据之后自动提交的批量写操作，max 为 0 时按照 1 处理
+    pub fn auto_batch(&self, max: usize) -> AutoBatch<'_> {
+        let max = max.max(1);
+        AutoBatch {
+            batch: WriteBatch {
+                pending_writes: Arc::new(Mutex::new(HashMap::new())),
+                engine: self,
+                options: WriteBatchOptions {
+                    max_batch_num: max,
+                    ..Default::default()
+                },
+            },
+            max,
+        }
+    }
+    /// 使用 pairs 替换数据库中的全部数据，不在 pairs 中的 key 会被删除
+    ///
+    /// 所有的删除和写入作为同一个批次提交，崩溃后不会出现只替换了一部分的情况
+    pub fn replace_all(&self, pairs: Vec<(Bytes, Bytes)>) -> Result<()> {
+        // 获取 key 的快照到提交之间不能有其他写入，否则新写入的 key 不会被删除
+        let _in_flight = self.shutdown.enter();
+        let _lock = self.batch_commit_lock.lock();
+        let keys = self.list_keys()?;
+        let wb = self.new_write_batch(WriteBatchOptions {
+            max_batch_num: keys.len() + pairs.len(),
+            ..Default::default()
+        })?;
+        for key in keys {
+            wb.delete(key)?;
+        }
+        for (key, value) in pairs {
+            wb.put(key, value)?;
+        }
+        wb.commit_locked()
+    }
 }
 impl WriteBatch<'_> {
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
-        if key.is_empty() {
-            return Err(Errors::KeyIsEmpty);
-        }
+        let key = self.engine.normalize_key(key);
+        self.engine.check_key(&key)?;
         let log_record = LogRecord {
             key: key.to_vec(),
             value: value.to_vec(),
             rec_type: LogRecordType::NORMAL,
+            flags: 0,
         };
         let mut pending_writes = self.pending_writes.lock();
         pending_writes.insert(key.to_vec(), log_record);
         Ok(())
     }
     pub fn delete(&self, key: Bytes) -> Result<()> {
-        if key.is_empty() {
-            return Err(Errors::KeyIsEmpty);
+        let key = self.engine.normalize_key(key);
+        self.engine.check_key(&key)?;
+        if self.engine.options().append_only {
+            return Err(Errors::DeletesDisabled);
         }
         let mut pending_writes = self.pending_writes.lock();
-        let index_pos = self.engine.index.get(key.to_vec());
-        if index_pos.is_none() {
-            if pending_writes.contains_key(&key.to_vec()) {
-                pending_writes.remove(&key.to_vec());
-            }
+        let index_pos = self.engine.lookup_index(&key);
+        if index_pos.is_none() && pending_writes.contains_key(&key.to_vec()) {
+            pending_writes.remove(&key.to_vec());
         }
         let log_record = LogRecord {
             key: key.to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::DElETED,
+            flags: 0,
         };
         pending_writes.insert(key.to_vec(), log_record);
         Ok(())
     }
     pub fn commit(&self) -> Result<()> {
+        let _in_flight = self.engine.shutdown.enter();
+        let _lock = self.engine.batch_commit_lock.lock();
+        self.commit_locked()
+    }
+    /// 提交批次，调用方需要已经持有 batch_commit_lock
+    fn commit_locked(&self) -> Result<()> {
         let mut pending_writes = self.pending_writes.lock();
-        if pending_writes.len() == 0 {
+        if pending_writes.is_empty() {
             return Ok(());
         }
         if pending_writes.len() > self.options.max_batch_num {
             return Err(Errors::ExceedMaxBatchNum);
         }
 
-        let _lock = self.engine.batch_commit_lock.lock();
+        // 只追加模式下批次中的 key 都不能已经存在
+        if self.engine.options().append_only
+            && pending_writes
+                .keys()
+                .any(|key| self.engine.lookup_index(key).is_some())
+        {
+            return Err(Errors::KeyAlreadyExists);
+        }
+        let _write_lock = self.engine.write_lock.read();
 
         let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
 
+        // 先写入开始标记，崩溃时可以找到没有完成的批次
+        let mut begin_record = LogRecord {
+            key: log_record_key_with_seq(TXN_BEGIN_KEY.to_vec(), seq_no),
+            value: Default::default(),
+            rec_type: LogRecordType::TxnBegin,
+            flags: 0,
+        };
+        self.engine.append_log_record(&mut begin_record)?;
+
         let mut positions = HashMap::new();
         for (_, item) in pending_writes.iter() {
             let mut log_record = LogRecord {
                 key: log_record_key_with_seq(item.key.clone(), seq_no),
                 value: item.value.clone(),
                 rec_type: item.rec_type,
+                flags: item.flags,
             };
             let pos = self.engine.append_log_record(&mut log_record)?;
             positions.insert(item.key.clone(), pos);
@@ -90,6 +155,7 @@ impl WriteBatch<'_> {
             key: log_record_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
             value: Default::default(),
             rec_type: LogRecordType::TxnFinish,
+            flags: 0,
         };
         self.engine.append_log_record(&mut finish_record)?;
 
@@ -101,15 +167,104 @@ impl WriteBatch<'_> {
         for (_, item) in pending_writes.iter() {
             let record_pos = positions.get(&item.key).unwrap();
             if item.rec_type == LogRecordType::NORMAL {
-                self.engine.index.put(item.key.clone(), *record_pos);
+                self.engine
+                    .update_index_or_defer(item.key.clone(), Some(*record_pos));
             }
             if item.rec_type == LogRecordType::DElETED {
-                self.engine.index.delete(item.key.clone());
+                self.engine.update_index_or_defer(item.key.clone(), None);
             }
+            self.engine
+                .record_change(item.rec_type, item.key.clone(), item.value.clone())?;
+        }
+        // 延迟更新索引时，需要再次 sync 才能让这个批次的数据更新到索引中
+        if self.engine.options().defer_index_until_sync && self.options.sync_writes {
+            self.engine.sync()?;
         }
+        for (key, item) in pending_writes.iter() {
+            let event = match item.rec_type {
+                LogRecordType::DElETED => WatchEvent::Delete,
+                _ => WatchEvent::Put(item.value.clone().into()),
+            };
+            self.engine.notify_watchers(key, event);
+        }
+        // clear 会保留已分配的容量，便于复用
         pending_writes.clear();
         Ok(())
     }
+    /// 丢弃所有未提交的数据，之后可以继续使用
+    pub fn rollback(&self) {
+        let mut pending_writes = self.pending_writes.lock();
+        pending_writes.clear();
+    }
+    /// 按照 key 的顺序遍历提交之后的数据：未提交的写入覆盖数据库中的 value，未提交的删除隐藏对应的 key
+    ///
+    /// 未提交的数据在调用时复制，之后对批次的修改不会反映到已经创建的迭代器中
+    pub fn iter(&self) -> impl std::iter::Iterator<Item = (Bytes, Bytes)> + '_ {
+        // None 表示删除
+        let pending: BTreeMap<Vec<u8>, Option<Bytes>> = self
+            .pending_writes
+            .lock()
+            .iter()
+            .map(|(key, record)| {
+                let value = match record.rec_type {
+                    LogRecordType::DElETED => None,
+                    _ => Some(Bytes::from(record.value.clone())),
+                };
+                (key.clone(), value)
+            })
+            .collect();
+        let mut pending = pending.into_iter().peekable();
+        let engine_iter = self.engine.iter(IteratorOptions::default());
+        let mut committed = std::iter::from_fn(move || engine_iter.next()).peekable();
+
+        std::iter::from_fn(move || loop {
+            let order = match (committed.peek(), pending.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => cmp::Ordering::Less,
+                (None, Some(_)) => cmp::Ordering::Greater,
+                (Some((key, _)), Some((pending_key, _))) => key[..].cmp(&pending_key[..]),
+            };
+            match order {
+                cmp::Ordering::Less => return committed.next(),
+                // 数据库中的 value 被批次中的写入或者删除覆盖
+                cmp::Ordering::Equal => {
+                    committed.next();
+                }
+                cmp::Ordering::Greater => {}
+            }
+            if let Some((key, Some(value))) = pending.next() {
+                return Some((key.into(), value));
+            }
+        })
+    }
+}
+
+/// 写满 max 条数据之后自动提交的批量写操作，用于大量数据的导入
+///
+/// 每次自动提交都是一个单独的批次，最后剩余的数据需要调用 flush 提交，没有提交就被丢弃的数据不会写入
+pub struct AutoBatch<'a> {
+    batch: WriteBatch<'a>,
+    max: usize,
+}
+impl AutoBatch<'_> {
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+        self.batch.put(key, value)?;
+        self.commit_if_full()
+    }
+    pub fn delete(&self, key: Bytes) -> Result<()> {
+        self.batch.delete(key)?;
+        self.commit_if_full()
+    }
+    /// 提交剩余的数据
+    pub fn flush(&self) -> Result<()> {
+        self.batch.commit()
+    }
+    fn commit_if_full(&self) -> Result<()> {
+        if self.batch.pending_writes.lock().len() >= self.max {
+            return self.batch.commit();
+        }
+        Ok(())
+    }
 }
 
 pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
@@ -118,12 +273,16 @@ pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
     enc_key.extend_from_slice(&key.to_vec());
     enc_key.to_vec()
 }
-pub(crate) fn parse_log_record_key(key: Vec<u8>) -> (Vec<u8>, usize) {
+/// 解析带序列号前缀的 key，前缀损坏时返回 InvalidLogRecordKey
+pub(crate) fn parse_log_record_key(key: Vec<u8>) -> Result<(Vec<u8>, usize)> {
     let mut buf = BytesMut::new();
     buf.put_slice(&key);
-    let seq_no = decode_length_delimiter(&mut buf).unwrap();
+    let seq_no = match decode_length_delimiter(&mut buf) {
+        Ok(seq_no) => seq_no,
+        Err(_) => return Err(Errors::InvalidLogRecordKey),
+    };
 
-    (buf.to_vec(), seq_no)
+    Ok((buf.to_vec(), seq_no))
 }
 
 #[cfg(test)]
@@ -211,4 +370,206 @@ mod tests {
         // 删除测试的文件夹
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+    #[test]
+    fn test_parse_log_record_key() {
+        let enc_key = log_record_key_with_seq("xia".as_bytes().to_vec(), 300);
+        let (key, seq_no) = parse_log_record_key(enc_key.clone()).unwrap();
+        assert_eq!("xia".as_bytes().to_vec(), key);
+        assert_eq!(300, seq_no);
+
+        // 截断或者损坏的前缀不会 panic
+        let res1 = parse_log_record_key(Vec::new());
+        assert_eq!(Errors::InvalidLogRecordKey, res1.err().unwrap());
+        let res2 = parse_log_record_key(enc_key[..1].to_vec());
+        assert_eq!(Errors::InvalidLogRecordKey, res2.err().unwrap());
+        let res3 = parse_log_record_key(vec![0xff; 16]);
+        assert_eq!(Errors::InvalidLogRecordKey, res3.err().unwrap());
+    }
+    #[test]
+    fn test_replace_all() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-replace-all"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+
+        // 与已有数据部分重叠
+        let pairs: Vec<(Bytes, Bytes)> = (5..15)
+            .map(|i| (get_test_key(i), get_test_value(i as usize + 100)))
+            .collect();
+        engine.replace_all(pairs.clone()).unwrap();
+
+        let keys = engine.list_keys().unwrap();
+        assert_eq!(pairs.len(), keys.len());
+        for (key, value) in pairs.iter() {
+            assert_eq!(*value, engine.get(key.clone()).unwrap());
+        }
+        for i in 0..5 {
+            assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(i)).err().unwrap());
+        }
+
+        // 重启之后数据保持一致
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(pairs.len(), engine2.list_keys().unwrap().len());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+    #[test]
+    fn test_write_batch_reuse() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-batch-reuse"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+
+        for i in 0..100 {
+            wb.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        let capacity = wb.pending_writes.lock().capacity();
+        wb.commit().unwrap();
+        assert!(wb.pending_writes.lock().is_empty());
+        assert!(wb.pending_writes.lock().capacity() >= capacity);
+
+        // rollback 之后的数据不会被提交
+        wb.put(get_test_key(200), get_test_value(200)).unwrap();
+        wb.rollback();
+        assert!(wb.pending_writes.lock().is_empty());
+        assert!(wb.pending_writes.lock().capacity() >= capacity);
+        wb.commit().unwrap();
+        assert_eq!(
+            Errors::KeyNotFound,
+            engine.get(get_test_key(200)).err().unwrap()
+        );
+
+        // 复用之后继续提交
+        wb.put(get_test_key(300), get_test_value(300)).unwrap();
+        wb.delete(get_test_key(1)).unwrap();
+        wb.commit().unwrap();
+        assert_eq!(get_test_value(300), engine.get(get_test_key(300)).unwrap());
+        assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(1)).err().unwrap());
+        assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+        assert_eq!(3, engine.seq_no.load(Ordering::SeqCst));
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+    #[test]
+    fn test_transactions() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-batch-transactions"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.transactions().unwrap().is_empty());
+
+        let commit_batches = |engine: &Engine, count: i32| {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            for i in 0..count {
+                wb.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+                wb.commit().unwrap();
+                // 非事务的写入不会出现在事务列表中
+                engine.put(get_test_key(100), get_test_value(100)).unwrap();
+            }
+        };
+        commit_batches(&engine, 3);
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(vec![1, 2, 3], engine2.transactions().unwrap());
+        commit_batches(&engine2, 2);
+        // 打开之后提交的事务不会出现在列表中
+        assert_eq!(vec![1, 2, 3], engine2.transactions().unwrap());
+        engine2.close().unwrap();
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(vec![1, 2, 3, 4, 5], engine3.transactions().unwrap());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+    #[test]
+    fn test_auto_batch() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-auto-batch"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let auto_batch = engine.auto_batch(100);
+        for i in 0..250 {
+            auto_batch.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        // 没有 flush 之前剩余的 50 条数据还没有提交
+        assert_eq!(200, engine.list_keys().unwrap().len());
+        auto_batch.flush().unwrap();
+        assert_eq!(250, engine.list_keys().unwrap().len());
+
+        // 重启之后可以看到三个批次
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(vec![1, 2, 3], engine2.transactions().unwrap());
+        for i in 0..250 {
+            assert_eq!(get_test_value(i as usize), engine2.get(get_test_key(i)).unwrap());
+        }
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+    #[test]
+    fn test_write_batch_iter() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-batch-iter"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for key in ["a", "c", "e"] {
+            engine.put(Bytes::from(key), Bytes::from(format!("{}-old", key))).unwrap();
+        }
+
+        let wb = engine.new_write_batch(WriteBatchOptions::default()).unwrap();
+        wb.put(Bytes::from("b"), Bytes::from("b-new")).unwrap();
+        wb.put(Bytes::from("c"), Bytes::from("c-new")).unwrap();
+        wb.delete(Bytes::from("e")).unwrap();
+        wb.put(Bytes::from("f"), Bytes::from("f-new")).unwrap();
+        // 删除不存在的 key 不影响结果
+        wb.delete(Bytes::from("g")).unwrap();
+
+        // 1.批次的迭代器反映未提交的写入和删除
+        let expected = vec![
+            (Bytes::from("a"), Bytes::from("a-old")),
+            (Bytes::from("b"), Bytes::from("b-new")),
+            (Bytes::from("c"), Bytes::from("c-new")),
+            (Bytes::from("f"), Bytes::from("f-new")),
+        ];
+        assert_eq!(expected, wb.iter().collect::<Vec<_>>());
+
+        // 2.数据库的迭代器只能看到已经提交的数据
+        let engine_iter = engine.iter(IteratorOptions::default());
+        let committed: Vec<_> = std::iter::from_fn(|| engine_iter.next()).collect();
+        assert_eq!(3, committed.len());
+        assert_eq!((Bytes::from("e"), Bytes::from("e-old")), committed[2]);
+
+        // 3.提交之后数据库的内容与批次的迭代器一致
+        wb.commit().unwrap();
+        let engine_iter = engine.iter(IteratorOptions::default());
+        let committed: Vec<_> = std::iter::from_fn(|| engine_iter.next()).collect();
+        assert_eq!(expected, committed);
+        assert_eq!(expected, wb.iter().collect::<Vec<_>>());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
 }
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+use crate::{
+    data::log_record::LogRecordType,
+    db::Engine,
+    errors::{Errors, Result},
+};
+
+/// 数据变更的类型
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeType {
+    Put,
+    Delete,
+}
+
+/// 一条数据变更记录
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// 变更的序号，按照写入日志的顺序从 1 开始递增
+    ///
+    /// 序号只在当前进程中有效，重启之后根据数据文件重新编号，合并之后同一个变更的序号可能不同
+    pub seq_no: usize,
+    pub change_type: ChangeType,
+    pub key: Bytes,
+    /// 删除操作的 value 为空
+    pub value: Bytes,
+}
+
+/// 内存中最近的数据变更，最多保留 max_change_log_len 条
+#[derive(Default)]
+pub(crate) struct ChangeLog {
+    changes: VecDeque<Change>,
+    /// 最后一条变更的序号，已经丢弃的变更同样计算在内
+    last_seq_no: usize,
+}
+
+impl ChangeLog {
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Engine {
+    /// 获取序号大于 seq_no 的所有数据变更（包括删除），需要开启 enable_change_log
+    ///
+    /// 其中一部分变更已经因为超过 max_change_log_len 被丢弃时返回 ChangesTrimmed
+    pub fn changes_since(&self, seq_no: usize) -> Result<Vec<Change>> {
+        let change_log = self.change_log.read();
+        let first_seq_no = change_log.last_seq_no + 1 - change_log.changes.len();
+        let next_seq_no = seq_no.saturating_add(1);
+        if next_seq_no < first_seq_no {
+            return Err(Errors::ChangesTrimmed);
+        }
+        let skip = next_seq_no - first_seq_no;
+        Ok(change_log.changes.iter().skip(skip).cloned().collect())
+    }
+    /// 记录一条已经生效的变更，同时更新 value 前缀索引
+    pub(crate) fn record_change(
+        &self,
+        rec_type: LogRecordType,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        if !self.options().enable_change_log && self.value_index.is_none() {
+            return Ok(());
+        }
+        let (change_type, value) = match rec_type {
+            LogRecordType::NORMAL => (ChangeType::Put, value),
+            LogRecordType::BlobPointer => (ChangeType::Put, self.read_blob_value(&value)?),
+            LogRecordType::DElETED => (ChangeType::Delete, value),
+            LogRecordType::TxnFinish | LogRecordType::TxnBegin => return Ok(()),
+        };
+        if let Some(value_index) = &self.value_index {
+            match change_type {
+                ChangeType::Put => value_index.put(&key, &value),
+                ChangeType::Delete => value_index.delete(&key),
+            }
+        }
+        if !self.options().enable_change_log {
+            return Ok(());
+        }
+        let max_len = self.options().max_change_log_len;
+        let mut change_log = self.change_log.write();
+        change_log.last_seq_no += 1;
+        let seq_no = change_log.last_seq_no;
+        change_log.changes.push_back(Change {
+            seq_no,
+            change_type,
+            key: key.into(),
+            value: value.into(),
+        });
+        while change_log.changes.len() > max_len {
+            change_log.changes.pop_front();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        options::{Options, WriteBatchOptions},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+    #[test]
+    fn test_changes_since() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-changes-since"),
+            data_file_size: 64 * 1024 * 1024,
+            enable_change_log: true,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.changes_since(0).unwrap().is_empty());
+
+        engine.put(get_test_key(1), get_test_value(1)).unwrap();
+        engine.put(get_test_key(2), get_test_value(2)).unwrap();
+        engine.delete(get_test_key(1)).unwrap();
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        wb.delete(get_test_key(2)).unwrap();
+        wb.commit().unwrap();
+
+        let changes = engine.changes_since(0).unwrap();
+        assert_eq!(4, changes.len());
+        assert_eq!(ChangeType::Put, changes[0].change_type);
+        assert_eq!(get_test_key(1), changes[0].key);
+        assert_eq!(get_test_value(1), changes[0].value);
+        assert_eq!(ChangeType::Put, changes[1].change_type);
+        assert_eq!(ChangeType::Delete, changes[2].change_type);
+        assert_eq!(get_test_key(1), changes[2].key);
+        assert_eq!(ChangeType::Delete, changes[3].change_type);
+        assert_eq!(get_test_key(2), changes[3].key);
+
+        let tail = engine.changes_since(2).unwrap();
+        assert_eq!(2, tail.len());
+        assert_eq!(3, tail[0].seq_no);
+
+        // 重启后变更记录从数据文件中恢复
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(changes, engine2.changes_since(0).unwrap());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_changes_since_trimmed() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-changes-trimmed"),
+            data_file_size: 64 * 1024 * 1024,
+            enable_change_log: true,
+            max_change_log_len: 10,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..25 {
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+
+        // 1.只保留最近的 10 条变更，序号继续递增
+        let changes = engine.changes_since(15).unwrap();
+        assert_eq!(10, changes.len());
+        assert_eq!(16, changes[0].seq_no);
+        assert_eq!(get_test_key(15), changes[0].key);
+        assert_eq!(25, changes[9].seq_no);
+        assert!(engine.changes_since(25).unwrap().is_empty());
+
+        // 2.需要的变更已经被丢弃时返回错误
+        let res = engine.changes_since(14);
+        assert_eq!(Errors::ChangesTrimmed, res.err().unwrap());
+        let res = engine.changes_since(0);
+        assert_eq!(Errors::ChangesTrimmed, res.err().unwrap());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use std::{path::Path, sync::Arc};
+
+use bytes::{Buf, BufMut, BytesMut};
+use parking_lot::RwLock;
+
+use crate::{
+    errors::{Errors, Result},
+    fio::{self, new_io_manager},
+    options::Options,
+};
+
+pub const BLOB_FILE_NAME: &str = "values.blob";
+/// 指针的编码长度 offset(8) + size(8) + crc(4)
+const BLOB_POINTER_SIZE: usize = 8 + 8 + 4;
+
+/// 存放大 value 的文件，日志中只保存指向这里的 BlobPointer
+pub struct BlobFile {
+    write_off: Arc<RwLock<u64>>,
+    io_manager: RwLock<Box<dyn fio::IOManager>>,
+}
+
+/// 一个 value 在 blob 文件中的位置
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlobPointer {
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+    pub(crate) crc: u32,
+}
+
+impl BlobFile {
+    pub fn new(options: &Options) -> Result<BlobFile> {
+        let file_name = options.dir_path.join(BLOB_FILE_NAME);
+        let io_manager = new_io_manager(file_name, options)?;
+        let write_off = io_manager.size()?;
+        Ok(BlobFile {
+            write_off: Arc::new(RwLock::new(write_off)),
+            io_manager: RwLock::new(io_manager),
+        })
+    }
+    /// 数据目录移动之后打开新位置的文件，写入位置保持不变
+    pub fn reopen(&self, options: &Options) -> Result<()> {
+        let file_name = options.dir_path.join(BLOB_FILE_NAME);
+        *self.io_manager.write() = new_io_manager(file_name, options)?;
+        Ok(())
+    }
+    pub fn get_write_off(&self) -> u64 {
+        *self.write_off.read()
+    }
+    pub fn exists(dir_path: &Path) -> bool {
+        dir_path.join(BLOB_FILE_NAME).is_file()
+    }
+    pub fn write(&self, value: &[u8]) -> Result<BlobPointer> {
+        let mut write_off = self.write_off.write();
+        let offset = *write_off;
+        let n_bytes = self.io_manager.read().write(value)?;
+        *write_off += n_bytes as u64;
+        Ok(BlobPointer {
+            offset,
+            size: value.len() as u64,
+            crc: crc32fast::hash(value),
+        })
+    }
+    pub fn read(&self, pointer: &BlobPointer) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; pointer.size as usize];
+        self.io_manager.read().read(&mut buf, pointer.offset)?;
+        if crc32fast::hash(&buf) != pointer.crc {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
+        Ok(buf)
+    }
+    /// 读取 value 的一部分，无法进行 crc 校验
+    pub fn read_range(&self, pointer: &BlobPointer, start: u64, len: u64) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; len as usize];
+        self.io_manager.read().read(&mut buf, pointer.offset + start)?;
+        Ok(buf)
+    }
+    pub fn sync(&self) -> Result<()> {
+        self.io_manager.read().sync()
+    }
+}
+
+impl BlobPointer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(BLOB_POINTER_SIZE);
+        buf.put_u64(self.offset);
+        buf.put_u64(self.size);
+        buf.put_u32(self.crc);
+        buf.to_vec()
+    }
+    pub fn decode(buf: &[u8]) -> Result<BlobPointer> {
+        if buf.len() != BLOB_POINTER_SIZE {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let mut buf = buf;
+        Ok(BlobPointer {
+            offset: buf.get_u64(),
+            size: buf.get_u64(),
+            crc: buf.get_u32(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    #[test]
+    fn test_blob_file_write_and_read() {
+        let dir_path = PathBuf::from("./tmp/bitcask-rs-blob-file");
+        fs::create_dir_all(dir_path.clone()).unwrap();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
+
+        let blob_file = BlobFile::new(&opts).unwrap();
+        let pointer1 = blob_file.write("xia".as_bytes()).unwrap();
+        let pointer2 = blob_file.write("sang".as_bytes()).unwrap();
+        assert_eq!(0, pointer1.offset);
+        assert_eq!(3, pointer2.offset);
+
+        let decoded = BlobPointer::decode(&pointer2.encode()).unwrap();
+        assert_eq!(pointer2, decoded);
+        assert_eq!("sang".as_bytes().to_vec(), blob_file.read(&decoded).unwrap());
+
+        // 重新打开后从文件末尾继续写入
+        let blob_file2 = BlobFile::new(&opts).unwrap();
+        let pointer3 = blob_file2.write("bitcask".as_bytes()).unwrap();
+        assert_eq!(7, pointer3.offset);
+        assert_eq!("xia".as_bytes().to_vec(), blob_file2.read(&pointer1).unwrap());
+
+        fs::remove_dir_all(dir_path).unwrap();
+    }
+}
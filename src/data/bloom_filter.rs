@@ -0,0 +1,130 @@
+use std::{fs, path::PathBuf};
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::{
+    data::data_file::get_data_file_dir,
+    errors::{Errors, Result},
+    options::Options,
+};
+
+pub const BLOOM_FILE_NAME_SUFFIX: &str = ".bloom";
+/// 每个 key 占用的 bit 数，误判率约为 1%
+const BITS_PER_KEY: usize = 10;
+/// 哈希函数的个数
+const NUM_HASHES: u32 = 7;
+
+/// 数据文件的布隆过滤器，用来判断一个 key 是否一定不在文件中
+#[derive(Clone, Debug, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(expected_keys: usize) -> BloomFilter {
+        let num_bits = (expected_keys * BITS_PER_KEY).max(64);
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_hashes: NUM_HASHES,
+        }
+    }
+    pub fn add(&mut self, key: &[u8]) {
+        for bit in self.bit_positions(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    /// 返回 false 时 key 一定不存在，返回 true 时 key 可能存在
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+    // 双重哈希：第 i 个位置为 h1 + i * h2
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let num_bits = (self.bits.len() * 64) as u64;
+        let h1 = crc32fast::hash(key) as u64;
+        let h2 = (fnv1a_hash(key) | 1) as u64;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(4 + self.bits.len() * 8 + 4);
+        buf.put_u32(self.num_hashes);
+        for word in self.bits.iter() {
+            buf.put_u64(*word);
+        }
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+        buf.to_vec()
+    }
+    pub fn decode(buf: &[u8]) -> Result<BloomFilter> {
+        if buf.len() < 8 || !(buf.len() - 8).is_multiple_of(8) {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let (content, mut crc_buf) = buf.split_at(buf.len() - 4);
+        if crc32fast::hash(content) != crc_buf.get_u32() {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let mut content = content;
+        let num_hashes = content.get_u32();
+        let mut bits = Vec::with_capacity(content.len() / 8);
+        while content.has_remaining() {
+            bits.push(content.get_u64());
+        }
+        if bits.is_empty() {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        Ok(BloomFilter { bits, num_hashes })
+    }
+    pub fn save(&self, file_name: PathBuf) -> Result<()> {
+        fs::write(file_name, self.encode()).map_err(|_| Errors::FailedToWriteToDataFile)
+    }
+    /// 读取布隆过滤器文件，文件不存在或者损坏时返回 None
+    pub fn load(file_name: PathBuf) -> Option<BloomFilter> {
+        let buf = fs::read(file_name).ok()?;
+        BloomFilter::decode(&buf).ok()
+    }
+}
+
+/// 布隆过滤器文件与数据文件存放在同一个目录中
+pub(crate) fn get_bloom_file_name(options: &Options, file_id: u32) -> PathBuf {
+    let name = std::format!("{:09}", file_id) + BLOOM_FILE_NAME_SUFFIX;
+    get_data_file_dir(options, file_id).join(name)
+}
+
+fn fnv1a_hash(key: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in key {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::rand_kv::get_test_key;
+
+    use super::*;
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000);
+        for i in 0..1000 {
+            filter.add(&get_test_key(i));
+        }
+        for i in 0..1000 {
+            assert!(filter.may_contain(&get_test_key(i)));
+        }
+        // 误判率应该远低于 10%
+        let false_positives = (1000..11000)
+            .filter(|i| filter.may_contain(&get_test_key(*i)))
+            .count();
+        assert!(false_positives < 1000);
+
+        let decoded = BloomFilter::decode(&filter.encode()).unwrap();
+        assert_eq!(filter, decoded);
+        let mut corrupted = filter.encode();
+        corrupted[5] ^= 0xff;
+        assert!(BloomFilter::decode(&corrupted).is_err());
+    }
+}
@@ -1,31 +1,95 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{fs, path::PathBuf, sync::Arc};
 
 use crate::{
-    data::log_record::max_long_record,
+    data::log_record::{
+        record_header_size, FIXED_LOG_RECORD_HEADER_SIZE, MAX_LOG_RECORD_HEADER_SIZE,
+        RECORD_FLAGS_BIT,
+    },
     errors::{Errors, Result},
     fio::{self, new_io_manager},
+    options::{Options, RecordLayout},
 };
 use bytes::{Buf, BytesMut};
-use parking_lot::RwLock;
-use prost::{decode_length_delimiter, length_delimiter_len};
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use prost::decode_length_delimiter;
 
-use super::log_record::{LogRecord, LogRecordType, ReadLogRecord};
+use super::{
+    bloom_filter::{BloomFilter, BLOOM_FILE_NAME_SUFFIX},
+    footer::{get_footer_file_name, FileFooter, FooterBuilder},
+    log_record::{LogRecord, LogRecordType, ReadLogRecord},
+};
 pub const DATA_FILE_NAME_SUFFIX: &str = ".data";
+
+/// 记录头部解析出来的信息
+pub struct LogRecordHeader {
+    pub rec_type: LogRecordType,
+    pub flags: u8,
+    pub header_size: usize,
+    pub key_size: usize,
+    pub value_size: usize,
+}
+
+impl LogRecordHeader {
+    /// 从 offset 开始的整条记录的长度以及结束位置
+    ///
+    /// 损坏的头部中的长度可能非常大，相加溢出时返回 DataDirectoryCorrupted
+    pub fn record_bounds(&self, offset: u64) -> Result<(usize, u64)> {
+        let record_size = self
+            .header_size
+            .checked_add(self.key_size)
+            .and_then(|n| n.checked_add(self.value_size))
+            .and_then(|n| n.checked_add(4))
+            .ok_or(Errors::DataDirectoryCorrupted)?;
+        let record_end = offset
+            .checked_add(record_size as u64)
+            .ok_or(Errors::DataDirectoryCorrupted)?;
+        Ok((record_size, record_end))
+    }
+}
+
 pub struct DataFile {
     file_id: Arc<RwLock<u32>>,
     write_off: Arc<RwLock<u64>>,
-    io_manager: Box<dyn fio::IOManager>,
+    /// 已经持久化到磁盘的偏移
+    synced_off: Arc<RwLock<u64>>,
+    /// 文件句柄，关闭之后在下一次读写时重新打开
+    io_manager: RwLock<Option<Box<dyn fio::IOManager>>>,
+    file_name: PathBuf,
+    options: Options,
+    /// 合并生成的文件带有布隆过滤器
+    bloom_filter: Option<BloomFilter>,
+    /// 开启 file_footer 时随着写入更新的摘要，None 表示需要先读取文件中已有的记录
+    footer: Mutex<Option<FooterBuilder>>,
 }
 impl DataFile {
-    pub fn new(dir_path: PathBuf, file_id: u32) -> Result<DataFile> {
-        let file_name = get_data_file_name(dir_path, file_id);
-        let io_manager = new_io_manager(file_name)?;
+    pub fn new(options: &Options, file_id: u32) -> Result<DataFile> {
+        let file_dir = get_data_file_dir(options, file_id);
+        if !file_dir.is_dir() {
+            fs::create_dir_all(file_dir).map_err(|_| Errors::FailedToOpenDataFile)?;
+        }
+        DataFile::open_path(options, file_id, get_data_file_name(options, file_id))
+    }
+    /// 打开 file_name 位置的数据文件，不要求文件位于 options 对应的目录布局中
+    pub fn open_path(options: &Options, file_id: u32, file_name: PathBuf) -> Result<DataFile> {
+        let io_manager = new_io_manager(file_name.clone(), options)?;
+        let bloom_filter = BloomFilter::load(file_name.with_extension(&BLOOM_FILE_NAME_SUFFIX[1..]));
         Ok(DataFile {
             file_id: Arc::new(RwLock::new(file_id)),
             write_off: Arc::new(RwLock::new(0)),
-            io_manager: Box::new(io_manager),
+            synced_off: Arc::new(RwLock::new(0)),
+            io_manager: RwLock::new(Some(io_manager)),
+            file_name,
+            options: options.clone(),
+            bloom_filter,
+            footer: Mutex::new(None),
         })
     }
+    /// 新建活跃文件，开启了 preallocate 时预先分配空间
+    pub fn new_active(options: &Options, file_id: u32) -> Result<DataFile> {
+        let data_file = DataFile::new(options, file_id)?;
+        data_file.preallocate()?;
+        Ok(data_file)
+    }
     pub fn get_write_off(&self) -> u64 {
         let read_guard = self.write_off.read();
         *read_guard
@@ -34,55 +98,267 @@ impl DataFile {
         let mut write_guard = self.write_off.write();
         *write_guard = offset;
     }
+    pub fn get_synced_off(&self) -> u64 {
+        let read_guard = self.synced_off.read();
+        *read_guard
+    }
+    pub fn set_synced_offset(&self, offset: u64) {
+        let mut write_guard = self.synced_off.write();
+        *write_guard = offset;
+    }
     pub fn get_file_id(&self) -> u32 {
         let read_guard = self.file_id.read();
         *read_guard
     }
-    pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
-        let mut header_buf = BytesMut::zeroed(max_long_record());
+    /// 文件在磁盘上的实际大小
+    pub fn file_size(&self) -> Result<u64> {
+        self.with_io(|io| io.size())
+    }
+    /// 根据布隆过滤器判断文件中是否可能存在该 key，没有过滤器时总是返回 true
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        match self.bloom_filter.as_ref() {
+            Some(filter) => filter.may_contain(key),
+            None => true,
+        }
+    }
+    #[cfg(test)]
+    pub(crate) fn set_io_manager(&mut self, io_manager: Box<dyn fio::IOManager>) {
+        *self.io_manager.write() = Some(io_manager);
+    }
+    /// 文件句柄当前是否处于打开状态
+    #[cfg(test)]
+    pub(crate) fn is_handle_open(&self) -> bool {
+        self.io_manager.read().is_some()
+    }
+    /// 持久化并关闭文件句柄，之后的读写会重新打开文件
+    pub fn close_handle(&self) -> Result<()> {
+        let mut io_manager = self.io_manager.write();
+        if let Some(io) = io_manager.take() {
+            io.sync()?;
+            self.set_synced_offset(self.get_write_off());
+        }
+        Ok(())
+    }
+    /// 使用文件句柄执行 f，句柄已经关闭时先重新打开
+    fn with_io<T>(&self, f: impl FnOnce(&dyn fio::IOManager) -> Result<T>) -> Result<T> {
+        let read_guard = self.io_manager.read();
+        if let Some(io) = read_guard.as_ref() {
+            return f(io.as_ref());
+        }
+        drop(read_guard);
 
-        self.io_manager.read(&mut header_buf, offset)?;
+        let mut write_guard = self.io_manager.write();
+        if write_guard.is_none() {
+            *write_guard = Some(new_io_manager(self.file_name.clone(), &self.options)?);
+        }
+        let read_guard = RwLockWriteGuard::downgrade(write_guard);
+        f(read_guard.as_ref().unwrap().as_ref())
+    }
+    pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
+        let (read_record, crc_ok) = self.read_log_record_unverified(offset)?;
+        if !crc_ok {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
+        Ok(read_record)
+    }
+    /// 读取一条记录，crc 校验失败时不报错，而是在返回值中标记 crc 是否正确
+    pub fn read_log_record_unverified(&self, offset: u64) -> Result<(ReadLogRecord, bool)> {
+        let header = self.read_log_record_header(offset)?;
+        // 损坏的头部中的长度可能非常大，分配内存之前先检查记录是否超出文件的范围
+        let (record_size, record_end) = header.record_bounds(offset)?;
+        let LogRecordHeader {
+            rec_type,
+            flags,
+            header_size: actual_header_size,
+            key_size,
+            value_size,
+        } = header;
+        // 写入位置之前都是完整的记录，只有启动时扫描文件才需要获取文件大小
+        if record_end > self.get_write_off() && record_end > self.file_size()? {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
+        self.with_io(|io| io.read(&mut kv_buf, offset + actual_header_size as u64))?;
+        let log_record = LogRecord {
+            key: kv_buf[..key_size].to_vec(),
+            value: kv_buf[key_size..kv_buf.len() - 4].to_vec(),
+            rec_type,
+            flags,
+        };
+        kv_buf.advance(key_size + value_size);
+        let crc_ok = kv_buf.get_u32() == log_record.get_crc_with_layout(self.options.record_layout);
+        Ok((
+            ReadLogRecord {
+                record: log_record,
+                size: record_size,
+            },
+            crc_ok,
+        ))
+    }
+    /// 只读取并解析记录的头部
+    pub fn read_log_record_header(&self, offset: u64) -> Result<LogRecordHeader> {
+        // 头部使用栈上的缓冲区解析，避免每次读取都分配内存
+        let layout = self.options.record_layout;
+        // FixedWidth 的头部比 varint 头部的最大长度更长，另外可能还有一个 flags 字节
+        let mut header_buf = [0u8; FIXED_LOG_RECORD_HEADER_SIZE + 1];
+        let header_len = match layout {
+            RecordLayout::Varint => MAX_LOG_RECORD_HEADER_SIZE + 1,
+            RecordLayout::FixedWidth => FIXED_LOG_RECORD_HEADER_SIZE + 1,
+        };
+        self.with_io(|io| io.read(&mut header_buf[..header_len], offset))?;
 
-        let rec_type = header_buf.get_u8();
+        let mut header = &header_buf[..header_len];
+        let mut rec_type = header.get_u8();
+        let mut flags = 0;
+        if rec_type & RECORD_FLAGS_BIT != 0 {
+            rec_type &= !RECORD_FLAGS_BIT;
+            flags = header.get_u8();
+            // flags 为 0 时不会写入 flags 字节
+            if flags == 0 {
+                return Err(Errors::DataDirectoryCorrupted);
+            }
+        }
 
-        let key_size = decode_length_delimiter(&mut header_buf).unwrap();
-        let value_size = decode_length_delimiter(&mut header_buf).unwrap();
+        let (key_size, value_size) = match layout {
+            // 损坏的头部中可能包含无法解析的长度
+            RecordLayout::Varint => (
+                decode_length_delimiter(&mut header).map_err(|_| Errors::DataDirectoryCorrupted)?,
+                decode_length_delimiter(&mut header).map_err(|_| Errors::DataDirectoryCorrupted)?,
+            ),
+            RecordLayout::FixedWidth => (
+                header.get_u32() as usize,
+                usize::try_from(header.get_u64()).map_err(|_| Errors::DataDirectoryCorrupted)?,
+            ),
+        };
 
         if key_size == 0 && value_size == 0 {
             return Err(Errors::ReadDataFileEOF);
         }
-        let actual_header_size =
-            length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
-        let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
-        self.io_manager
-            .read(&mut kv_buf, offset + actual_header_size as u64)?;
-        let log_record = LogRecord {
-            key: kv_buf.get(..key_size).unwrap().to_vec(),
-            value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-            rec_type: LogRecordType::from_u8(rec_type),
-        };
-        kv_buf.advance(key_size + value_size);
-        if kv_buf.get_u32() != log_record.get_crc() {
-            return Err(Errors::InvalidLogRecordCrc);
+        // 写入的记录的 key 至少包含序列号，key 为空说明头部已经损坏
+        if key_size == 0 {
+            return Err(Errors::DataDirectoryCorrupted);
         }
-        Ok(ReadLogRecord {
-            record: log_record,
-            size: actual_header_size + key_size + value_size + 4,
+        Ok(LogRecordHeader {
+            rec_type: LogRecordType::from_u8(rec_type)?,
+            flags,
+            header_size: record_header_size(layout, flags, key_size, value_size),
+            key_size,
+            value_size,
         })
     }
+    /// 从指定位置读取原始的字节
+    pub fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.with_io(|io| io.read(buf, offset))
+    }
     pub fn write(&self, buf: &[u8]) -> Result<usize> {
-        let n_bytes = self.io_manager.write(buf)?;
+        // 写入到数据的末尾而不是文件的末尾，预分配的文件末尾是空白的空间
         let mut write_off = self.write_off.write();
+        let n_bytes = self.with_io(|io| io.write_at(buf, *write_off))?;
         *write_off += n_bytes as u64;
+        // 每次写入的是一条完整的记录
+        if let Some(footer) = self.footer.lock().as_mut() {
+            footer.add(buf);
+        }
         Ok(n_bytes)
     }
+    /// 开启了 file_footer 时把当前的摘要写入摘要文件
+    pub(crate) fn save_footer(&self) -> Result<()> {
+        if !self.options.file_footer {
+            return Ok(());
+        }
+        // 与 write 相同，先持有 write_off 再持有 footer
+        let write_off = self.write_off.read();
+        let mut footer = self.footer.lock();
+        if footer.is_none() {
+            *footer = Some(self.build_footer(*write_off)?);
+        }
+        let file_name = get_footer_file_name(&self.options, self.get_file_id());
+        footer.as_ref().unwrap().footer().save(file_name)
+    }
+    /// 校验文件与摘要文件是否一致，没有摘要文件时返回 None
+    ///
+    /// 只校验摘要记录的前 size 个字节，之后写入但是没有 sync 的数据不参与校验
+    pub fn verify_footer(&self) -> Result<Option<bool>> {
+        let file_name = get_footer_file_name(&self.options, self.get_file_id());
+        let footer = match FileFooter::load(file_name) {
+            Ok(Some(footer)) => footer,
+            Ok(None) => return Ok(None),
+            Err(_) => return Ok(Some(false)),
+        };
+        if self.file_size()? < footer.size {
+            return Ok(Some(false));
+        }
+        match self.build_footer(footer.size) {
+            Ok(builder) => Ok(Some(builder.footer() == footer)),
+            Err(_) => Ok(Some(false)),
+        }
+    }
+    /// 按照记录的边界读取文件的前 size 个字节计算摘要
+    fn build_footer(&self, size: u64) -> Result<FooterBuilder> {
+        let mut builder = FooterBuilder::default();
+        let mut offset = 0;
+        while offset < size {
+            let header = match self.read_log_record_header(offset) {
+                Ok(header) => header,
+                Err(Errors::ReadDataFileEOF) => break,
+                Err(e) => return Err(e),
+            };
+            let (record_size, record_end) = header.record_bounds(offset)?;
+            if record_end > size {
+                return Err(Errors::DataDirectoryCorrupted);
+            }
+            let mut buf = vec![0u8; record_size];
+            self.with_io(|io| io.read(&mut buf, offset))?;
+            builder.add(&buf);
+            offset += record_size as u64;
+        }
+        Ok(builder)
+    }
+    /// 开启了 preallocate 时把文件扩展到 data_file_size，文件中的数据不受影响
+    pub fn preallocate(&self) -> Result<()> {
+        if !self.options.preallocate {
+            return Ok(());
+        }
+        self.with_io(|io| {
+            if io.size()? < self.options.data_file_size {
+                io.truncate(self.options.data_file_size)?;
+            }
+            Ok(())
+        })
+    }
+    /// 截断文件，offset 之后的数据被丢弃
+    pub fn truncate(&self, offset: u64) -> Result<()> {
+        self.with_io(|io| {
+            io.truncate(offset)?;
+            io.sync()
+        })?;
+        self.set_write_offset(offset);
+        self.set_synced_offset(offset);
+        *self.footer.lock() = None;
+        self.save_footer()
+    }
     pub fn sync(&self) -> Result<()> {
-        self.io_manager.sync()
+        let write_off = self.get_write_off();
+        // 关闭句柄时已经持久化过，不需要为了 sync 重新打开文件
+        if let Some(io) = self.io_manager.read().as_ref() {
+            io.sync()?;
+        }
+        self.set_synced_offset(write_off);
+        self.save_footer()
     }
 }
-fn get_data_file_name(dir_path: PathBuf, file_id: u32) -> PathBuf {
+pub(crate) fn get_data_file_name(options: &Options, file_id: u32) -> PathBuf {
     let name = std::format!("{:09}", file_id) + DATA_FILE_NAME_SUFFIX;
-    dir_path.join(name)
+    get_data_file_dir(options, file_id).join(name)
+}
+/// 数据文件所在的目录，按照 files_per_dir 分散到子目录中
+pub(crate) fn get_data_file_dir(options: &Options, file_id: u32) -> PathBuf {
+    match options.files_per_dir {
+        Some(files_per_dir) => options
+            .dir_path
+            .join(std::format!("{:09}", file_id / files_per_dir)),
+        None => options.dir_path.clone(),
+    }
 }
 
 #[cfg(test)]
@@ -92,20 +368,24 @@ mod tests {
     #[test]
     fn test_new_data_file() {
         let dir_path = std::env::temp_dir();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
 
-        let data_file_res1 = DataFile::new(dir_path.clone(), 0);
+        let data_file_res1 = DataFile::new(&opts, 0);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 0);
         println!("temp dir:{:?}", dir_path.clone().as_os_str());
 
-        let data_file_res2 = DataFile::new(dir_path.clone(), 0);
+        let data_file_res2 = DataFile::new(&opts, 0);
         assert!(data_file_res2.is_ok());
         let data_file2 = data_file_res2.unwrap();
         assert_eq!(data_file2.get_file_id(), 0);
         println!("temp dir:{:?}", dir_path.clone().as_os_str());
 
-        let data_file_res3 = DataFile::new(dir_path.clone(), 660);
+        let data_file_res3 = DataFile::new(&opts, 660);
         assert!(data_file_res3.is_ok());
         let data_file3 = data_file_res3.unwrap();
         assert_eq!(data_file3.get_file_id(), 660);
@@ -114,8 +394,12 @@ mod tests {
     #[test]
     fn test_data_file_write() {
         let dir_path = std::env::temp_dir();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
 
-        let data_file_res1 = DataFile::new(dir_path.clone(), 10);
+        let data_file_res1 = DataFile::new(&opts, 10);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 10);
@@ -133,8 +417,12 @@ mod tests {
     #[test]
     fn test_data_file_sync() {
         let dir_path = std::env::temp_dir();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
 
-        let data_file_res1 = DataFile::new(dir_path.clone(), 100);
+        let data_file_res1 = DataFile::new(&opts, 100);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 100);
@@ -144,10 +432,46 @@ mod tests {
         assert!(sync_res1.is_ok());
     }
     #[test]
+    fn test_data_file_close_handle() {
+        let dir_path = std::env::temp_dir();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
+        let data_file = DataFile::new(&opts, 400).unwrap();
+        data_file.truncate(0).unwrap();
+        let enc = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs-kv".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        }
+        .encode();
+        data_file.write(&enc).unwrap();
+
+        // 关闭句柄时会持久化，之后读写时重新打开
+        data_file.close_handle().unwrap();
+        assert!(!data_file.is_handle_open());
+        assert_eq!(enc.len() as u64, data_file.get_synced_off());
+        let read_res = data_file.read_log_record(0).unwrap();
+        assert_eq!("bitcask-rs-kv".as_bytes().to_vec(), read_res.record.value);
+        assert!(data_file.is_handle_open());
+
+        data_file.close_handle().unwrap();
+        data_file.write(&enc).unwrap();
+        assert_eq!(2 * enc.len() as u64, data_file.get_write_off());
+        let read_res = data_file.read_log_record(enc.len() as u64).unwrap();
+        assert_eq!("name".as_bytes().to_vec(), read_res.record.key);
+    }
+    #[test]
     fn test_data_file_read_log_record() {
         let dir_path = std::env::temp_dir();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
 
-        let data_file_res1 = DataFile::new(dir_path.clone(), 200);
+        let data_file_res1 = DataFile::new(&opts, 200);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 200);
@@ -156,6 +480,7 @@ mod tests {
             key: "xia".as_bytes().to_vec(),
             value: "sang".as_bytes().to_vec(),
             rec_type: LogRecordType::NORMAL,
+            flags: 0,
         };
 
         let write_enc1 = data_file1.write(&enc1.encode());
@@ -174,6 +499,7 @@ mod tests {
             key: "sang".as_bytes().to_vec(),
             value: "xia".as_bytes().to_vec(),
             rec_type: LogRecordType::DElETED,
+            flags: 0,
         };
 
         let write_enc2 = data_file1.write(&enc2.encode());
@@ -186,4 +512,139 @@ mod tests {
         assert_eq!(read_enc2.value, enc2.value);
         assert_eq!(read_enc2.rec_type, enc2.rec_type);
     }
+    #[test]
+    fn test_data_file_read_log_record_header_sizes() {
+        let dir_path = PathBuf::from("./tmp/bitcask-rs-data-file-header");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
+        let data_file = DataFile::new(&opts, 0).unwrap();
+
+        // 不同长度的 key/value，覆盖 1~3 字节的 varint 头部
+        let sizes = [(1, 0), (3, 4), (127, 128), (200, 20000), (16384, 1)];
+        let mut records = Vec::new();
+        for (key_size, value_size) in sizes {
+            let record = LogRecord {
+                key: vec![b'k'; key_size],
+                value: vec![b'v'; value_size],
+                rec_type: LogRecordType::NORMAL,
+                flags: 0,
+            };
+            let offset = data_file.get_write_off();
+            data_file.write(&record.encode()).unwrap();
+            records.push((offset, record));
+        }
+        for (offset, record) in records.iter() {
+            let read_record = data_file.read_log_record(*offset).unwrap();
+            assert_eq!(record.key, read_record.record.key);
+            assert_eq!(record.value, read_record.record.value);
+            assert_eq!(record.rec_type, read_record.record.rec_type);
+            assert_eq!(record.encode().len(), read_record.size);
+        }
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+    #[test]
+    fn test_data_file_footer_with_overflowing_sizes() {
+        let dir_path = PathBuf::from("./tmp/bitcask-rs-data-file-footer-overflow");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            record_layout: RecordLayout::FixedWidth,
+            ..Default::default()
+        };
+        let data_file = DataFile::new(&opts, 0).unwrap();
+
+        // value 长度接近 u64::MAX 的头部，计算记录的长度时不能溢出
+        let mut buf = vec![LogRecordType::NORMAL as u8];
+        buf.extend_from_slice(&3u32.to_be_bytes());
+        buf.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        buf.extend_from_slice(b"key");
+        data_file.write(&buf).unwrap();
+        assert_eq!(
+            Errors::DataDirectoryCorrupted,
+            data_file.build_footer(buf.len() as u64).err().unwrap()
+        );
+        assert_eq!(
+            Errors::DataDirectoryCorrupted,
+            data_file.read_log_record(0).err().unwrap()
+        );
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+    #[test]
+    fn test_data_file_read_log_record_invalid_sizes() {
+        let dir_path = PathBuf::from("./tmp/bitcask-rs-data-file-invalid-sizes");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
+        let data_file = DataFile::new(&opts, 0).unwrap();
+
+        // key 长度为 0 而 value 长度不为 0 的头部
+        let mut buf = vec![LogRecordType::NORMAL as u8, 0, 5];
+        buf.extend_from_slice(b"value");
+        buf.extend_from_slice(&[0u8; 4]);
+        data_file.write(&buf).unwrap();
+        assert_eq!(
+            Errors::DataDirectoryCorrupted,
+            data_file.read_log_record(0).err().unwrap()
+        );
+        assert_eq!(
+            Errors::DataDirectoryCorrupted,
+            data_file.read_log_record_header(0).err().unwrap()
+        );
+
+        // 之后正常写入的记录不受影响
+        let record = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        };
+        data_file.write(&record.encode()).unwrap();
+        let read_record = data_file.read_log_record(buf.len() as u64).unwrap();
+        assert_eq!(record.value, read_record.record.value);
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+    #[test]
+    fn test_data_file_read_without_file_size() {
+        use crate::fio::counting_io::{CountingIO, IOCounters};
+
+        let dir_path = PathBuf::from("./tmp/bitcask-rs-data-file-read-size");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
+        let mut data_file = DataFile::new(&opts, 0).unwrap();
+        let counters = IOCounters::default();
+        let file_name = get_data_file_name(&opts, 0);
+        data_file.set_io_manager(Box::new(CountingIO::new(file_name, counters.clone()).unwrap()));
+        let enc = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs-kv".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        }
+        .encode();
+        data_file.write(&enc).unwrap();
+
+        // 1.写入位置之前的记录不需要获取文件大小
+        for _ in 0..10 {
+            data_file.read_log_record(0).unwrap();
+        }
+        assert_eq!(0, counters.size_calls());
+
+        // 2.启动时扫描文件，写入位置还没有确定，需要检查文件大小
+        data_file.set_write_offset(0);
+        data_file.read_log_record(0).unwrap();
+        assert_eq!(1, counters.size_calls());
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
 }
@@ -0,0 +1,115 @@
+use std::{fs, path::PathBuf};
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::{
+    data::data_file::get_data_file_dir,
+    errors::{Errors, Result},
+    options::Options,
+};
+
+pub const FOOTER_FILE_NAME_SUFFIX: &str = ".footer";
+/// size(u64) + record_count(u64) + hash(u32) + crc(u32)
+const FOOTER_SIZE: usize = 8 + 8 + 4 + 4;
+
+/// 数据文件前 size 个字节的摘要，sync 时写入单独的文件中
+///
+/// 打开时按照摘要校验文件，可以发现整个文件被截断或者某条记录被修改，不依赖每条记录的 crc
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileFooter {
+    pub size: u64,
+    pub record_count: u64,
+    /// 所有记录的字节依次计算的 crc
+    pub hash: u32,
+}
+
+impl FileFooter {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(FOOTER_SIZE);
+        buf.put_u64(self.size);
+        buf.put_u64(self.record_count);
+        buf.put_u32(self.hash);
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+        buf.to_vec()
+    }
+    pub fn decode(buf: &[u8]) -> Result<FileFooter> {
+        if buf.len() != FOOTER_SIZE {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let (mut content, mut crc_buf) = buf.split_at(buf.len() - 4);
+        if crc32fast::hash(content) != crc_buf.get_u32() {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        Ok(FileFooter {
+            size: content.get_u64(),
+            record_count: content.get_u64(),
+            hash: content.get_u32(),
+        })
+    }
+    pub fn save(&self, file_name: PathBuf) -> Result<()> {
+        fs::write(file_name, self.encode()).map_err(|_| Errors::FailedToWriteToDataFile)
+    }
+    /// 读取摘要文件，文件不存在时返回 None
+    pub fn load(file_name: PathBuf) -> Result<Option<FileFooter>> {
+        match fs::read(file_name) {
+            Ok(buf) => FileFooter::decode(&buf).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// 随着写入逐条记录更新的摘要
+#[derive(Clone, Default)]
+pub(crate) struct FooterBuilder {
+    size: u64,
+    record_count: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl FooterBuilder {
+    pub(crate) fn add(&mut self, record: &[u8]) {
+        self.size += record.len() as u64;
+        self.record_count += 1;
+        self.hasher.update(record);
+    }
+    pub(crate) fn footer(&self) -> FileFooter {
+        FileFooter {
+            size: self.size,
+            record_count: self.record_count,
+            hash: self.hasher.clone().finalize(),
+        }
+    }
+}
+
+/// 摘要文件与数据文件存放在同一个目录中
+pub(crate) fn get_footer_file_name(options: &Options, file_id: u32) -> PathBuf {
+    let name = std::format!("{:09}", file_id) + FOOTER_FILE_NAME_SUFFIX;
+    get_data_file_dir(options, file_id).join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_file_footer_encode_and_decode() {
+        let mut builder = FooterBuilder::default();
+        builder.add("record-1".as_bytes());
+        builder.add("record-2".as_bytes());
+        let footer = builder.footer();
+        assert_eq!(16, footer.size);
+        assert_eq!(2, footer.record_count);
+
+        // 记录的内容不同时摘要不同
+        let mut other = FooterBuilder::default();
+        other.add("record-1".as_bytes());
+        other.add("record-3".as_bytes());
+        assert_ne!(footer.hash, other.footer().hash);
+
+        let decoded = FileFooter::decode(&footer.encode()).unwrap();
+        assert_eq!(footer, decoded);
+        let mut corrupted = footer.encode();
+        corrupted[3] ^= 0xff;
+        assert!(FileFooter::decode(&corrupted).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::warn;
+use prost::{decode_length_delimiter, encode_length_delimiter};
+
+use crate::{
+    data::log_record::{LogRecordPos, LogRecordType},
+    errors::{Errors, Result},
+};
+
+pub(crate) const INDEX_SIDECAR_FILE_NAME: &str = "index-sidecar";
+
+/// 索引文件中的一项，对应数据文件中的一条记录，不包括 value
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SidecarEntry {
+    /// 带有序列号的 key
+    pub(crate) key: Vec<u8>,
+    pub(crate) rec_type: LogRecordType,
+    pub(crate) pos: LogRecordPos,
+    /// 记录在数据文件中占用的字节数
+    pub(crate) size: usize,
+}
+
+impl SidecarEntry {
+    /// type + file_id + offset + size + key 长度 + key + crc
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(1 + 4 + 8 + 10 + 5 + self.key.len() + 4);
+        buf.put_u8(self.rec_type as u8);
+        buf.put_u32(self.pos.file_id);
+        buf.put_u64(self.pos.offset);
+        encode_length_delimiter(self.size, &mut buf).unwrap();
+        encode_length_delimiter(self.key.len(), &mut buf).unwrap();
+        buf.extend_from_slice(&self.key);
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+        buf.to_vec()
+    }
+    /// 解析 buf 开头的一项，返回该项以及占用的字节数
+    pub(crate) fn decode(buf: &[u8]) -> Result<(SidecarEntry, usize)> {
+        let mut content = buf;
+        if content.remaining() < 1 + 4 + 8 {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let rec_type = LogRecordType::from_u8(content.get_u8())?;
+        let file_id = content.get_u32();
+        let offset = content.get_u64();
+        let size = decode_length_delimiter(&mut content)
+            .map_err(|_| Errors::DataDirectoryCorrupted)?;
+        let key_len = decode_length_delimiter(&mut content)
+            .map_err(|_| Errors::DataDirectoryCorrupted)?;
+        if content.remaining() < key_len + 4 {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let key = content[..key_len].to_vec();
+        content.advance(key_len);
+        let len = buf.len() - content.remaining();
+        if crc32fast::hash(&buf[..len]) != content.get_u32() {
+            return Err(Errors::DataDirectoryCorrupted);
+        }
+        let entry = SidecarEntry {
+            key,
+            rec_type,
+            pos: LogRecordPos { file_id, offset },
+            size,
+        };
+        Ok((entry, len + 4))
+    }
+}
+
+/// 随着写入追加的索引文件，启动时按照其中的位置恢复索引，不需要读取已经记录的数据
+pub(crate) struct IndexSidecar {
+    file: File,
+    path: PathBuf,
+}
+
+impl IndexSidecar {
+    /// 创建新的索引文件，已有的内容会被清空
+    pub(crate) fn create(dir_path: &Path) -> Result<IndexSidecar> {
+        let path = dir_path.join(INDEX_SIDECAR_FILE_NAME);
+        File::create(&path).map_err(|_| Errors::FailedToOpenDataFile)?;
+        IndexSidecar::open(dir_path, 0)
+    }
+    /// 打开已有的索引文件，截断 len 之后无法解析的部分，在末尾继续写入
+    pub(crate) fn open(dir_path: &Path, len: u64) -> Result<IndexSidecar> {
+        let path = dir_path.join(INDEX_SIDECAR_FILE_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|_| Errors::FailedToOpenDataFile)?;
+        file.set_len(len).map_err(|_| Errors::FailedToWriteToDataFile)?;
+        Ok(IndexSidecar { file, path })
+    }
+    /// 数据目录移动之后打开新位置的索引文件，在末尾继续写入
+    pub(crate) fn reopen(&self, dir_path: &Path) -> Result<IndexSidecar> {
+        let len = self
+            .file
+            .metadata()
+            .map_err(|_| Errors::FailedToReadFromDataFile)?
+            .len();
+        IndexSidecar::open(dir_path, len)
+    }
+    /// 读取索引文件中的所有项，遇到无法解析的项时停止，返回读取到的项以及有效部分的长度
+    ///
+    /// 索引文件不存在时返回 None
+    pub(crate) fn read_entries(dir_path: &Path) -> Result<Option<(Vec<SidecarEntry>, u64)>> {
+        let buf = match fs::read(dir_path.join(INDEX_SIDECAR_FILE_NAME)) {
+            Ok(buf) => buf,
+            Err(_) => return Ok(None),
+        };
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            match SidecarEntry::decode(&buf[offset..]) {
+                Ok((entry, size)) => {
+                    entries.push(entry);
+                    offset += size;
+                }
+                Err(_) => {
+                    warn!("invalid index sidecar entry, offset:{}", offset);
+                    break;
+                }
+            }
+        }
+        Ok(Some((entries, offset as u64)))
+    }
+    pub(crate) fn append(&mut self, entry: &SidecarEntry) -> Result<()> {
+        self.file
+            .write_all(&entry.encode())
+            .map_err(|_| Errors::FailedToWriteToDataFile)
+    }
+    /// 写入失败时删除索引文件并停止维护，下一次启动时重新扫描数据文件
+    pub(crate) fn append_or_discard(sidecar: &mut Option<IndexSidecar>, entry: &SidecarEntry) {
+        if let Some(index_sidecar) = sidecar {
+            if let Err(e) = index_sidecar.append(entry) {
+                warn!("failed to append index sidecar entry: {:?}", e);
+                IndexSidecar::discard(sidecar);
+            }
+        }
+    }
+    /// 删除索引文件并停止维护
+    pub(crate) fn discard(sidecar: &mut Option<IndexSidecar>) {
+        if let Some(index_sidecar) = sidecar.take() {
+            IndexSidecar::remove_file(&index_sidecar.path);
+        }
+    }
+    /// 删除目录中的索引文件
+    pub(crate) fn remove(dir_path: &Path) {
+        IndexSidecar::remove_file(&dir_path.join(INDEX_SIDECAR_FILE_NAME));
+    }
+    fn remove_file(path: &Path) {
+        if path.is_file() {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("failed to remove index sidecar: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_index_sidecar_entries() {
+        let dir_path = PathBuf::from("./tmp/bitcask-rs-index-sidecar-entries");
+        fs::create_dir_all(&dir_path).unwrap();
+        let entries: Vec<SidecarEntry> = (0..3)
+            .map(|i| SidecarEntry {
+                key: format!("key-{}", i).into_bytes(),
+                rec_type: LogRecordType::NORMAL,
+                pos: LogRecordPos {
+                    file_id: 1,
+                    offset: i * 100,
+                },
+                size: 100,
+            })
+            .collect();
+
+        // 1.依次写入之后可以全部读出
+        let mut sidecar = IndexSidecar::create(&dir_path).unwrap();
+        for entry in entries.iter() {
+            sidecar.append(entry).unwrap();
+        }
+        let (read, len) = IndexSidecar::read_entries(&dir_path).unwrap().unwrap();
+        assert_eq!(entries, read);
+
+        // 2.末尾写入了一部分的项被忽略，重新打开时被截断
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir_path.join(INDEX_SIDECAR_FILE_NAME))
+            .unwrap();
+        file.write_all(&entries[0].encode()[..5]).unwrap();
+        let (read, valid_len) = IndexSidecar::read_entries(&dir_path).unwrap().unwrap();
+        assert_eq!(entries, read);
+        assert_eq!(len, valid_len);
+        let mut sidecar = IndexSidecar::open(&dir_path, valid_len).unwrap();
+        sidecar.append(&entries[0]).unwrap();
+        let (read, _) = IndexSidecar::read_entries(&dir_path).unwrap().unwrap();
+        assert_eq!(4, read.len());
+
+        // 3.删除之后读取返回 None
+        IndexSidecar::discard(&mut Some(sidecar));
+        assert!(IndexSidecar::read_entries(&dir_path).unwrap().is_none());
+
+        fs::remove_dir_all(dir_path).unwrap();
+    }
+}
@@ -1,19 +1,32 @@
 use bytes::{BufMut, BytesMut};
 use prost::{encode_length_delimiter, length_delimiter_len};
 
+use crate::{
+    errors::{Errors, Result},
+    options::RecordLayout,
+};
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum LogRecordType {
     NORMAL = 1,
     DElETED = 2,
     TxnFinish=3,
+    /// value 存放在 blob 文件中，记录中只保存 BlobPointer
+    BlobPointer = 4,
+    /// 批次开始的标记，写在批次的第一条记录之前
+    TxnBegin = 5,
 }
 impl LogRecordType {
-    pub fn from_u8(v: u8) -> Self {
+    /// 未知的类型说明记录已经损坏，返回 DataDirectoryCorrupted
+    pub fn from_u8(v: u8) -> Result<Self> {
         match v{
-            1 => LogRecordType::NORMAL,
-            2 => LogRecordType::DElETED,
-            3=>LogRecordType::TxnFinish,
-            _ => panic!("unknown log record type"),
+            1 => Ok(LogRecordType::NORMAL),
+            2 => Ok(LogRecordType::DElETED),
+            3=>Ok(LogRecordType::TxnFinish),
+            4 => Ok(LogRecordType::BlobPointer),
+            5 => Ok(LogRecordType::TxnBegin),
+            _ => Err(Errors::DataDirectoryCorrupted),
         }
     }
 }
@@ -22,13 +35,23 @@ pub struct LogRecord {
     pub(crate) key: Vec<u8>,
     pub(crate) value: Vec<u8>,
     pub(crate) rec_type: LogRecordType,
+    /// 用户自定义的标记，不影响 value 的读写
+    pub(crate) flags: u8,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct LogRecordPos {
     pub(crate) file_id: u32,
     pub(crate) offset: u64,
 }
+impl LogRecordPos {
+    pub fn file_id(&self) -> u32 {
+        self.file_id
+    }
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
 pub struct TransactionRecord{
     pub(crate)record:LogRecord,
     pub(crate) pos:LogRecordPos,
@@ -40,25 +63,54 @@ pub struct ReadLogRecord {
 }
 impl LogRecord {
     // 进行数据encode操作
+    #[cfg(test)]
     pub fn encode(&self) -> Vec<u8> {
-        let (enc_buf, _) = self.encode_and_get_crc();
+        self.encode_with_layout(RecordLayout::Varint)
+    }
+    /// 按照指定的布局编码记录
+    pub fn encode_with_layout(&self, layout: RecordLayout) -> Vec<u8> {
+        let (enc_buf, _) = self.encode_and_get_crc(layout);
         enc_buf
     }
+    #[cfg(test)]
     pub fn get_crc(&self) -> u32 {
-        let (_, crc_value) = self.encode_and_get_crc();
+        self.get_crc_with_layout(RecordLayout::Varint)
+    }
+    /// crc 覆盖记录头部，不同布局下的 crc 不同
+    pub fn get_crc_with_layout(&self, layout: RecordLayout) -> u32 {
+        let (_, crc_value) = self.encode_and_get_crc(layout);
         crc_value
     }
-    // type keySize valueSize key value crc
-    fn encode_and_get_crc(&self) -> (Vec<u8>, u32) {
+    // type [flags] keySize valueSize key value crc
+    fn encode_and_get_crc(&self, layout: RecordLayout) -> (Vec<u8>, u32) {
         let mut buf = BytesMut::new();
-        buf.reserve(self.encoded_length());
+        buf.reserve(
+            record_header_size(layout, self.flags, self.key.len(), self.value.len())
+                + self.key.len()
+                + self.value.len()
+                + 4,
+        );
 
-        // type
-        buf.put_u8(self.rec_type as u8);
+        // type flags
+        match self.flags {
+            0 => buf.put_u8(self.rec_type as u8),
+            flags => {
+                buf.put_u8(self.rec_type as u8 | RECORD_FLAGS_BIT);
+                buf.put_u8(flags);
+            }
+        }
 
         // key and value size
-        encode_length_delimiter(self.key.len(), &mut buf).unwrap();
-        encode_length_delimiter(self.value.len(), &mut buf).unwrap();
+        match layout {
+            RecordLayout::Varint => {
+                encode_length_delimiter(self.key.len(), &mut buf).unwrap();
+                encode_length_delimiter(self.value.len(), &mut buf).unwrap();
+            }
+            RecordLayout::FixedWidth => {
+                buf.put_u32(self.key.len() as u32);
+                buf.put_u64(self.value.len() as u64);
+            }
+        }
 
         // key and value
         buf.extend_from_slice(&self.key);
@@ -74,20 +126,57 @@ impl LogRecord {
         (buf.to_vec(), crc)
     }
     // 计算数据长度
-    fn encoded_length(&self) -> usize {
-        std::mem::size_of::<u8>()
-            + length_delimiter_len(self.key.len())
-            + length_delimiter_len(self.key.len())
-            + self.key.len()
-            + self.value.len()
-            + 4
+    #[cfg(test)]
+    pub fn encoded_length(&self) -> usize {
+        encoded_record_length(self.key.len(), self.value.len())
     }
 }
 
-pub fn max_long_record() -> usize {
+/// 根据 key 和 value 的长度计算编码后的记录长度
+pub fn encoded_record_length(key_len: usize, value_len: usize) -> usize {
     // type keySize valueSize key value crc
-    std::mem::size_of::<u8>() + length_delimiter_len(std::u32::MAX as usize) * 2
+    std::mem::size_of::<u8>()
+        + length_delimiter_len(key_len)
+        + length_delimiter_len(value_len)
+        + key_len
+        + value_len
+        + 4
+}
+
+/// 记录头部的长度，FixedWidth 布局下除了 flags 之外是固定的
+pub fn record_header_size(
+    layout: RecordLayout,
+    flags: u8,
+    key_len: usize,
+    value_len: usize,
+) -> usize {
+    let flags_len = match flags {
+        0 => 0,
+        _ => std::mem::size_of::<u8>(),
+    };
+    let header_len = match layout {
+        RecordLayout::Varint => {
+            std::mem::size_of::<u8>()
+                + length_delimiter_len(key_len)
+                + length_delimiter_len(value_len)
+        }
+        RecordLayout::FixedWidth => FIXED_LOG_RECORD_HEADER_SIZE,
+    };
+    header_len + flags_len
 }
+
+/// type 的最高位表示 type 之后有一个 flags 字节
+///
+/// flags 为 0 时不写入 flags 字节，记录与之前的格式完全相同，旧的数据文件不需要迁移
+pub const RECORD_FLAGS_BIT: u8 = 0x80;
+
+/// 记录头部的最大长度，type + 两个 u32 的 varint
+pub const MAX_LOG_RECORD_HEADER_SIZE: usize = std::mem::size_of::<u8>() + 5 * 2;
+
+/// FixedWidth 布局的头部长度，type + u32 的 key 长度 + u64 的 value 长度
+pub const FIXED_LOG_RECORD_HEADER_SIZE: usize =
+    std::mem::size_of::<u8>() + std::mem::size_of::<u32>() + std::mem::size_of::<u64>();
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,6 +187,7 @@ mod tests {
             key: "xia".as_bytes().to_vec(),
             value: "sang".as_bytes().to_vec(),
             rec_type: LogRecordType::NORMAL,
+            flags: 0,
         };
         let enc1 = rec1.encode();
         // println!("{:?}",enc1);
@@ -110,6 +200,7 @@ mod tests {
             key: "xia".as_bytes().to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::NORMAL,
+            flags: 0,
         };
         let enc2 = rec2.encode();
         // println!("{:?}",enc1);
@@ -122,6 +213,7 @@ mod tests {
             key: "xia".as_bytes().to_vec(),
             value: "sang".as_bytes().to_vec(),
             rec_type: LogRecordType::DElETED,
+            flags: 0,
         };
         let enc3 = rec3.encode();
         // println!("{:?}",enc1);
@@ -129,4 +221,65 @@ mod tests {
         assert!(enc3.len() > 5);
         assert_eq!(379652320, rec3.get_crc());
     }
+    #[test]
+    fn test_log_record_encoded_length() {
+        let sizes = [(1, 0), (3, 4), (127, 128), (200, 20000), (16384, 1)];
+        for (key_size, value_size) in sizes {
+            let rec = LogRecord {
+                key: vec![b'k'; key_size],
+                value: vec![b'v'; value_size],
+                rec_type: LogRecordType::NORMAL,
+                flags: 0,
+            };
+            assert_eq!(rec.encode().len(), rec.encoded_length());
+        }
+    }
+    #[test]
+    fn test_log_record_fixed_width_layout() {
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        };
+        let enc = rec.encode_with_layout(RecordLayout::FixedWidth);
+        assert_eq!(FIXED_LOG_RECORD_HEADER_SIZE + 4 + 10 + 4, enc.len());
+        assert_eq!(LogRecordType::NORMAL as u8, enc[0]);
+        assert_eq!(4u32.to_be_bytes(), enc[1..5]);
+        assert_eq!(10u64.to_be_bytes(), enc[5..13]);
+        assert_eq!(b"name", &enc[13..17]);
+        assert_ne!(rec.get_crc(), rec.get_crc_with_layout(RecordLayout::FixedWidth));
+        assert_eq!(rec.encode(), rec.encode_with_layout(RecordLayout::Varint));
+    }
+    #[test]
+    fn test_log_record_flags() {
+        let mut rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        };
+        let enc1 = rec.encode();
+        assert_eq!(LogRecordType::NORMAL as u8, enc1[0]);
+
+        // flags 写在 type 之后，并且计入 crc
+        rec.flags = 0b101;
+        let enc2 = rec.encode();
+        assert_eq!(enc1.len() + 1, enc2.len());
+        assert_eq!(LogRecordType::NORMAL as u8 | RECORD_FLAGS_BIT, enc2[0]);
+        assert_eq!(0b101, enc2[1]);
+        assert_eq!(enc1[1..enc1.len() - 4], enc2[2..enc2.len() - 4]);
+        assert_ne!(enc1[enc1.len() - 4..], enc2[enc2.len() - 4..]);
+        assert_eq!(
+            FIXED_LOG_RECORD_HEADER_SIZE + 1,
+            record_header_size(RecordLayout::FixedWidth, rec.flags, 4, 10)
+        );
+    }
+    #[test]
+    fn test_max_log_record_header_size() {
+        assert_eq!(
+            std::mem::size_of::<u8>() + length_delimiter_len(u32::MAX as usize) * 2,
+            MAX_LOG_RECORD_HEADER_SIZE
+        );
+    }
 }
@@ -1,2 +1,6 @@
+pub mod blob_file;
+pub mod bloom_filter;
 pub mod data_file;
+pub mod footer;
+pub mod index_sidecar;
 pub mod log_record;
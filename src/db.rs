@@ -1,53 +1,197 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fs,
-    path::PathBuf,
+    io::ErrorKind,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::Sender,
         Arc,
     },
-    u32,
 };
 
 use crate::{
     batch::{log_record_key_with_seq, parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    changes::ChangeLog,
     data::{
-        data_file::{DataFile, DATA_FILE_NAME_SUFFIX},
-        log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionRecord},
+        blob_file::{BlobFile, BlobPointer},
+        bloom_filter::{get_bloom_file_name, BLOOM_FILE_NAME_SUFFIX},
+        data_file::{get_data_file_dir, get_data_file_name, DataFile, DATA_FILE_NAME_SUFFIX},
+        footer::{get_footer_file_name, FOOTER_FILE_NAME_SUFFIX},
+        index_sidecar::{IndexSidecar, SidecarEntry},
+        log_record::{
+            encoded_record_length, LogRecord, ReadLogRecord, TransactionRecord,
+            FIXED_LOG_RECORD_HEADER_SIZE,
+        },
     },
+    entry::new_key_locks,
     errors::{Errors, Result},
-    index,
-    options::Options,
+    index::{self, value_prefix::ValuePrefixIndex},
+    merge::MERGE_DIR_NAME,
+    options::{LockPriority, Options, RecordLayout, RecoverMode},
+    repair::RepairReport,
+    shutdown::ShutdownGate,
+    watch::WatchEvent,
 };
 use bytes::Bytes;
 use log::warn;
-use parking_lot::{Mutex, RwLock};
+use prost::length_delimiter_len;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+
+pub use crate::data::log_record::{LogRecordPos, LogRecordType};
 
 const INITIAL_FILE_ID: u32 = 0;
+/// 保存记录布局的文件
+const RECORD_LAYOUT_FILE_NAME: &str = "record-layout";
+/// 没有设置 max_key_size 时，按照这个长度的 key 计算数据文件的最小大小
+const MIN_FILE_KEY_SIZE: usize = 64;
+/// 存储引擎
+///
+/// Engine 可以在多个线程之间共享，并发保证如下：
+/// - 写操作（put、delete、批量提交）之间是串行的，索引的更新顺序与记录写入文件的顺序一致，
+///   因此内存中的索引与重启后恢复出来的索引相同
+/// - 读操作可以与写操作以及其他读操作并发执行，读到的是某一次写操作完成之后的值
+/// - 批量提交的数据在索引中逐个生效，并发的读操作可能看到部分生效的批量写入
+/// - 读写活跃文件发生竞争时谁优先由 Options::lock_priority 决定，默认写操作优先
+/// - close 会阻塞新的写操作，等待已经开始的写操作完成之后再持久化，返回之后可以继续写入
 pub struct Engine {
-    options: Arc<Options>,
-    active_file: Arc<RwLock<DataFile>>,
-    older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
+    /// relocate 之后 dir_path 会改变
+    options: RwLock<Arc<Options>>,
+    pub(crate) active_file: Arc<RwLock<DataFile>>,
+    pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
     pub(crate) index: Box<dyn index::Indexer>,
     file_ids: Vec<u32>,
+    /// 串行化所有的写操作，保证追加记录和更新索引的顺序一致
     pub(crate) batch_commit_lock: Mutex<()>,
+    /// 写操作（追加记录并更新索引）持有读锁，merge 封存活跃文件时持有写锁
+    pub(crate) write_lock: RwLock<()>,
+    pub(crate) merge_lock: Mutex<()>,
     pub(crate) seq_no: Arc<AtomicUsize>,
+    /// 打开之后追加的记录数，用于 sync_interval_writes
+    appended_records: AtomicU64,
+    /// 写入数据文件和 blob 文件的总字节数，打开时从已有文件的大小开始计算，用于 write_amplification
+    pub(crate) written_bytes: AtomicU64,
+    /// 数据文件中的数据记录数以及其中的删除记录数，用于 should_merge_by_deletes
+    pub(crate) total_records: AtomicU64,
+    pub(crate) deleted_records: AtomicU64,
+    /// entry 使用的 key 锁，按照 key 的哈希值选择
+    pub(crate) key_locks: Vec<Mutex<()>>,
+    /// 最近的数据变更，用于 changes_since
+    pub(crate) change_log: RwLock<ChangeLog>,
+    /// value 前缀的二级索引
+    pub(crate) value_index: Option<ValuePrefixIndex>,
+    /// 等待 sync 之后才更新到索引中的位置，None 表示删除
+    pub(crate) pending_index: Mutex<HashMap<Vec<u8>, Option<LogRecordPos>>>,
+    /// 打开了文件句柄的旧数据文件，按照最近使用的顺序排列，只在设置了 max_open_files 时使用
+    open_files: Mutex<VecDeque<u32>>,
+    /// 监听 key 变更的调用方
+    pub(crate) watchers: Mutex<HashMap<Vec<u8>, Vec<Sender<WatchEvent>>>>,
+    /// close 等待正在进行的写操作完成
+    pub(crate) shutdown: ShutdownGate,
+    pub(crate) open_report: OpenReport,
+    /// 恢复时找到的已提交事务的序列号
+    transactions: Vec<usize>,
+    blob_file: Option<BlobFile>,
+    /// 开启 index_sidecar 时随着写入追加的索引文件
+    pub(crate) index_sidecar: Mutex<Option<IndexSidecar>>,
+}
+/// 一个已经持久化的数据位置，可用于外部 WAL/复制 对齐
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub active_file_id: u32,
+    pub write_offset: u64,
+    /// 最近一次已提交的事务序列号
+    pub seq_no: usize,
+}
+/// key 当前记录的元信息
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordMetadata {
+    pub pos: LogRecordPos,
+    pub value_len: u64,
+    /// put_with_flags 写入的 flags，put 写入的记录为 0
+    pub flags: u8,
+}
+/// 关闭数据库时的持久化情况
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloseReport {
+    /// 关闭时活跃文件中距离上一次 sync 写入的字节数
+    pub unsynced_bytes: u64,
+    /// 关闭时是否执行了 sync
+    pub synced: bool,
+}
+/// 打开数据库时的恢复情况
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpenReport {
+    /// crc 校验失败或者无法解析的记录
+    pub corrupted_records: Vec<CorruptedRecord>,
+    /// 缓存的未提交事务过多时被丢弃的事务序列号
+    pub dropped_transactions: Vec<usize>,
+    /// 只写入了部分记录、没有结束标记的批次
+    pub orphaned_batches: Vec<OrphanedBatch>,
+    /// 开启 file_footer 时与摘要文件不一致的数据文件 id
+    pub footer_mismatches: Vec<u32>,
+    /// 从数据文件中读取的记录数，开启 index_sidecar 时不包括从索引文件恢复的记录
+    pub scanned_records: usize,
+    /// open_or_repair 按照 RepairPolicy::Salvage 修复了数据目录时的修复结果
+    pub repair: Option<RepairReport>,
+}
+/// 一个没有完成提交的批次
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrphanedBatch {
+    pub seq_no: usize,
+    /// 批次中已经写入的记录（包括开始标记）占用的字节数
+    pub bytes: u64,
+}
+/// 一条损坏的记录所在的位置
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CorruptedRecord {
+    pub file_id: u32,
+    pub offset: u64,
+    pub size: usize,
 }
 impl Engine {
-    pub fn open(opts: Options) -> Result<Self> {
+    pub fn open(mut opts: Options) -> Result<Self> {
         if let Some(e) = check_options(&opts) {
             return Err(e);
         }
-        let options = opts.clone();
-
-        let dir_path = options.dir_path.clone();
+        let dir_path = opts.dir_path.clone();
         if !dir_path.is_dir() {
+            if !opts.create_if_missing {
+                return Err(Errors::DatabaseNotFound);
+            }
             if let Err(e) = fs::create_dir_all(dir_path.clone()) {
                 warn!("create database directory err:{}", e);
                 return Err(Errors::FailedToCreateDataBaseDir);
             }
         }
-        let mut data_files = load_data_files(dir_path.clone())?;
+        // 已有的数据库按照保存的布局解析记录，没有保存布局的旧数据库使用 varint
+        let layout_missing = match read_record_layout(&dir_path)? {
+            Some(layout) => {
+                opts.record_layout = layout;
+                false
+            }
+            None => {
+                let mut data_file_paths = HashMap::new();
+                find_data_files(&dir_path, &mut data_file_paths)?;
+                if !data_file_paths.is_empty() {
+                    opts.record_layout = RecordLayout::Varint;
+                }
+                true
+            }
+        };
+        let options = opts.clone();
+
+        // 清理上一次未完成的合并
+        let merge_path = dir_path.join(MERGE_DIR_NAME);
+        if merge_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(&merge_path) {
+                warn!("remove unfinished merge directory err:{}", e);
+            }
+        }
+        let mut data_files = load_data_files(&options)?;
+        if options.error_if_exists && !data_files.is_empty() {
+            return Err(Errors::DatabaseAlreadyExists);
+        }
 
         let mut file_ids = Vec::new();
         for v in data_files.iter() {
@@ -65,154 +209,974 @@ impl Engine {
         }
         let active_file = match data_files.pop() {
             Some(v) => v,
-            None => DataFile::new(dir_path.clone(), INITIAL_FILE_ID)?,
+            None => DataFile::new_active(&options, INITIAL_FILE_ID)?,
+        };
+        let blob_file = match options.inline_value_threshold.is_some() || BlobFile::exists(&dir_path) {
+            true => Some(BlobFile::new(&options)?),
+            false => None,
         };
         let mut engine = Self {
-            options: Arc::new(opts),
+            options: RwLock::new(Arc::new(opts)),
             active_file: Arc::new(RwLock::new(active_file)),
             older_files: Arc::new(RwLock::new(older_files)),
-            index: Box::new(index::new_index(options.index_type)),
-            file_ids: file_ids,
+            index: index::new_index(options.index_type),
+            file_ids,
             batch_commit_lock: Mutex::new(()),
+            write_lock: RwLock::new(()),
+            merge_lock: Mutex::new(()),
             seq_no: Arc::new(AtomicUsize::new(1)),
+            appended_records: AtomicU64::new(0),
+            written_bytes: AtomicU64::new(0),
+            total_records: AtomicU64::new(0),
+            deleted_records: AtomicU64::new(0),
+            key_locks: new_key_locks(),
+            change_log: RwLock::new(ChangeLog::default()),
+            value_index: options.value_index_prefix_len.map(ValuePrefixIndex::new),
+            pending_index: Mutex::new(HashMap::new()),
+            open_files: Mutex::new(VecDeque::new()),
+            watchers: Mutex::new(HashMap::new()),
+            shutdown: ShutdownGate::new(),
+            open_report: OpenReport::default(),
+            transactions: Vec::new(),
+            blob_file,
+            index_sidecar: Mutex::new(None),
         };
-        let current_seq_no = engine.load_index_from_data_files()?;
+        let (current_seq_no, open_report, transactions) =
+            engine.load_index_from_data_files(&engine.file_ids)?;
+        engine.open_report = open_report;
+        if options.file_footer {
+            engine.open_report.footer_mismatches = engine.verify_file_footers()?;
+        }
+        engine.transactions = transactions;
+        engine.written_bytes.store(engine.disk_bytes(), Ordering::SeqCst);
         if current_seq_no > 0 {
             engine.seq_no.store(current_seq_no+1, Ordering::SeqCst);
         }
+        // 以更小的 data_file_size 重新打开时，已写满的活跃文件作为旧文件封存
+        engine.seal_oversized_active_file()?;
+        // 已有的活跃文件可能是在没有开启 preallocate 时创建的
+        engine.active_file.read().preallocate()?;
+        // 所有检查都通过之后才保存布局，打开失败时不改变目录中的内容
+        if layout_missing {
+            write_record_layout(&dir_path, options.record_layout)?;
+        }
         Ok(engine)
     }
+    /// 获取数据库生效的配置项
+    pub fn options(&self) -> Arc<Options> {
+        self.options.read().clone()
+    }
+    /// 获取打开数据库时的恢复情况
+    pub fn open_report(&self) -> &OpenReport {
+        &self.open_report
+    }
+    /// 获取打开数据库时在数据文件中找到的已提交事务的序列号，按照提交的顺序排列
+    ///
+    /// 不包括打开之后提交的事务，合并之后的数据不再保留事务信息
+    pub fn transactions(&self) -> Result<Vec<usize>> {
+        Ok(self.transactions.clone())
+    }
     pub fn close(&self) -> Result<()> {
-        let read_guard = self.active_file.read();
-        read_guard.sync()
+        self.close_with_report().map(|_| ())
+    }
+    /// 关闭数据库，并返回关闭时尚未持久化的字节数，用于估计非同步写入模式下可能丢失的数据
+    pub fn close_with_report(&self) -> Result<CloseReport> {
+        self.shutdown.drain(|| {
+            let mut pending_index = self.pending_index.lock();
+            let read_guard = self.active_file.read();
+            let unsynced_bytes = read_guard.get_write_off() - read_guard.get_synced_off();
+            // 所有数据都已经持久化时不需要再次 sync
+            let synced = unsynced_bytes > 0;
+            // 数据文件中的指针持久化之前，blob 文件中对应的 value 需要先持久化
+            if let Some(blob_file) = &self.blob_file {
+                blob_file.sync()?;
+            }
+            if synced {
+                read_guard.sync()?;
+            }
+            self.apply_pending_index(&mut pending_index);
+            Ok(CloseReport {
+                unsynced_bytes,
+                synced,
+            })
+        })
     }
+    /// 持久化数据文件
+    ///
+    /// 数据先追加到活跃文件再更新索引，因此索引可能指向尚未持久化的数据。
+    /// 开启 read_synced_only 后，get 只返回已经 sync 的数据，
+    /// 未持久化的写入在下一次 sync（或 sync_writes 的写入）之后才可见。
     pub fn sync(&self) -> Result<()> {
+        // 持有队列的锁直到 sync 完成，队列中的记录都在 sync 之前写入
+        let mut pending_index = self.pending_index.lock();
+        if let Some(blob_file) = &self.blob_file {
+            blob_file.sync()?;
+        }
         let read_guard = self.active_file.read();
-        read_guard.sync()
+        read_guard.sync()?;
+        self.apply_pending_index(&mut pending_index);
+        Ok(())
+    }
+    /// 将活跃文件截断到 offset，丢弃之后的所有记录并重建索引
+    ///
+    /// offset 必须位于记录的边界上，重建索引期间的读操作可能读不到数据，
+    /// 内存中的变更记录也会按照截断之后的数据重新生成
+    pub fn truncate_active(&self, offset: u64) -> Result<()> {
+        let _merge_lock = self.merge_lock.lock();
+        let _lock = self.batch_commit_lock.lock();
+        let _write_lock = self.write_lock.write();
+        let mut pending_index = self.pending_index.lock();
+        {
+            let active_file = self.active_file.write();
+            if offset > active_file.get_write_off() {
+                return Err(Errors::InvalidTruncateOffset);
+            }
+            // 从头扫描到 offset，确认 offset 位于记录边界上
+            let mut record_off = 0;
+            while record_off < offset {
+                record_off += active_file.read_log_record(record_off)?.size as u64;
+            }
+            if record_off != offset {
+                return Err(Errors::InvalidTruncateOffset);
+            }
+            active_file.truncate(offset)?;
+            active_file.preallocate()?;
+        }
+        // 索引会根据数据文件重建，队列中的位置不再需要
+        pending_index.clear();
+        // 索引文件中截断之后的记录已经不存在，重新扫描数据文件生成
+        IndexSidecar::discard(&mut self.index_sidecar.lock());
+
+        for key in self.index.list_keys()? {
+            self.index.delete(key.to_vec());
+        }
+        self.change_log.write().clear();
+        if let Some(value_index) = &self.value_index {
+            value_index.clear();
+        }
+        self.total_records.store(0, Ordering::SeqCst);
+        self.deleted_records.store(0, Ordering::SeqCst);
+        self.load_index_from_data_files(&self.data_file_ids())?;
+        Ok(())
+    }
+    /// 把数据目录移动到 new_dir，之后在新的位置继续读写，new_dir 不能已经存在
+    ///
+    /// 移动期间阻塞所有的写操作。同一个文件系统中直接重命名目录，否则复制之后删除原来的目录；
+    /// 在新的位置打开文件失败时把目录恢复到原来的位置
+    pub fn relocate(&self, new_dir: PathBuf) -> Result<()> {
+        self.shutdown.drain(|| self.relocate_dir(new_dir))
+    }
+    fn relocate_dir(&self, new_dir: PathBuf) -> Result<()> {
+        let _merge_lock = self.merge_lock.lock();
+        let _lock = self.batch_commit_lock.lock();
+        let _write_lock = self.write_lock.write();
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+        let old_options = self.options();
+        let old_dir = old_options.dir_path.clone();
+        if new_dir.exists() {
+            return Err(Errors::DatabaseAlreadyExists);
+        }
+        active_file.sync()?;
+        if let Some(blob_file) = &self.blob_file {
+            blob_file.sync()?;
+        }
+        let copied = move_dir(&old_dir, &new_dir)?;
+
+        let options = Options {
+            dir_path: new_dir.clone(),
+            ..(*old_options).clone()
+        };
+        let reopened = self
+            .reopen_data_files(&options, &active_file, &older_files)
+            .and_then(|data_files| {
+                let index_sidecar = match &*self.index_sidecar.lock() {
+                    Some(index_sidecar) => Some(index_sidecar.reopen(&new_dir)?),
+                    None => None,
+                };
+                if let Some(blob_file) = &self.blob_file {
+                    blob_file.reopen(&options)?;
+                }
+                Ok((data_files, index_sidecar))
+            });
+        let ((new_active_file, new_older_files), index_sidecar) = match reopened {
+            Ok(v) => v,
+            Err(e) => {
+                // 恢复到原来的位置，原来的文件句柄仍然可以使用
+                let res = match copied {
+                    true => fs::remove_dir_all(&new_dir),
+                    false => fs::rename(&new_dir, &old_dir),
+                };
+                if let Err(e) = res {
+                    warn!("failed to roll back relocated directory: {}", e);
+                }
+                return Err(e);
+            }
+        };
+        *active_file = new_active_file;
+        *older_files = new_older_files;
+        self.open_files.lock().clear();
+        *self.index_sidecar.lock() = index_sidecar;
+        *self.options.write() = Arc::new(options);
+        if copied {
+            if let Err(e) = fs::remove_dir_all(&old_dir) {
+                warn!("failed to remove old database directory: {}", e);
+            }
+        }
+        Ok(())
+    }
+    /// 在新的目录中打开所有的数据文件，写入位置保持不变
+    fn reopen_data_files(
+        &self,
+        options: &Options,
+        active_file: &DataFile,
+        older_files: &HashMap<u32, DataFile>,
+    ) -> Result<(DataFile, HashMap<u32, DataFile>)> {
+        let reopen = |data_file: &DataFile| -> Result<DataFile> {
+            let new_file = DataFile::new(options, data_file.get_file_id())?;
+            new_file.set_write_offset(data_file.get_write_off());
+            new_file.set_synced_offset(data_file.get_synced_off());
+            Ok(new_file)
+        };
+        let new_active_file = reopen(active_file)?;
+        let mut new_older_files = HashMap::new();
+        for (file_id, data_file) in older_files.iter() {
+            let new_file = reopen(data_file)?;
+            // 限制了句柄数量时先关闭，使用时再按需打开
+            if options.max_open_files.is_some() {
+                new_file.close_handle()?;
+            }
+            new_older_files.insert(*file_id, new_file);
+        }
+        Ok((new_active_file, new_older_files))
+    }
+    /// 持久化所有数据文件，并返回当前的持久化位置
+    pub fn checkpoint(&self) -> Result<Checkpoint> {
+        // 持有活跃文件的写锁，避免 checkpoint 期间有新的写入
+        let mut pending_index = self.pending_index.lock();
+        let active_file = self.active_file.write();
+        let older_files = self.older_files.read();
+        for data_file in older_files.values() {
+            data_file.sync()?;
+        }
+        if let Some(blob_file) = &self.blob_file {
+            blob_file.sync()?;
+        }
+        active_file.sync()?;
+        self.apply_pending_index(&mut pending_index);
+
+        Ok(Checkpoint {
+            active_file_id: active_file.get_file_id(),
+            write_offset: active_file.get_write_off(),
+            seq_no: self.seq_no.load(Ordering::SeqCst) - 1,
+        })
     }
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
-        if key.is_empty() {
-            return Err(Errors::KeyIsEmpty);
+        self.put_at(key, value).map(|_| ())
+    }
+    /// 写入字符串类型的 value
+    pub fn put_string(&self, key: Bytes, value: &str) -> Result<()> {
+        self.put(key, Bytes::copy_from_slice(value.as_bytes()))
+    }
+    /// 写入数据并返回记录在数据文件中的位置，可用于维护外部索引或者复制的偏移
+    pub fn put_at(&self, key: Bytes, value: Bytes) -> Result<LogRecordPos> {
+        self.put_record(key, value, 0)
+    }
+    /// 写入数据并在记录中保存自定义的 flags（例如 value 是否经过压缩），可以通过 metadata 读取
+    pub fn put_with_flags(&self, key: Bytes, value: Bytes, flags: u8) -> Result<()> {
+        self.put_record(key, value, flags).map(|_| ())
+    }
+    /// 大量导入数据：先把所有记录追加到数据文件中，最后一次性更新索引并 sync
+    ///
+    /// 导入期间不应该读取数据库，新写入的数据在导入完成之前读不到，也不会通知 watch 的调用方。
+    /// 中途失败时已经写入的记录仍然会被更新到索引中，与重启之后恢复的结果一致
+    pub fn bulk_load(&self, pairs: impl Iterator<Item = (Bytes, Bytes)>) -> Result<()> {
+        let _in_flight = self.shutdown.enter();
+        let _lock = self.batch_commit_lock.lock();
+        let _write_lock = self.write_lock.read();
+        let mut positions = Vec::new();
+        let res = self.append_bulk_records(pairs, &mut positions);
+        // 先 sync 把之前等待 sync 的位置更新到索引中，避免覆盖导入的数据
+        self.sync()?;
+        self.index.put_batch(positions);
+        res
+    }
+    fn append_bulk_records(
+        &self,
+        pairs: impl Iterator<Item = (Bytes, Bytes)>,
+        positions: &mut Vec<(Vec<u8>, LogRecordPos)>,
+    ) -> Result<()> {
+        // 只追加模式下导入的数据之间也不能有重复的 key
+        let mut loaded_keys = HashSet::new();
+        for (key, value) in pairs {
+            let key = self.normalize_key(key);
+            self.check_key(&key)?;
+            if self.options().append_only
+                && (self.lookup_index(&key).is_some() || !loaded_keys.insert(key.clone()))
+            {
+                return Err(Errors::KeyAlreadyExists);
+            }
+            let mut record = LogRecord {
+                key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+                value: value.to_vec(),
+                rec_type: LogRecordType::NORMAL,
+                flags: 0,
+            };
+            let log_record_pos = self.append_log_record(&mut record)?;
+            self.record_change(LogRecordType::NORMAL, key.to_vec(), value.to_vec())?;
+            positions.push((key.to_vec(), log_record_pos));
         }
+        Ok(())
+    }
+    fn put_record(&self, key: Bytes, value: Bytes, flags: u8) -> Result<LogRecordPos> {
+        let key = self.normalize_key(key);
+        self.check_key(&key)?;
+        let _in_flight = self.shutdown.enter();
         let mut record = LogRecord {
             key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
             value: value.to_vec(),
             rec_type: LogRecordType::NORMAL,
+            flags,
         };
 
+        let _lock = self.batch_commit_lock.lock();
+        if self.options().append_only && self.lookup_index(&key).is_some() {
+            return Err(Errors::KeyAlreadyExists);
+        }
+        if self.options().skip_identical_writes {
+            if let Some(pos) = self.lookup_index(&key) {
+                if self.record_equals(&pos, &value, flags)? {
+                    return Ok(pos);
+                }
+            }
+        }
+        let _write_lock = self.write_lock.read();
         let log_record_pos = self.append_log_record(&mut record)?;
-        match self.index.put(key.to_vec(), log_record_pos) {
-            false => Err(Errors::IndexUpdateFailed),
-            true => Ok(()),
+        if !self.update_index_or_defer(key.to_vec(), Some(log_record_pos)) {
+            return Err(Errors::IndexUpdateFailed);
         }
+        self.record_change(LogRecordType::NORMAL, key.to_vec(), value.to_vec())?;
+        if self.options().defer_index_until_sync && self.options().sync_writes {
+            self.sync()?;
+        }
+        self.notify_watchers(&key, WatchEvent::Put(value));
+        Ok(log_record_pos)
     }
     pub fn delete(&self, key: Bytes) -> Result<()> {
-        if key.is_empty() {
-            return Err(Errors::KeyIsEmpty);
+        let key = self.normalize_key(key);
+        self.check_key(&key)?;
+        if self.options().append_only {
+            return Err(Errors::DeletesDisabled);
         }
-        let pos = self.index.get(key.to_vec());
-        if pos.is_none() {
+        let _in_flight = self.shutdown.enter();
+        let _lock = self.batch_commit_lock.lock();
+        if self.lookup_index(&key).is_none() {
             return Ok(());
         }
         let mut record = LogRecord {
             key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
             value: Default::default(),
             rec_type: LogRecordType::DElETED,
+            flags: 0,
         };
+        let _write_lock = self.write_lock.read();
         self.append_log_record(&mut record)?;
 
-        let ok = self.index.delete(key.to_vec());
-        if !ok {
+        if !self.update_index_or_defer(key.to_vec(), None) {
             return Err(Errors::IndexUpdateFailed);
         }
+        self.record_change(LogRecordType::DElETED, key.to_vec(), Vec::new())?;
+        if self.options().defer_index_until_sync && self.options().sync_writes {
+            self.sync()?;
+        }
+        self.notify_watchers(&key, WatchEvent::Delete);
         Ok(())
     }
+    /// 读取 pos 位置上记录的 value，pos 通常来自 put_at 的返回值
+    ///
+    /// 合并之后旧的位置可能已经失效，此时返回 DataFileNotFound
+    pub fn read_at(&self, pos: &LogRecordPos) -> Result<Bytes> {
+        let log_record = self.read_log_record_at(pos)?.record;
+        match log_record.rec_type {
+            LogRecordType::NORMAL => Ok(log_record.value.into()),
+            LogRecordType::BlobPointer => Ok(self.read_blob_value(&log_record.value)?.into()),
+            _ => Err(Errors::KeyNotFound),
+        }
+    }
     pub fn get(&self, key: Bytes) -> Result<Bytes> {
         // println!("key: {:?}",key);
+        let key = self.normalize_key(key);
+        let log_record_pos = self.get_position(&key)?;
+        self.get_value_resolving(&key, log_record_pos)
+    }
+    /// 读取 value 并转换为字符串，value 不是合法的 UTF-8 时返回 InvalidUtf8
+    pub fn get_string(&self, key: Bytes) -> Result<String> {
+        let value = self.get(key)?;
+        String::from_utf8(value.to_vec()).map_err(|_| Errors::InvalidUtf8)
+    }
+    /// 读取 pos 位置的 value，文件已经被合并删除时重新从索引中查找 key 的位置
+    ///
+    /// 查找索引和读取文件之间 merge 可能已经删除了旧文件，此时索引已经指向合并后的位置；
+    /// read_at 使用外部保存的位置，不会重新查找，直接返回 DataFileNotFound
+    pub(crate) fn get_value_resolving(&self, key: &Bytes, pos: LogRecordPos) -> Result<Bytes> {
+        let mut pos = pos;
+        loop {
+            match self.get_value_by_position(&pos) {
+                Err(Errors::DataFileNotFound) => {
+                    let new_pos = self.get_position(key)?;
+                    // 索引中的位置没有变化，说明文件确实不存在
+                    if new_pos == pos {
+                        return Err(Errors::DataFileNotFound);
+                    }
+                    pos = new_pos;
+                }
+                res => return res,
+            }
+        }
+    }
+    /// 读取索引快照中 key 的 value，文件已经被合并删除时重新查找，key 已经被删除时返回 None
+    pub(crate) fn get_snapshot_value(
+        &self,
+        key: &[u8],
+        pos: LogRecordPos,
+    ) -> Result<Option<Bytes>> {
+        match self.get_value_resolving(&Bytes::copy_from_slice(key), pos) {
+            Ok(value) => Ok(Some(value)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// 非阻塞的 get，key 不存在时返回 None
+    ///
+    /// 需要的数据文件锁或者等待 sync 的索引队列被其他线程持有时立即返回 WouldBlock，而不是等待
+    pub fn try_get(&self, key: Bytes) -> Result<Option<Bytes>> {
+        let key = self.normalize_key(key);
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
-        let pos = self.index.get(key.to_vec());
+        let mut pos = None;
+        if self.options().defer_index_until_sync {
+            let pending_index = self.pending_index.try_lock().ok_or(Errors::WouldBlock)?;
+            pos = pending_index.get(&key[..]).copied();
+        }
+        let log_record_pos = match pos.unwrap_or_else(|| self.index.get(key.to_vec())) {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+
+        let active_file = self.active_file.try_read().ok_or(Errors::WouldBlock)?;
+        let log_record = if active_file.get_file_id() == log_record_pos.file_id {
+            if self.options().read_synced_only
+                && log_record_pos.offset >= active_file.get_synced_off()
+            {
+                return Ok(None);
+            }
+            active_file.read_log_record(log_record_pos.offset)?
+        } else {
+            drop(active_file);
+            let older_files = self.older_files.try_read().ok_or(Errors::WouldBlock)?;
+            let data_file = match older_files.get(&log_record_pos.file_id) {
+                Some(data_file) => data_file,
+                None => return Err(Errors::DataFileNotFound),
+            };
+            let log_record = data_file.read_log_record(log_record_pos.offset)?;
+            self.touch_older_file(&older_files, log_record_pos.file_id);
+            log_record
+        };
+        match self.log_record_value(log_record.record) {
+            Ok(value) => Ok(Some(value)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// 查找 value 以 prefix 开头的所有 key，按照 key 排序，需要设置 value_index_prefix_len
+    ///
+    /// prefix 比 value_index_prefix_len 长时，会读取候选 key 的 value 进行过滤
+    pub fn keys_by_value_prefix(&self, prefix: &[u8]) -> Vec<Bytes> {
+        let value_index = match &self.value_index {
+            Some(value_index) => value_index,
+            None => return Vec::new(),
+        };
+        let keys = value_index.keys(prefix);
+        if prefix.len() <= value_index.prefix_len() {
+            return keys;
+        }
+        keys.into_iter()
+            .filter(|key| match self.get(key.clone()) {
+                Ok(value) => value.starts_with(prefix),
+                Err(_) => false,
+            })
+            .collect()
+    }
+    /// 重建索引，在大量删除之后把多余的内存归还给分配器
+    pub fn shrink_index(&self) {
+        self.index.shrink();
+    }
+    /// 获取 value 的长度，只读取记录的头部，不读取 value
+    pub fn value_len(&self, key: Bytes) -> Result<u64> {
+        self.metadata(key).map(|metadata| metadata.value_len)
+    }
+    /// 获取 key 当前记录的位置、value 的长度以及写入时的 flags，只读取记录的头部
+    pub fn metadata(&self, key: Bytes) -> Result<RecordMetadata> {
+        let key = self.normalize_key(key);
+        let pos = self.get_position(&key)?;
+        let header = self.with_data_file(pos.file_id, |data_file| {
+            data_file.read_log_record_header(pos.offset)
+        })?;
+        let value_len = match header.rec_type {
+            LogRecordType::NORMAL => header.value_size as u64,
+            LogRecordType::BlobPointer => {
+                let log_record = self.read_log_record(pos.file_id, pos.offset)?.record;
+                BlobPointer::decode(&log_record.value)?.size
+            }
+            _ => return Err(Errors::KeyNotFound),
+        };
+        Ok(RecordMetadata {
+            pos,
+            value_len,
+            flags: header.flags,
+        })
+    }
+    /// 读取 value 中 [start, start + len) 范围内的数据
+    ///
+    /// 只读取需要的部分，因此不会对整条记录进行 crc 校验
+    pub fn get_range(&self, key: Bytes, start: u64, len: u64) -> Result<Bytes> {
+        let key = self.normalize_key(key);
+        let pos = self.get_position(&key)?;
+        let header = self.with_data_file(pos.file_id, |data_file| {
+            data_file.read_log_record_header(pos.offset)
+        })?;
+        match header.rec_type {
+            LogRecordType::NORMAL => {
+                check_value_range(start, len, header.value_size as u64)?;
+                let value_off = pos.offset + (header.header_size + header.key_size) as u64;
+                let mut buf = vec![0u8; len as usize];
+                self.with_data_file(pos.file_id, |data_file| {
+                    data_file.read(&mut buf, value_off + start)
+                })?;
+                Ok(buf.into())
+            }
+            LogRecordType::BlobPointer => {
+                let log_record = self.read_log_record(pos.file_id, pos.offset)?.record;
+                let pointer = BlobPointer::decode(&log_record.value)?;
+                check_value_range(start, len, pointer.size)?;
+                Ok(self.get_blob_file()?.read_range(&pointer, start, len)?.into())
+            }
+            _ => Err(Errors::KeyNotFound),
+        }
+    }
+    /// 查找 key 的位置，尚未 sync 的写入优先于索引
+    pub(crate) fn lookup_index(&self, key: &[u8]) -> Option<LogRecordPos> {
+        if self.options().defer_index_until_sync {
+            if let Some(pos) = self.pending_index.lock().get(key) {
+                return *pos;
+            }
+        }
+        self.index.get(key.to_vec())
+    }
+    /// 更新索引，开启 defer_index_until_sync 时放入队列等待 sync 之后再更新
+    pub(crate) fn update_index_or_defer(&self, key: Vec<u8>, pos: Option<LogRecordPos>) -> bool {
+        if self.options().defer_index_until_sync {
+            self.pending_index.lock().insert(key, pos);
+            return true;
+        }
+        match pos {
+            Some(pos) => self.index.put(key, pos),
+            None => self.index.delete(key),
+        }
+    }
+    /// 数据文件 sync 成功之后，将队列中的位置更新到索引中
+    pub(crate) fn apply_pending_index(
+        &self,
+        pending_index: &mut HashMap<Vec<u8>, Option<LogRecordPos>>,
+    ) {
+        for (key, pos) in pending_index.drain() {
+            match pos {
+                Some(pos) => self.index.put(key, pos),
+                None => self.index.delete(key),
+            };
+        }
+    }
+    /// 根据索引找到 key 对应的记录位置
+    fn get_position(&self, key: &Bytes) -> Result<LogRecordPos> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let pos = self.lookup_index(key);
         if pos.is_none() {
             return Err(Errors::KeyNotFound);
         }
         let log_record_pos = pos.unwrap();
-        self.get_value_by_position(&log_record_pos)
+        if self.options().read_synced_only {
+            let active_file = self.read_active_file();
+            if active_file.get_file_id() == log_record_pos.file_id
+                && log_record_pos.offset >= active_file.get_synced_off()
+            {
+                return Err(Errors::KeyNotFound);
+            }
+        }
+        Ok(log_record_pos)
     }
-    pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
-        let active_file = self.active_file.read();
-        let older_files = self.older_files.read();
-        let log_record = match active_file.get_file_id() == log_record_pos.file_id {
-            true => active_file.read_log_record(log_record_pos.offset)?.record,
-            false => {
-                let data_file = older_files.get(&log_record_pos.file_id);
-                if data_file.is_none() {
-                    return Err(Errors::DataFileNotFound);
-                }
-                data_file
-                    .unwrap()
-                    .read_log_record(log_record_pos.offset)?
-                    .record
+    /// 计算 put 一条数据在数据文件中占用的字节数
+    ///
+    /// 包含记录头部、带序列号前缀的 key、value 和 crc，按照 Varint 布局计算，
+    /// 超过 inline_value_threshold 的 value 实际写入的是 blob 指针
+    pub fn record_size(key: &[u8], value: &[u8]) -> usize {
+        let key_len = key.len() + length_delimiter_len(NON_TRANSACTION_SEQ_NO);
+        encoded_record_length(key_len, value.len())
+    }
+    /// 按照 key_normalizer 转换调用方传入的 key
+    pub(crate) fn normalize_key(&self, key: Bytes) -> Bytes {
+        match &self.options().key_normalizer {
+            Some(normalizer) => normalizer(&key).into(),
+            None => key,
+        }
+    }
+    /// 校验写入的 key 是否合法
+    pub(crate) fn check_key(&self, key: &Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        if let Some(max_key_size) = self.options().max_key_size {
+            if key.len() > max_key_size {
+                return Err(Errors::KeyTooLarge);
             }
-        };
-
+        }
+        Ok(())
+    }
+    pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
+        let log_record = self.read_log_record_at(log_record_pos)?.record;
+        self.log_record_value(log_record)
+    }
+    /// 判断 pos 位置记录的 value 是否与 value 相同，先比较长度，长度相同时才读取 value
+    fn record_equals(&self, pos: &LogRecordPos, value: &[u8], flags: u8) -> Result<bool> {
+        let header = self.with_data_file(pos.file_id, |data_file| {
+            data_file.read_log_record_header(pos.offset)
+        })?;
+        if header.flags != flags {
+            return Ok(false);
+        }
+        match header.rec_type {
+            LogRecordType::NORMAL if header.value_size != value.len() => return Ok(false),
+            LogRecordType::NORMAL | LogRecordType::BlobPointer => {}
+            _ => return Ok(false),
+        }
+        let log_record = self.read_log_record(pos.file_id, pos.offset)?.record;
+        if log_record.rec_type == LogRecordType::NORMAL {
+            return Ok(log_record.value == value);
+        }
+        let pointer = BlobPointer::decode(&log_record.value)?;
+        if pointer.size != value.len() as u64 {
+            return Ok(false);
+        }
+        Ok(self.get_blob_file()?.read(&pointer)? == value)
+    }
+    /// 索引指向的记录中的 value，blob 指针会读取 blob 文件中实际的 value
+    fn log_record_value(&self, log_record: LogRecord) -> Result<Bytes> {
         if log_record.rec_type == LogRecordType::DElETED {
+            // 删除时会移除索引，索引指向删除记录说明存在 bug，debug 模式下单独报错
+            if cfg!(debug_assertions) {
+                return Err(Errors::IndexInconsistent);
+            }
             return Err(Errors::KeyNotFound);
         }
+        if log_record.rec_type == LogRecordType::BlobPointer {
+            return Ok(self.read_blob_value(&log_record.value)?.into());
+        }
         Ok(log_record.value.into())
     }
+    /// 根据文件的布隆过滤器判断 key 是否可能存在，活跃文件总是返回 true
+    ///
+    /// 只用于不经过索引逐个检查文件的场景，通过索引读取时索引已经确定了 key 所在的文件
+    pub(crate) fn file_may_contain(&self, file_id: u32, key: &[u8]) -> bool {
+        match self.older_files.read().get(&file_id) {
+            Some(data_file) => data_file.may_contain(key),
+            None => true,
+        }
+    }
+    /// 从活跃文件或者旧文件中读取一条记录
+    pub(crate) fn read_log_record(&self, file_id: u32, offset: u64) -> Result<ReadLogRecord> {
+        self.with_data_file(file_id, |data_file| data_file.read_log_record(offset))
+    }
+    /// 读取 pos 位置的记录，pos 超出文件已经写入的范围时返回 InvalidLogRecordPos
+    ///
+    /// 活跃文件写入位置之后可能是预分配的空间，读到的内容没有意义
+    pub(crate) fn read_log_record_at(&self, pos: &LogRecordPos) -> Result<ReadLogRecord> {
+        self.with_data_file(pos.file_id, |data_file| {
+            if pos.offset >= data_file.get_write_off() {
+                return Err(Errors::InvalidLogRecordPos);
+            }
+            data_file.read_log_record(pos.offset)
+        })
+    }
+    /// 在活跃文件或者旧的数据文件中找到 file_id 对应的文件并执行 f
+    pub(crate) fn with_data_file<T>(
+        &self,
+        file_id: u32,
+        f: impl FnOnce(&DataFile) -> Result<T>,
+    ) -> Result<T> {
+        let active_file = self.read_active_file();
+        if active_file.get_file_id() == file_id {
+            return f(&active_file);
+        }
+        // 读取旧文件时可能需要重新打开文件，不能阻塞活跃文件的写入
+        drop(active_file);
+        let older_files = self.older_files.read();
+        let res = match older_files.get(&file_id) {
+            Some(data_file) => f(data_file),
+            None => return Err(Errors::DataFileNotFound),
+        };
+        self.touch_older_file(&older_files, file_id);
+        res
+    }
+    /// 将旧数据文件标记为最近使用，打开的句柄超过 max_open_files 时关闭最久没有使用的句柄
+    pub(crate) fn touch_older_file(&self, older_files: &HashMap<u32, DataFile>, file_id: u32) {
+        let max_open_files = match self.options().max_open_files {
+            Some(max_open_files) => max_open_files,
+            None => return,
+        };
+        let mut open_files = self.open_files.lock();
+        if let Some(i) = open_files.iter().position(|fid| *fid == file_id) {
+            open_files.remove(i);
+        }
+        open_files.push_back(file_id);
+        while open_files.len() > max_open_files {
+            let evicted = open_files.pop_front().unwrap();
+            // 已经被合并删除的文件不需要处理
+            if let Some(data_file) = older_files.get(&evicted) {
+                if let Err(e) = data_file.close_handle() {
+                    warn!("failed to close data file handle, file id:{}, err:{}", evicted, e);
+                }
+            }
+        }
+    }
+    /// 读操作按照 lock_priority 获取活跃文件的读锁
+    pub(crate) fn read_active_file(&self) -> RwLockReadGuard<'_, DataFile> {
+        match self.options().lock_priority {
+            LockPriority::WriterPreferred => self.active_file.read(),
+            // read_recursive 不会等待排队中的写操作
+            LockPriority::ReaderPreferred => self.active_file.read_recursive(),
+        }
+    }
+    /// 当前所有数据文件的 id，按照从小到大排列
+    pub(crate) fn data_file_ids(&self) -> Vec<u32> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+        file_ids.push(active_file.get_file_id());
+        file_ids.sort();
+        file_ids
+    }
+    /// 根据记录中保存的 BlobPointer 读取实际的 value
+    pub(crate) fn read_blob_value(&self, pointer: &[u8]) -> Result<Vec<u8>> {
+        self.get_blob_file()?.read(&BlobPointer::decode(pointer)?)
+    }
+    pub(crate) fn get_blob_file(&self) -> Result<&BlobFile> {
+        match &self.blob_file {
+            Some(blob_file) => Ok(blob_file),
+            None => Err(Errors::DataFileNotFound),
+        }
+    }
     pub(crate) fn append_log_record(&self, log_record: &mut LogRecord) -> Result<LogRecordPos> {
-        let dir_path = self.options.dir_path.clone();
-        let enc_record = log_record.encode();
+        // 较大的 value 写入 blob 文件，数据文件中只保存指针
+        if let Some(threshold) = self.options().inline_value_threshold {
+            if log_record.rec_type == LogRecordType::NORMAL && log_record.value.len() > threshold {
+                let blob_file = self.blob_file.as_ref().unwrap();
+                let pointer = blob_file.write(&log_record.value)?;
+                self.written_bytes.fetch_add(pointer.size, Ordering::SeqCst);
+                if self.options().sync_writes {
+                    blob_file.sync()?;
+                }
+                log_record.value = pointer.encode();
+                log_record.rec_type = LogRecordType::BlobPointer;
+            }
+        }
+        let enc_record = log_record.encode_with_layout(self.options().record_layout);
         let record_len = enc_record.len() as u64;
 
         let mut active_file = self.active_file.write();
 
-        if active_file.get_write_off() + record_len > self.options.data_file_size {
+        if active_file.get_write_off() + record_len > self.options().data_file_size {
+            if let Some(blob_file) = &self.blob_file {
+                blob_file.sync()?;
+            }
             active_file.sync()?;
 
+            // 直接把当前的活跃文件移动到旧文件中，不需要重新打开
             let current_fid = active_file.get_file_id();
+            let new_file = DataFile::new_active(&self.options(), current_fid + 1)?;
+            let old_file = std::mem::replace(&mut *active_file, new_file);
             let mut older_files = self.older_files.write();
-            let old_file = DataFile::new(dir_path.clone(), current_fid)?;
             older_files.insert(current_fid, old_file);
-
-            let new_file = DataFile::new(dir_path.clone(), current_fid + 1)?;
-            *active_file = new_file;
+            self.touch_older_file(&older_files, current_fid);
         }
         let write_off = active_file.get_write_off();
         active_file.write(&enc_record)?;
-        if self.options.sync_writes {
+        self.written_bytes.fetch_add(record_len, Ordering::SeqCst);
+        let log_record_pos = LogRecordPos {
+            file_id: active_file.get_file_id(),
+            offset: write_off,
+        };
+        self.append_index_sidecar(log_record, log_record_pos, enc_record.len());
+        self.count_records(log_record.rec_type, true);
+        if self.options().sync_writes {
             active_file.sync()?;
+        } else if let Some(interval) = self.options().sync_interval_writes {
+            // 每追加 interval 条记录持久化一次
+            let appended = self.appended_records.fetch_add(1, Ordering::SeqCst) + 1;
+            if appended.is_multiple_of(interval) {
+                if let Some(blob_file) = &self.blob_file {
+                    blob_file.sync()?;
+                }
+                active_file.sync()?;
+            }
         }
 
-        Ok(LogRecordPos {
-            file_id: active_file.get_file_id(),
-            offset: write_off,
-        })
+        Ok(log_record_pos)
     }
-    fn load_index_from_data_files(&mut self) -> Result<usize> {
-        let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
-        if self.file_ids.is_empty() {
-            return Ok(current_seq_no);
+    /// 更新数据记录和删除记录的计数，added 为 false 时表示记录从数据文件中移除
+    pub(crate) fn count_records(&self, rec_type: LogRecordType, added: bool) {
+        let deleted = match rec_type {
+            LogRecordType::NORMAL | LogRecordType::BlobPointer => false,
+            LogRecordType::DElETED => true,
+            _ => return,
+        };
+        let update = |counter: &AtomicU64| match added {
+            true => counter.fetch_add(1, Ordering::SeqCst),
+            false => counter
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| Some(n.saturating_sub(1)))
+                .unwrap(),
+        };
+        update(&self.total_records);
+        if deleted {
+            update(&self.deleted_records);
         }
+    }
+    /// 开启 index_sidecar 时在索引文件中追加刚写入的记录，需要持有活跃文件的写锁
+    fn append_index_sidecar(&self, log_record: &LogRecord, pos: LogRecordPos, size: usize) {
+        let mut index_sidecar = self.index_sidecar.lock();
+        if index_sidecar.is_some() {
+            let entry = SidecarEntry {
+                key: log_record.key.clone(),
+                rec_type: log_record.rec_type,
+                pos,
+                size,
+            };
+            IndexSidecar::append_or_discard(&mut index_sidecar, &entry);
+        }
+    }
+    /// 按照摘要文件校验所有数据文件，返回不一致的文件 id，没有摘要文件的文件不校验
+    fn verify_file_footers(&self) -> Result<Vec<u32>> {
+        let mut mismatches = Vec::new();
+        for file_id in self.file_ids.iter() {
+            let verified = self.with_data_file(*file_id, |data_file| data_file.verify_footer())?;
+            if verified == Some(false) {
+                warn!("data file {} does not match its footer", file_id);
+                mismatches.push(*file_id);
+            }
+        }
+        Ok(mismatches)
+    }
+    fn seal_oversized_active_file(&self) -> Result<()> {
+        let mut active_file = self.active_file.write();
+        if active_file.get_write_off() < self.options().data_file_size {
+            return Ok(());
+        }
+        active_file.sync()?;
 
-        let mut transaction_records = HashMap::new();
-
+        let current_fid = active_file.get_file_id();
+        let new_file = DataFile::new_active(&self.options(), current_fid + 1)?;
+        let old_file = std::mem::replace(&mut *active_file, new_file);
+        let mut older_files = self.older_files.write();
+        older_files.insert(current_fid, old_file);
+        self.touch_older_file(&older_files, current_fid);
+        Ok(())
+    }
+    /// 按顺序读取数据文件构建索引，返回最大的序列号、恢复情况以及已提交事务的序列号
+    ///
+    /// 开启 index_sidecar 时先按照索引文件恢复，只读取索引文件中没有记录的数据
+    fn load_index_from_data_files(
+        &self,
+        file_ids: &[u32],
+    ) -> Result<(usize, OpenReport, Vec<usize>)> {
+        let mut loader = IndexLoader::default();
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
 
-        for (i, file_id) in self.file_ids.iter().enumerate() {
-            let mut offset = 0;
-            loop {
-                let log_record_res = match *file_id == active_file.get_file_id() {
-                    true => active_file.read_log_record(offset),
-                    false => {
-                        let data_file = older_files.get(file_id).unwrap();
-                        data_file.read_log_record(offset)
+        // 从 file_ids[start_index] 的 start_offset 处开始读取数据文件
+        let mut start_index = 0;
+        let mut start_offset = 0;
+        let mut sidecar = None;
+        if self.index_sidecar_enabled() {
+            let dir_path = &self.options().dir_path;
+            match self.read_index_sidecar(file_ids, &active_file, &older_files)? {
+                Some((entries, len)) => {
+                    for entry in entries {
+                        if entry.pos.file_id != file_ids[start_index] {
+                            // 之后的记录位于下一个文件中，当前文件已经全部恢复
+                            let data_file = older_files.get(&file_ids[start_index]).unwrap();
+                            data_file.set_write_offset(start_offset);
+                            data_file.set_synced_offset(start_offset);
+                            self.touch_older_file(&older_files, file_ids[start_index]);
+                            start_index += 1;
+                        }
+                        start_offset = entry.pos.offset + entry.size as u64;
+                        let log_record = LogRecord {
+                            key: entry.key,
+                            value: Vec::new(),
+                            rec_type: entry.rec_type,
+                            flags: 0,
+                        };
+                        loader.apply(self, log_record, entry.pos, entry.size)?;
                     }
+                    sidecar = Some(IndexSidecar::open(dir_path, len)?);
+                }
+                None => sidecar = Some(IndexSidecar::create(dir_path)?),
+            }
+        } else {
+            IndexSidecar::remove(&self.options().dir_path);
+        }
+
+        for (i, file_id) in file_ids.iter().enumerate().skip(start_index) {
+            let mut offset = match i == start_index {
+                true => start_offset,
+                false => 0,
+            };
+            loop {
+                let data_file = match *file_id == active_file.get_file_id() {
+                    true => &*active_file,
+                    false => older_files.get(file_id).unwrap(),
+                };
+                let log_record_res = match self.options().verify_on_open {
+                    true => data_file.read_log_record_unverified(offset),
+                    false => data_file.read_log_record(offset).map(|r| (r, true)),
                 };
-                let (mut log_record, size) = match log_record_res {
-                    Ok(result) => (result.record, result.size),
+                let (log_record, size) = match log_record_res {
+                    Ok((result, true)) => (result.record, result.size),
+                    Ok((result, false)) => {
+                        warn!("invalid log record crc, file id:{}, offset:{}", file_id, offset);
+                        loader.report.corrupted_records.push(CorruptedRecord {
+                            file_id: *file_id,
+                            offset,
+                            size: result.size,
+                        });
+                        offset = advance_offset(offset, result.size)?;
+                        continue;
+                    }
+                    Err(Errors::DataDirectoryCorrupted)
+                        if self.options().recover_mode != RecoverMode::Strict =>
+                    {
+                        // 记录超出了文件的范围，文件剩余的部分都无法解析
+                        warn!("log record out of bounds, file id:{}, offset:{}", file_id, offset);
+                        let file_size = data_file.file_size()?;
+                        loader.report.corrupted_records.push(CorruptedRecord {
+                            file_id: *file_id,
+                            offset,
+                            size: file_size.saturating_sub(offset) as usize,
+                        });
+                        offset = file_size;
+                        break;
+                    }
                     Err(e) => {
                         if e == Errors::ReadDataFileEOF {
                             break;
@@ -224,48 +1188,115 @@ impl Engine {
                     file_id: *file_id,
                     offset,
                 };
-                let (real_key, seq_no) = parse_log_record_key(log_record.key.clone());
-                // 如果不是事务提交的话
-                if seq_no == NON_TRANSACTION_SEQ_NO {
-                    self.update_index(real_key, log_record.rec_type, log_record_pos)
-                } else {
-                    if log_record.rec_type == LogRecordType::TxnFinish {
-                        let records: &Vec<TransactionRecord> =
-                            transaction_records.get(&seq_no).unwrap();
-                        for txn_record in records.iter() {
-                            self.update_index(
-                                txn_record.record.key.clone(),
-                                txn_record.record.rec_type,
-                                txn_record.pos,
-                            )
-                        }
-                        transaction_records.remove(&seq_no);
-                    } else {
-                        log_record.key = real_key;
-                        transaction_records
-                            .entry(seq_no)
-                            .or_insert(Vec::new())
-                            .push(TransactionRecord {
-                                record: log_record,
-                                pos: log_record_pos,
-                            })
-                    }
-                }
-                if seq_no > current_seq_no {
-                    current_seq_no=seq_no;
+                loader.report.scanned_records += 1;
+                if sidecar.is_some() {
+                    let entry = SidecarEntry {
+                        key: log_record.key.clone(),
+                        rec_type: log_record.rec_type,
+                        pos: log_record_pos,
+                        size,
+                    };
+                    IndexSidecar::append_or_discard(&mut sidecar, &entry);
                 }
-
-                offset += size as u64;
+                loader.apply(self, log_record, log_record_pos, size)?;
+                offset = advance_offset(offset, size)?;
             }
-            if i == self.file_ids.len() - 1 {
-                active_file.set_write_offset(offset);
+            // 启动时文件中已有的数据视为已经持久化
+            let data_file = match i == file_ids.len() - 1 {
+                true => &*active_file,
+                false => older_files.get(file_id).unwrap(),
+            };
+            data_file.set_write_offset(offset);
+            data_file.set_synced_offset(offset);
+            if i < file_ids.len() - 1 {
+                self.touch_older_file(&older_files, *file_id);
             }
         }
+        let mut report = loader.report;
+        if !report.corrupted_records.is_empty() {
+            if self.options().recover_mode == RecoverMode::Strict {
+                return Err(Errors::InvalidLogRecordCrc);
+            }
+            // 索引文件需要与数据文件中的记录一一对应，存在损坏的记录时每次启动都扫描数据文件
+            IndexSidecar::discard(&mut sidecar);
+        }
+        *self.index_sidecar.lock() = sidecar;
+        // 没有结束标记的批次在恢复时被忽略，但是仍然占用磁盘空间直到合并
+        report.orphaned_batches = loader
+            .batch_bytes
+            .into_iter()
+            .map(|(seq_no, bytes)| OrphanedBatch { seq_no, bytes })
+            .collect();
 
-        Ok(current_seq_no)
+        drop(active_file);
+        drop(older_files);
+        Ok((loader.current_seq_no, report, loader.transactions))
+    }
+    /// 不读取 value 就可以恢复时才使用索引文件
+    fn index_sidecar_enabled(&self) -> bool {
+        self.options().index_sidecar
+            && !self.options().enable_change_log
+            && self.value_index.is_none()
+            && !self.options().verify_on_open
+    }
+    /// 读取索引文件，返回其中的项以及有效部分的长度
+    ///
+    /// 索引文件中的记录需要从第一个数据文件开始按照顺序连续排列，并且最后一条记录存在于数据文件中，
+    /// 否则返回 None，从头扫描所有的数据文件
+    fn read_index_sidecar(
+        &self,
+        file_ids: &[u32],
+        active_file: &DataFile,
+        older_files: &HashMap<u32, DataFile>,
+    ) -> Result<Option<(Vec<SidecarEntry>, u64)>> {
+        let (entries, len) = match IndexSidecar::read_entries(&self.options().dir_path)? {
+            Some(v) if !file_ids.is_empty() => v,
+            _ => return Ok(None),
+        };
+        let mut file_index = 0;
+        let mut next_offset = 0;
+        for entry in entries.iter() {
+            if entry.pos.file_id != file_ids[file_index] {
+                // 只能在读取了当前文件的记录之后进入下一个文件
+                file_index += 1;
+                if next_offset == 0
+                    || file_index >= file_ids.len()
+                    || entry.pos.file_id != file_ids[file_index]
+                {
+                    warn!("index sidecar does not match data files, file id:{}", entry.pos.file_id);
+                    return Ok(None);
+                }
+                next_offset = 0;
+            }
+            if entry.pos.offset != next_offset {
+                warn!("index sidecar is not contiguous, file id:{}", entry.pos.file_id);
+                return Ok(None);
+            }
+            next_offset = entry.pos.offset + entry.size as u64;
+        }
+        // 数据文件中未持久化的记录可能丢失，索引文件中的最后一条记录需要与数据文件一致
+        if let Some(last) = entries.last() {
+            let data_file = match last.pos.file_id == active_file.get_file_id() {
+                true => active_file,
+                false => older_files.get(&last.pos.file_id).unwrap(),
+            };
+            let matched = match data_file.read_log_record(last.pos.offset) {
+                Ok(result) => {
+                    result.size == last.size
+                        && result.record.key == last.key
+                        && result.record.rec_type == last.rec_type
+                }
+                Err(_) => false,
+            };
+            if !matched {
+                warn!("index sidecar is ahead of data files");
+                return Ok(None);
+            }
+        }
+        Ok(Some((entries, len)))
     }
     fn update_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) {
-        if rec_type == LogRecordType::NORMAL {
+        if rec_type == LogRecordType::NORMAL || rec_type == LogRecordType::BlobPointer {
             self.index.put(key.clone(), pos);
         }
         if rec_type == LogRecordType::DElETED {
@@ -281,50 +1312,310 @@ impl Engine {
         // }
     }
 }
-fn check_options(opts: &Options) -> Option<Errors> {
+fn check_value_range(start: u64, len: u64, value_size: u64) -> Result<()> {
+    match start.checked_add(len) {
+        Some(end) if end <= value_size => Ok(()),
+        _ => Err(Errors::InvalidValueRange),
+    }
+}
+
+pub(crate) fn check_options(opts: &Options) -> Option<Errors> {
     let dir_path = opts.dir_path.to_str();
-    if dir_path.is_none() || dir_path.unwrap().len() == 0 {
+    if dir_path.is_none() || dir_path.unwrap().is_empty() {
         return Some(Errors::DirPathIsEmpty);
     }
-    if opts.data_file_size <= 0 {
+    if opts.data_file_size < min_data_file_size(opts) {
         return Some(Errors::DirFileSizeTooSmall);
     }
+    if opts.files_per_dir == Some(0) {
+        return Some(Errors::InvalidFilesPerDir);
+    }
+    if opts.sync_interval_writes == Some(0) {
+        return Some(Errors::InvalidSyncInterval);
+    }
     None
 }
 
-fn load_data_files(dir_path: PathBuf) -> Result<Vec<DataFile>> {
-    let dir = fs::read_dir(dir_path.clone());
-    if dir.is_err() {
-        return Err(Errors::FailedToReadDataBaseDir);
-    }
-    let mut file_ids: Vec<u32> = Vec::new();
-    let mut data_files: Vec<DataFile> = Vec::new();
-    for file in dir.unwrap() {
-        if let Ok(entry) = file {
-            let file_os_str = entry.file_name();
-            let file_name = file_os_str.to_str().unwrap();
-
-            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-                let split_names: Vec<&str> = file_name.split('.').collect();
-                let file_id = match split_names[0].parse::<u32>() {
-                    Ok(fid) => fid,
-                    Err(_) => {
-                        return Err(Errors::DataDirectoryCorrupted);
-                    }
-                };
-                file_ids.push(file_id);
+/// 数据文件的最小大小，至少要能放下一条 key 达到最大长度的记录，否则几乎每次写入都会切换文件
+///
+/// 按照最长的 FixedWidth 头部、flags 字节、key、最长的序列号以及 crc 计算
+pub(crate) fn min_data_file_size(opts: &Options) -> u64 {
+    let key_size = opts.max_key_size.unwrap_or(MIN_FILE_KEY_SIZE);
+    let record_size =
+        FIXED_LOG_RECORD_HEADER_SIZE + 1 + key_size + length_delimiter_len(usize::MAX) + 4;
+    record_size as u64
+}
+
+/// 启动时按照写入顺序处理每一条记录，恢复索引和事务
+#[derive(Default)]
+struct IndexLoader {
+    current_seq_no: usize,
+    /// 按照序列号排列，第一个是最早的事务
+    transaction_records: BTreeMap<usize, Vec<TransactionRecord>>,
+    pending_txn_records: usize,
+    /// 尚未读到结束标记的批次已经占用的字节数
+    batch_bytes: BTreeMap<usize, u64>,
+    transactions: Vec<usize>,
+    report: OpenReport,
+}
+
+impl IndexLoader {
+    fn apply(
+        &mut self,
+        engine: &Engine,
+        mut log_record: LogRecord,
+        log_record_pos: LogRecordPos,
+        size: usize,
+    ) -> Result<()> {
+        let (real_key, seq_no) = match parse_log_record_key(log_record.key.clone()) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!(
+                    "invalid log record key, file id:{}, offset:{}",
+                    log_record_pos.file_id, log_record_pos.offset
+                );
+                if engine.options().recover_mode == RecoverMode::Strict {
+                    return Err(e);
+                }
+                self.report.corrupted_records.push(CorruptedRecord {
+                    file_id: log_record_pos.file_id,
+                    offset: log_record_pos.offset,
+                    size,
+                });
+                return Ok(());
+            }
+        };
+        engine.count_records(log_record.rec_type, true);
+        // 如果不是事务提交的话
+        if seq_no == NON_TRANSACTION_SEQ_NO {
+            engine.update_index(real_key.clone(), log_record.rec_type, log_record_pos);
+            engine.record_change(log_record.rec_type, real_key, log_record.value)?;
+        } else if self.report.dropped_transactions.contains(&seq_no) {
+            // 已经被丢弃的事务，剩余的记录也不再处理
+        } else if log_record.rec_type == LogRecordType::TxnFinish {
+            let records = self.transaction_records.remove(&seq_no).unwrap_or_default();
+            self.pending_txn_records -= records.len();
+            for txn_record in records.iter() {
+                engine.update_index(
+                    txn_record.record.key.clone(),
+                    txn_record.record.rec_type,
+                    txn_record.pos,
+                );
+                engine.record_change(
+                    txn_record.record.rec_type,
+                    txn_record.record.key.clone(),
+                    txn_record.record.value.clone(),
+                )?;
+            }
+            self.transactions.push(seq_no);
+            self.batch_bytes.remove(&seq_no);
+        } else if log_record.rec_type == LogRecordType::TxnBegin {
+            self.transaction_records.entry(seq_no).or_default();
+            *self.batch_bytes.entry(seq_no).or_default() += size as u64;
+        } else {
+            *self.batch_bytes.entry(seq_no).or_default() += size as u64;
+            log_record.key = real_key;
+            self.transaction_records
+                .entry(seq_no)
+                .or_default()
+                .push(TransactionRecord {
+                    record: log_record,
+                    pos: log_record_pos,
+                });
+            self.pending_txn_records += 1;
+            while self.pending_txn_records > engine.options().max_pending_txn_records {
+                if engine.options().recover_mode == RecoverMode::Strict {
+                    return Err(Errors::TooManyPendingTransactions);
+                }
+                let (dropped_seq_no, records) = self.transaction_records.pop_first().unwrap();
+                warn!("drop pending transaction, seq no:{}", dropped_seq_no);
+                self.pending_txn_records -= records.len();
+                self.batch_bytes.remove(&dropped_seq_no);
+                self.report.dropped_transactions.push(dropped_seq_no);
             }
         }
+        if seq_no > self.current_seq_no {
+            self.current_seq_no = seq_no;
+        }
+        Ok(())
     }
+}
+
+/// 跳过 size 字节的记录，损坏的记录长度导致溢出时返回错误
+fn advance_offset(offset: u64, size: usize) -> Result<u64> {
+    offset
+        .checked_add(size as u64)
+        .ok_or(Errors::DataDirectoryCorrupted)
+}
+
+/// 读取目录中保存的记录布局，没有保存时返回 None
+pub(crate) fn read_record_layout(dir_path: &Path) -> Result<Option<RecordLayout>> {
+    match fs::read_to_string(dir_path.join(RECORD_LAYOUT_FILE_NAME)) {
+        Ok(content) => match content.trim() {
+            "varint" => Ok(Some(RecordLayout::Varint)),
+            "fixed-width" => Ok(Some(RecordLayout::FixedWidth)),
+            _ => Err(Errors::DataDirectoryCorrupted),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(_) => Err(Errors::FailedToReadDataBaseDir),
+    }
+}
 
-    if file_ids.is_empty() {
+fn write_record_layout(dir_path: &Path, layout: RecordLayout) -> Result<()> {
+    let content = match layout {
+        RecordLayout::Varint => "varint",
+        RecordLayout::FixedWidth => "fixed-width",
+    };
+    fs::write(dir_path.join(RECORD_LAYOUT_FILE_NAME), content)
+        .map_err(|_| Errors::FailedToWriteToDataFile)
+}
+
+fn load_data_files(options: &Options) -> Result<Vec<DataFile>> {
+    let mut data_file_paths = HashMap::new();
+    find_data_files(&options.dir_path, &mut data_file_paths)?;
+    let mut data_files: Vec<DataFile> = Vec::new();
+    if data_file_paths.is_empty() {
         return Ok(data_files);
     }
+    let mut file_ids: Vec<u32> = data_file_paths.keys().copied().collect();
     file_ids.sort();
 
     for file_id in file_ids.iter() {
-        let data_file = DataFile::new(dir_path.clone(), *file_id)?;
+        // 文件的位置与当前的目录布局不一致时，移动到正确的位置
+        let path = &data_file_paths[file_id];
+        let expected_path = get_data_file_name(options, *file_id);
+        if *path != expected_path {
+            relocate_data_file(options, path, *file_id)?;
+        }
+        let data_file = DataFile::new(options, *file_id)?;
+        // 限制了句柄数量时先关闭，使用时再按需打开
+        if options.max_open_files.is_some() {
+            data_file.close_handle()?;
+        }
         data_files.push(data_file);
     }
     Ok(data_files)
 }
+
+/// 递归查找目录中的数据文件，只进入 files_per_dir 生成的子目录
+///
+/// 目录本身无法读取时返回 FailedToReadDataBaseDir，单个无法读取的目录项只记录日志并跳过
+pub(crate) fn find_data_files(
+    dir_path: &Path,
+    data_file_paths: &mut HashMap<u32, PathBuf>,
+) -> Result<()> {
+    let dir = match fs::read_dir(dir_path) {
+        Ok(dir) => dir,
+        Err(_) => return Err(Errors::FailedToReadDataBaseDir),
+    };
+    for entry in dir {
+        // 单个目录项读取失败时跳过，不影响其他文件的加载
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("failed to read entry in {}: {}", dir_path.display(), e);
+                continue;
+            }
+        };
+        let file_os_str = entry.file_name();
+        let file_name = match file_os_str.to_str() {
+            Some(file_name) => file_name,
+            // 数据文件的 id 无法解析
+            None if file_os_str
+                .as_encoded_bytes()
+                .ends_with(DATA_FILE_NAME_SUFFIX.as_bytes()) =>
+            {
+                return Err(Errors::DataDirectoryCorrupted);
+            }
+            None => {
+                warn!("skip entry with non utf-8 name: {}", entry.path().display());
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        if path.is_dir() {
+            // 其他目录（包括合并的临时目录）中的文件不属于数据库
+            if is_data_file_dir_name(file_name) {
+                find_data_files(&path, data_file_paths)?;
+            }
+            continue;
+        }
+        if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
+            let split_names: Vec<&str> = file_name.split('.').collect();
+            let file_id = match split_names[0].parse::<u32>() {
+                Ok(fid) => fid,
+                Err(_) => {
+                    return Err(Errors::DataDirectoryCorrupted);
+                }
+            };
+            // 同一个 id 出现在多个目录中
+            if data_file_paths.insert(file_id, path).is_some() {
+                return Err(Errors::DataDirectoryCorrupted);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 移动目录，src 和 dest 位于不同的文件系统时复制整个目录，返回是否进行了复制
+fn move_dir(src: &Path, dest: &Path) -> Result<bool> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|_| Errors::FailedToCreateDataBaseDir)?;
+    }
+    match fs::rename(src, dest) {
+        Ok(()) => Ok(false),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            if let Err(e) = copy_dir(src, dest) {
+                warn!("failed to copy database directory: {}", e);
+                let _ = fs::remove_dir_all(dest);
+                return Err(Errors::FailedToRelocateDatabase);
+            }
+            Ok(true)
+        }
+        Err(e) => {
+            warn!("failed to rename database directory: {}", e);
+            Err(Errors::FailedToRelocateDatabase)
+        }
+    }
+}
+
+/// 递归复制目录，复制的文件都会持久化
+fn copy_dir(src: &Path, dest: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dest_path = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest_path)?;
+        } else {
+            fs::copy(entry.path(), &dest_path)?;
+            fs::File::open(&dest_path)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// 将数据文件以及对应的布隆过滤器文件、摘要文件移动到当前目录布局下的位置
+/// files_per_dir 生成的子目录名称是 9 位数字
+fn is_data_file_dir_name(name: &str) -> bool {
+    name.len() == 9 && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn relocate_data_file(options: &Options, path: &Path, file_id: u32) -> Result<()> {
+    let file_dir = get_data_file_dir(options, file_id);
+    fs::create_dir_all(&file_dir).map_err(|_| Errors::FailedToCreateDataBaseDir)?;
+    fs::rename(path, get_data_file_name(options, file_id))
+        .map_err(|_| Errors::DataDirectoryCorrupted)?;
+    let bloom_path = path.with_extension(&BLOOM_FILE_NAME_SUFFIX[1..]);
+    if bloom_path.is_file() {
+        fs::rename(bloom_path, get_bloom_file_name(options, file_id))
+            .map_err(|_| Errors::DataDirectoryCorrupted)?;
+    }
+    let footer_path = path.with_extension(&FOOTER_FILE_NAME_SUFFIX[1..]);
+    if footer_path.is_file() {
+        fs::rename(footer_path, get_footer_file_name(options, file_id))
+            .map_err(|_| Errors::DataDirectoryCorrupted)?;
+    }
+    Ok(())
+}
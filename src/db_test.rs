@@ -4,7 +4,7 @@ use std::path::PathBuf;
 use crate::{
     db::Engine,
     errors::Errors,
-    options::Options,
+    options::{IndexType, LockPriority, Options, RecoverMode, WriteBatchOptions},
     util::rand_kv::{get_test_key, get_test_value},
 };
 
@@ -164,3 +164,2467 @@ fn test_engine_delete() {
     // 删除测试的文件夹
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
+
+#[test]
+fn test_engine_reopen_with_smaller_file_size() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-reopen-smaller"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        let res = engine.put(get_test_key(i), get_test_value(i as usize));
+        assert!(res.is_ok());
+    }
+    engine.close().expect("failed to close engine");
+    std::mem::drop(engine);
+
+    // 1.以更小的 data_file_size 重新打开，旧数据依然可以读取
+    let small_opts = Options {
+        data_file_size: 1024,
+        ..opts.clone()
+    };
+    let engine2 = Engine::open(small_opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(get_test_value(i as usize), res.unwrap());
+    }
+
+    // 2.新的写入遵循新的文件大小
+    for i in 100..200 {
+        let res = engine2.put(get_test_key(i), get_test_value(i as usize));
+        assert!(res.is_ok());
+    }
+    for i in 0..200 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(get_test_value(i as usize), res.unwrap());
+    }
+    let mut file_count = 0;
+    for entry in std::fs::read_dir(&small_opts.dir_path).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_name() == "000000000.data" {
+            continue;
+        }
+        file_count += 1;
+        assert!(entry.metadata().unwrap().len() <= small_opts.data_file_size);
+    }
+    assert!(file_count > 1);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_checkpoint() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-checkpoint"),
+        data_file_size: 64 * 1024 * 1024,
+        sync_writes: false,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.空的数据库
+    let cp1 = engine.checkpoint().unwrap();
+    assert_eq!(0, cp1.active_file_id);
+    assert_eq!(0, cp1.write_offset);
+    assert_eq!(0, cp1.seq_no);
+
+    // 2.写入数据后
+    for i in 0..10 {
+        let res = engine.put(get_test_key(i), get_test_value(i as usize));
+        assert!(res.is_ok());
+    }
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+    wb.put(get_test_key(100), get_test_value(100)).unwrap();
+    wb.commit().unwrap();
+
+    let cp2 = engine.checkpoint().unwrap();
+    assert_eq!(0, cp2.active_file_id);
+    assert!(cp2.write_offset > 0);
+    assert_eq!(1, cp2.seq_no);
+    assert_eq!(
+        cp2.write_offset,
+        std::fs::metadata(opts.dir_path.join("000000000.data"))
+            .unwrap()
+            .len()
+    );
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_key_size() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-max-key-size"),
+        data_file_size: 64 * 1024 * 1024,
+        max_key_size: Some(8),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.key 长度刚好在限制内
+    let res1 = engine.put(Bytes::from("12345678"), get_test_value(1));
+    assert!(res1.is_ok());
+    let res2 = engine.delete(Bytes::from("12345678"));
+    assert!(res2.is_ok());
+
+    // 2.key 长度超过限制
+    let res3 = engine.put(Bytes::from("123456789"), get_test_value(1));
+    assert_eq!(Errors::KeyTooLarge, res3.err().unwrap());
+    let res4 = engine.delete(Bytes::from("123456789"));
+    assert_eq!(Errors::KeyTooLarge, res4.err().unwrap());
+
+    // 3.批量写入同样校验
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+    let res5 = wb.put(Bytes::from("12345678"), get_test_value(1));
+    assert!(res5.is_ok());
+    let res6 = wb.put(Bytes::from("123456789"), get_test_value(1));
+    assert_eq!(Errors::KeyTooLarge, res6.err().unwrap());
+    let res7 = wb.delete(Bytes::from("123456789"));
+    assert_eq!(Errors::KeyTooLarge, res7.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_verify_on_open() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-verify-on-open"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let mut offsets = Vec::new();
+    for i in 0..3 {
+        offsets.push(engine.checkpoint().unwrap().write_offset);
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    offsets.push(engine.checkpoint().unwrap().write_offset);
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 破坏第二条记录的 value
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(opts.dir_path.join("000000000.data"))
+        .unwrap();
+    file.seek(SeekFrom::Start(offsets[2] - 5)).unwrap();
+    file.write_all(b"x").unwrap();
+    file.sync_all().unwrap();
+
+    // 1.严格模式下打开失败
+    let strict_opts = Options {
+        verify_on_open: true,
+        ..opts.clone()
+    };
+    let res1 = Engine::open(strict_opts);
+    assert_eq!(Errors::InvalidLogRecordCrc, res1.err().unwrap());
+
+    // 2.非严格模式下跳过损坏的记录
+    let skip_opts = Options {
+        verify_on_open: true,
+        recover_mode: RecoverMode::SkipCorrupted,
+        ..opts.clone()
+    };
+    let engine2 = Engine::open(skip_opts).expect("failed to open engine");
+    let report = engine2.open_report();
+    assert_eq!(1, report.corrupted_records.len());
+    assert_eq!(0, report.corrupted_records[0].file_id);
+    assert_eq!(offsets[1], report.corrupted_records[0].offset);
+    assert_eq!(
+        (offsets[2] - offsets[1]) as usize,
+        report.corrupted_records[0].size
+    );
+    assert_eq!(get_test_value(0), engine2.get(get_test_key(0)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(1)).err().unwrap());
+    assert_eq!(get_test_value(2), engine2.get(get_test_key(2)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_inline_value_threshold() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-inline-value-threshold"),
+        data_file_size: 64 * 1024 * 1024,
+        inline_value_threshold: Some(64),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let small_value = Bytes::from("small value");
+    let large_value = Bytes::from(vec![b'x'; 16 * 1024]);
+    engine.put(get_test_key(1), small_value.clone()).unwrap();
+    engine.put(get_test_key(2), large_value.clone()).unwrap();
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+    wb.put(get_test_key(3), large_value.clone()).unwrap();
+    wb.commit().unwrap();
+
+    assert_eq!(small_value, engine.get(get_test_key(1)).unwrap());
+    assert_eq!(large_value, engine.get(get_test_key(2)).unwrap());
+    assert_eq!(large_value, engine.get(get_test_key(3)).unwrap());
+
+    // 大的 value 不会写入数据文件
+    let data_file_size = std::fs::metadata(opts.dir_path.join("000000000.data"))
+        .unwrap()
+        .len();
+    assert!(data_file_size < 1024);
+
+    // 重启之后依然可以读取
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(small_value, engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(large_value, engine2.get(get_test_key(2)).unwrap());
+    assert_eq!(large_value, engine2.get(get_test_key(3)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_blob_file_synced_before_data_file() {
+    use crate::{
+        data::blob_file::BLOB_FILE_NAME,
+        fio::{
+            counting_io::{CountingIO, IOCounters},
+            file_io::FileIO,
+            IOManager,
+        },
+    };
+    use std::sync::Arc;
+
+    // 只统计 blob 文件的 sync 次数
+    let blob_counters = IOCounters::default();
+    let factory_counters = blob_counters.clone();
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-blob-file-synced"),
+        data_file_size: 1024,
+        sync_writes: false,
+        inline_value_threshold: Some(64),
+        io_factory: Some(Arc::new(move |file_name: PathBuf| {
+            if file_name.ends_with(BLOB_FILE_NAME) {
+                let io_manager = CountingIO::new(file_name, factory_counters.clone())?;
+                return Ok(Box::new(io_manager) as Box<dyn IOManager>);
+            }
+            Ok(Box::new(FileIO::new(file_name, 3)?) as Box<dyn IOManager>)
+        })),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let large_value = Bytes::from(vec![b'x'; 1024]);
+
+    // 1.活跃文件写满切换时先持久化 blob 文件
+    let mut i = 0;
+    while engine.data_file_ids().len() == 1 {
+        engine.put(get_test_key(i), large_value.clone()).unwrap();
+        i += 1;
+    }
+    let syncs_after_rotation = blob_counters.syncs();
+    assert!(syncs_after_rotation > 0);
+
+    // 2.关闭时同样持久化 blob 文件
+    engine.put(get_test_key(i), large_value.clone()).unwrap();
+    engine.close().unwrap();
+    assert!(blob_counters.syncs() > syncs_after_rotation);
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    for k in 0..=i {
+        assert_eq!(large_value, engine2.get(get_test_key(k)).unwrap());
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_read_synced_only() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-read-synced-only"),
+        data_file_size: 64 * 1024 * 1024,
+        sync_writes: false,
+        read_synced_only: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.未 sync 的数据不可见
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    let res1 = engine.get(get_test_key(1));
+    assert_eq!(Errors::KeyNotFound, res1.err().unwrap());
+
+    // 2.sync 之后可见
+    engine.sync().unwrap();
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+    // 3.覆盖写入后，新的值在 sync 之前同样不可见
+    engine.put(get_test_key(1), get_test_value(2)).unwrap();
+    let res2 = engine.get(get_test_key(1));
+    assert_eq!(Errors::KeyNotFound, res2.err().unwrap());
+    engine.sync().unwrap();
+    assert_eq!(get_test_value(2), engine.get(get_test_key(1)).unwrap());
+
+    // 4.重启后已有的数据都视为已持久化
+    engine.put(get_test_key(3), get_test_value(3)).unwrap();
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(3), engine2.get(get_test_key(3)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_hashmap_index() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-hashmap-index"),
+        data_file_size: 64 * 1024 * 1024,
+        index_type: IndexType::HashMap,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.put(get_test_key(1), Bytes::from("a new value")).unwrap();
+    engine.delete(get_test_key(2)).unwrap();
+    assert_eq!(Bytes::from("a new value"), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(2)).err().unwrap());
+
+    // 重启后索引正确恢复
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(99, engine2.list_keys().unwrap().len());
+    assert_eq!(Bytes::from("a new value"), engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(2)).err().unwrap());
+    assert_eq!(get_test_value(50), engine2.get(get_test_key(50)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_record_size() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-record-size"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let sizes = [(1, 0), (3, 4), (127, 128), (200, 20000), (16384, 1)];
+    for (key_size, value_size) in sizes {
+        let key = Bytes::from(vec![b'k'; key_size]);
+        let value = Bytes::from(vec![b'v'; value_size]);
+        let before = engine.checkpoint().unwrap().write_offset;
+        engine.put(key.clone(), value.clone()).unwrap();
+        let after = engine.checkpoint().unwrap().write_offset;
+        assert_eq!((after - before) as usize, Engine::record_size(&key, &value));
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_create_if_missing() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-create-if-missing"),
+        data_file_size: 64 * 1024 * 1024,
+        create_if_missing: false,
+        ..Default::default()
+    };
+
+    // 1.目录不存在时直接返回错误，并且不会创建目录
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::DatabaseNotFound, res1.err().unwrap());
+    assert!(!opts.dir_path.exists());
+
+    // 2.默认自动创建目录
+    let engine = Engine::open(Options {
+        create_if_missing: true,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 3.目录存在时正常打开
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_error_if_exists() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-error-if-exists"),
+        data_file_size: 64 * 1024 * 1024,
+        error_if_exists: true,
+        ..Default::default()
+    };
+
+    // 1.空的目录可以正常打开
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 2.已经存在数据文件时打开失败
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::DatabaseAlreadyExists, res.err().unwrap());
+
+    // 3.没有保存布局的旧数据库打开失败时不会写入布局文件
+    let layout_path = opts.dir_path.join("record-layout");
+    std::fs::remove_file(&layout_path).unwrap();
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::DatabaseAlreadyExists, res.err().unwrap());
+    assert!(!layout_path.exists());
+    let engine = Engine::open(Options {
+        error_if_exists: false,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    assert!(layout_path.is_file());
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_data_file_size_too_small() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-file-size-too-small"),
+        data_file_size: 1,
+        ..Default::default()
+    };
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::DirFileSizeTooSmall, res1.err().unwrap());
+
+    let res2 = Engine::open(Options {
+        data_file_size: 0,
+        ..opts.clone()
+    });
+    assert_eq!(Errors::DirFileSizeTooSmall, res2.err().unwrap());
+    assert!(!opts.dir_path.exists());
+
+    // 最小大小要能放下一条 key 达到 max_key_size 的记录
+    let opts = Options {
+        max_key_size: Some(100),
+        ..opts
+    };
+    let min_size = crate::db::min_data_file_size(&opts);
+    assert!(min_size > 100);
+    let res3 = Engine::open(Options {
+        data_file_size: min_size - 1,
+        ..opts.clone()
+    });
+    assert_eq!(Errors::DirFileSizeTooSmall, res3.err().unwrap());
+    assert!(!opts.dir_path.exists());
+
+    let opts = Options {
+        data_file_size: min_size,
+        ..opts
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let key = Bytes::from(vec![b'k'; 100]);
+    engine.put(key.clone(), Bytes::new()).unwrap();
+    engine.put(Bytes::from("a"), Bytes::from("b")).unwrap();
+    // 较短的记录可以写入同一个文件
+    assert_eq!(1, engine.data_file_ids().len());
+    assert_eq!(Bytes::new(), engine.get(key).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_options() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-options"),
+        data_file_size: 32 * 1024 * 1024,
+        sync_writes: false,
+        max_key_size: Some(128),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let engine_opts = engine.options();
+    assert_eq!(opts.dir_path, engine_opts.dir_path);
+    assert_eq!(opts.data_file_size, engine_opts.data_file_size);
+    assert_eq!(opts.sync_writes, engine_opts.sync_writes);
+    assert_eq!(opts.max_key_size, engine_opts.max_key_size);
+    assert_eq!(opts.recover_mode, engine_opts.recover_mode);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_open_with_invalid_key_prefix() {
+    use crate::{
+        batch::log_record_key_with_seq,
+        data::log_record::{LogRecord, LogRecordType},
+    };
+    use std::io::Write;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-invalid-key-prefix"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    let corrupt_offset = engine.checkpoint().unwrap().write_offset;
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 追加一条 crc 正确但是 key 前缀被截断的记录，以及一条正常的记录
+    let bad_record = LogRecord {
+        key: vec![0x80],
+        value: "xia".as_bytes().to_vec(),
+        rec_type: LogRecordType::NORMAL,
+        flags: 0,
+    };
+    let good_record = LogRecord {
+        key: log_record_key_with_seq(get_test_key(2).to_vec(), 0),
+        value: get_test_value(2).to_vec(),
+        rec_type: LogRecordType::NORMAL,
+        flags: 0,
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(opts.dir_path.join("000000000.data"))
+        .unwrap();
+    file.write_all(&bad_record.encode()).unwrap();
+    file.write_all(&good_record.encode()).unwrap();
+    file.sync_all().unwrap();
+
+    // 1.严格模式下返回错误而不是 panic
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::InvalidLogRecordKey, res1.err().unwrap());
+
+    // 2.非严格模式下跳过该记录
+    let engine2 = Engine::open(Options {
+        recover_mode: RecoverMode::SkipCorrupted,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    let report = engine2.open_report();
+    assert_eq!(1, report.corrupted_records.len());
+    assert_eq!(corrupt_offset, report.corrupted_records[0].offset);
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(2), engine2.get(get_test_key(2)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_history_skips_file_by_bloom_filter() {
+    use crate::{
+        data::{data_file::get_data_file_name, log_record::LogRecordType},
+        fio::counting_io::{CountingIO, IOCounters},
+    };
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-bloom-filter"),
+        data_file_size: 4 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    // 删除记录所在的文件写满之后只合并该文件，删除记录需要保留在合并后的文件中
+    engine.delete(get_test_key(5)).unwrap();
+    let tombstone_fid = engine.active_file.read().get_file_id();
+    for i in 100..200 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    assert_ne!(tombstone_fid, engine.active_file.read().get_file_id());
+    let files_before = engine.data_file_ids();
+    engine.merge_files(vec![tombstone_fid]).unwrap();
+    let file_id = engine
+        .data_file_ids()
+        .into_iter()
+        .filter(|fid| !files_before.contains(fid))
+        .find(|fid| engine.file_may_contain(*fid, &get_test_key(5)))
+        .unwrap();
+
+    // 1.合并后的文件的布隆过滤器包含删除记录的 key
+    let history = engine.history(get_test_key(5)).unwrap();
+    assert_eq!(LogRecordType::DElETED, history.last().unwrap().1);
+    assert_eq!(file_id, history.last().unwrap().0.file_id);
+
+    // 合并后的文件替换为可以统计读取次数的 IOManager
+    let counters = IOCounters::default();
+    {
+        let mut older_files = engine.older_files.write();
+        let data_file = older_files.get_mut(&file_id).unwrap();
+        let file_name = get_data_file_name(&opts, file_id);
+        data_file.set_io_manager(Box::new(CountingIO::new(file_name, counters.clone()).unwrap()));
+    }
+
+    // 2.布隆过滤器排除的 key 不会读取该文件
+    let absent_key = (1000..2000)
+        .map(get_test_key)
+        .find(|key| !engine.file_may_contain(file_id, key))
+        .unwrap();
+    assert!(engine.history(absent_key.clone()).unwrap().is_empty());
+    assert_eq!(0, counters.reads());
+
+    // 3.文件中存在的 key 需要读取文件
+    assert_eq!(2, engine.history(get_test_key(5)).unwrap().len());
+    assert!(counters.reads() > 0);
+
+    // 4.重启之后布隆过滤器依然生效
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(!engine2.file_may_contain(file_id, &absent_key));
+    assert!(engine2.file_may_contain(file_id, &get_test_key(5)));
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_range() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-get-range"),
+        data_file_size: 64 * 1024 * 1024,
+        inline_value_threshold: Some(1024),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let small_value = Bytes::from((0..200).map(|i| i as u8).collect::<Vec<u8>>());
+    let large_value = Bytes::from((0..5000).map(|i| (i % 251) as u8).collect::<Vec<u8>>());
+    engine.put(get_test_key(1), small_value.clone()).unwrap();
+    engine.put(get_test_key(2), large_value.clone()).unwrap();
+
+    // 1.读取不同的范围，与完整 value 的切片一致
+    for (key, value) in [(get_test_key(1), &small_value), (get_test_key(2), &large_value)] {
+        let size = value.len() as u64;
+        for (start, len) in [(0, size), (0, 1), (10, 50), (size - 7, 7), (size, 0)] {
+            let range = engine.get_range(key.clone(), start, len).unwrap();
+            assert_eq!(value.slice(start as usize..(start + len) as usize), range);
+        }
+        // 2.超出 value 长度的范围
+        let res = engine.get_range(key.clone(), size - 1, 2);
+        assert_eq!(Errors::InvalidValueRange, res.err().unwrap());
+        let res = engine.get_range(key.clone(), 1, u64::MAX);
+        assert_eq!(Errors::InvalidValueRange, res.err().unwrap());
+    }
+
+    // 3.不存在或者已经删除的 key
+    let res = engine.get_range(get_test_key(3), 0, 1);
+    assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+    engine.delete(get_test_key(1)).unwrap();
+    let res = engine.get_range(get_test_key(1), 0, 1);
+    assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_shrink_index() {
+    for (name, index_type) in [("btree", IndexType::BTree), ("hashmap", IndexType::HashMap)] {
+        let opts = Options {
+            dir_path: PathBuf::from(format!("./tmp/bitcask-rs-shrink-index-{}", name)),
+            data_file_size: 64 * 1024 * 1024,
+            sync_writes: false,
+            index_type,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10000 {
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        for i in 0..10000 {
+            if i % 100 != 0 {
+                engine.delete(get_test_key(i)).unwrap();
+            }
+        }
+
+        engine.shrink_index();
+
+        // 剩余的 key 依然可以正常读取
+        assert_eq!(100, engine.list_keys().unwrap().len());
+        for i in 0..10000 {
+            let res = engine.get(get_test_key(i));
+            if i % 100 == 0 {
+                assert_eq!(get_test_value(i as usize), res.unwrap());
+            } else {
+                assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+            }
+        }
+        engine.put(get_test_key(1), get_test_value(1)).unwrap();
+        assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
+#[test]
+fn test_engine_concurrent_stress() {
+    use std::{sync::Arc, thread};
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-concurrent-stress"),
+        data_file_size: 64 * 1024,
+        sync_writes: false,
+        ..Default::default()
+    };
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+    let num_threads = 8;
+    let keys_per_thread = 300;
+    let hot_key = |j: i32| get_test_key(100000 + j);
+
+    let mut handles = Vec::new();
+    for t in 0..num_threads {
+        let engine = engine.clone();
+        handles.push(thread::spawn(move || {
+            let base = t * keys_per_thread;
+            for i in base..base + keys_per_thread {
+                // 每个线程独占的 key
+                let value = Bytes::from(format!("{}-v1", i));
+                engine.put(get_test_key(i), value.clone()).unwrap();
+                assert_eq!(value, engine.get(get_test_key(i)).unwrap());
+                if i % 3 == 0 {
+                    engine.delete(get_test_key(i)).unwrap();
+                }
+
+                // 所有线程竞争的 key，读到的值要么不存在要么是完整的
+                let hot = hot_key(i % 10);
+                engine.put(hot.clone(), Bytes::from(format!("{}-hot", t))).unwrap();
+                let res = engine.get(hot);
+                assert!(res.unwrap().ends_with(b"-hot"));
+
+                // 批量提交
+                if i % 10 == 0 {
+                    let wb = engine.new_write_batch(WriteBatchOptions::default()).unwrap();
+                    for j in i..i + 5 {
+                        if j % 3 != 0 {
+                            wb.put(get_test_key(j), Bytes::from(format!("{}-v2", j))).unwrap();
+                        }
+                    }
+                    wb.commit().unwrap();
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let expected = |i: i32| -> Option<Bytes> {
+        if i % 3 == 0 {
+            return None;
+        }
+        // 批量提交写入的其他 key 随后会被单独的 put 覆盖
+        if i % 10 == 0 {
+            return Some(Bytes::from(format!("{}-v2", i)));
+        }
+        Some(Bytes::from(format!("{}-v1", i)))
+    };
+    let check = |engine: &Engine| {
+        for i in 0..num_threads * keys_per_thread {
+            match expected(i) {
+                Some(value) => assert_eq!(value, engine.get(get_test_key(i)).unwrap()),
+                None => assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(i)).err().unwrap()),
+            }
+        }
+    };
+    check(&engine);
+    assert!(engine.data_file_ids().len() > 1);
+    let hot_values: Vec<Bytes> = (0..10).map(|j| engine.get(hot_key(j)).unwrap()).collect();
+
+    // 重启之后恢复出来的数据与内存中的一致
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    check(&engine2);
+    for (j, value) in hot_values.iter().enumerate() {
+        assert_eq!(*value, engine2.get(hot_key(j as i32)).unwrap());
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_files_per_dir() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-files-per-dir"),
+        data_file_size: 1024,
+        sync_writes: false,
+        files_per_dir: Some(3),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..200 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    let file_ids = engine.data_file_ids();
+    assert!(file_ids.len() > 6);
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 1.数据文件分散在多个子目录中
+    for file_id in file_ids.iter() {
+        let shard_dir = opts.dir_path.join(format!("{:09}", file_id / 3));
+        assert!(shard_dir.join(format!("{:09}.data", file_id)).is_file());
+    }
+
+    // 2.重启之后可以找到所有的数据文件
+    let check = |engine: &Engine| {
+        assert_eq!(file_ids, engine.data_file_ids());
+        for i in 0..200 {
+            assert_eq!(get_test_value(i as usize), engine.get(get_test_key(i)).unwrap());
+        }
+    };
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    check(&engine2);
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+
+    // 3.修改目录布局之后，数据文件被移动到新的位置
+    let flat_opts = Options {
+        files_per_dir: None,
+        ..opts.clone()
+    };
+    let engine3 = Engine::open(flat_opts.clone()).expect("failed to open engine");
+    check(&engine3);
+    for file_id in file_ids.iter() {
+        assert!(opts.dir_path.join(format!("{:09}.data", file_id)).is_file());
+    }
+
+    // 4.files_per_dir 不能为 0
+    let res = Engine::open(Options {
+        files_per_dir: Some(0),
+        ..opts.clone()
+    });
+    assert_eq!(Errors::InvalidFilesPerDir, res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_append_only() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-append-only"),
+        data_file_size: 64 * 1024 * 1024,
+        append_only: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.第一次写入成功，再次写入同一个 key 失败
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    let res1 = engine.put(get_test_key(1), get_test_value(2));
+    assert_eq!(Errors::KeyAlreadyExists, res1.err().unwrap());
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+    // 2.不允许删除
+    let res2 = engine.delete(get_test_key(1));
+    assert_eq!(Errors::DeletesDisabled, res2.err().unwrap());
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+    // 3.批量写入同样受到限制
+    let wb = engine.new_write_batch(WriteBatchOptions::default()).unwrap();
+    let res3 = wb.delete(get_test_key(1));
+    assert_eq!(Errors::DeletesDisabled, res3.err().unwrap());
+    wb.put(get_test_key(1), get_test_value(3)).unwrap();
+    wb.put(get_test_key(2), get_test_value(2)).unwrap();
+    let res4 = wb.commit();
+    assert_eq!(Errors::KeyAlreadyExists, res4.err().unwrap());
+    assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(2)).err().unwrap());
+    wb.rollback();
+    wb.put(get_test_key(2), get_test_value(2)).unwrap();
+    wb.commit().unwrap();
+    assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_index_points_to_deleted_record() {
+    use crate::data::log_record::LogRecordPos;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-index-inconsistent"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    let checkpoint = engine.checkpoint().unwrap();
+    engine.delete(get_test_key(1)).unwrap();
+
+    // 让索引指向删除记录，模拟索引与数据文件不一致
+    engine.index.put(
+        get_test_key(1).to_vec(),
+        LogRecordPos {
+            file_id: checkpoint.active_file_id,
+            offset: checkpoint.write_offset,
+        },
+    );
+    let res = engine.get(get_test_key(1));
+    if cfg!(debug_assertions) {
+        assert_eq!(Errors::IndexInconsistent, res.err().unwrap());
+    } else {
+        assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_lock_priority() {
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+        time::Duration,
+    };
+
+    for (name, lock_priority) in [
+        ("writer", LockPriority::WriterPreferred),
+        ("reader", LockPriority::ReaderPreferred),
+    ] {
+        let opts = Options {
+            dir_path: PathBuf::from(format!("./tmp/bitcask-rs-lock-priority-{}", name)),
+            data_file_size: 64 * 1024 * 1024,
+            lock_priority,
+            ..Default::default()
+        };
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        engine.put(get_test_key(1), get_test_value(1)).unwrap();
+
+        // 一个长时间的读操作持有活跃文件的读锁
+        let read_guard = engine.read_active_file();
+        let (write_tx, write_rx) = mpsc::channel();
+        let writer = {
+            let engine = engine.clone();
+            thread::spawn(move || {
+                engine.put(get_test_key(2), get_test_value(2)).unwrap();
+                write_tx.send(()).unwrap();
+            })
+        };
+        // 写操作在 active_file.write() 上排队之后，普通的读锁无法再获取
+        while engine.active_file.try_read().is_some() {
+            thread::yield_now();
+        }
+        let (read_tx, read_rx) = mpsc::channel();
+        let reader = {
+            let engine = engine.clone();
+            thread::spawn(move || {
+                assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+                read_tx.send(()).unwrap();
+            })
+        };
+
+        match lock_priority {
+            // 写操作优先：新的读操作排在等待中的写操作之后
+            LockPriority::WriterPreferred => {
+                let res = read_rx.recv_timeout(Duration::from_millis(100));
+                assert_eq!(Err(mpsc::RecvTimeoutError::Timeout), res);
+            }
+            // 读操作优先：新的读操作不需要等待写操作
+            LockPriority::ReaderPreferred => read_rx.recv().unwrap(),
+        }
+        assert_eq!(Err(mpsc::TryRecvError::Empty), write_rx.try_recv());
+
+        std::mem::drop(read_guard);
+        writer.join().unwrap();
+        reader.join().unwrap();
+        write_rx.recv().unwrap();
+        if lock_priority == LockPriority::WriterPreferred {
+            read_rx.recv().unwrap();
+        }
+        assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
+#[test]
+fn test_engine_truncate_active() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-truncate-active"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine.put(get_test_key(2), get_test_value(2)).unwrap();
+    let offset = engine.checkpoint().unwrap().write_offset;
+    engine.put(get_test_key(3), get_test_value(3)).unwrap();
+    engine.put(get_test_key(1), get_test_value(10)).unwrap();
+    engine.delete(get_test_key(2)).unwrap();
+    let end_offset = engine.checkpoint().unwrap().write_offset;
+
+    // 1.offset 不在记录边界上或者超出文件长度
+    let res1 = engine.truncate_active(offset + 1);
+    assert_eq!(Errors::InvalidTruncateOffset, res1.err().unwrap());
+    let res2 = engine.truncate_active(end_offset + 1);
+    assert_eq!(Errors::InvalidTruncateOffset, res2.err().unwrap());
+    assert_eq!(get_test_value(10), engine.get(get_test_key(1)).unwrap());
+
+    // 2.截断之后的记录全部消失，被覆盖和删除的数据恢复
+    engine.truncate_active(offset).unwrap();
+    assert_eq!(offset, engine.checkpoint().unwrap().write_offset);
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(3)).err().unwrap());
+    assert_eq!(2, engine.list_keys().unwrap().len());
+
+    // 3.截断之后可以继续写入，重启之后数据一致
+    engine.put(get_test_key(4), get_test_value(4)).unwrap();
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(4), engine2.get(get_test_key(4)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(3)).err().unwrap());
+    assert_eq!(3, engine2.list_keys().unwrap().len());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_read_after_rotation() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-read-after-rotation"),
+        data_file_size: 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let mut i = 0;
+    while engine.data_file_ids().len() < 2 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        i += 1;
+    }
+
+    // 刚刚封存的文件保留了写入的偏移，并且可以正常读取
+    let sealed_fid = engine.data_file_ids()[0];
+    let sealed_len = std::fs::metadata(opts.dir_path.join(format!("{:09}.data", sealed_fid)))
+        .unwrap()
+        .len();
+    {
+        let older_files = engine.older_files.read();
+        let sealed_file = older_files.get(&sealed_fid).unwrap();
+        assert_eq!(sealed_len, sealed_file.get_write_off());
+        assert_eq!(sealed_len, sealed_file.get_synced_off());
+    }
+    for j in 0..i {
+        assert_eq!(get_test_value(j as usize), engine.get(get_test_key(j)).unwrap());
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_defer_index_until_sync() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-defer-index"),
+        data_file_size: 4 * 1024,
+        sync_writes: false,
+        defer_index_until_sync: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine.put(get_test_key(2), get_test_value(2)).unwrap();
+    engine.sync().unwrap();
+
+    // 1.未 sync 的写入不会更新索引，但是 get 可以读到
+    engine.put(get_test_key(3), get_test_value(3)).unwrap();
+    engine.put(get_test_key(1), get_test_value(10)).unwrap();
+    engine.delete(get_test_key(2)).unwrap();
+    assert!(engine.index.get(get_test_key(3).to_vec()).is_none());
+    assert!(engine.index.get(get_test_key(2).to_vec()).is_some());
+    assert_eq!(2, engine.list_keys().unwrap().len());
+    assert_eq!(get_test_value(3), engine.get(get_test_key(3)).unwrap());
+    assert_eq!(get_test_value(10), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(2)).err().unwrap());
+
+    // 2.批量写入同样延迟更新
+    let wb = engine
+        .new_write_batch(WriteBatchOptions {
+            sync_writes: false,
+            ..Default::default()
+        })
+        .unwrap();
+    wb.put(get_test_key(4), get_test_value(4)).unwrap();
+    wb.commit().unwrap();
+    assert!(engine.index.get(get_test_key(4).to_vec()).is_none());
+    assert_eq!(get_test_value(4), engine.get(get_test_key(4)).unwrap());
+
+    // 3.sync 之后索引更新
+    engine.sync().unwrap();
+    assert!(engine.pending_index.lock().is_empty());
+    assert_eq!(3, engine.list_keys().unwrap().len());
+    assert!(engine.index.get(get_test_key(2).to_vec()).is_none());
+    assert_eq!(get_test_value(10), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(3), engine.get(get_test_key(3)).unwrap());
+    assert_eq!(get_test_value(4), engine.get(get_test_key(4)).unwrap());
+
+    // 4.合并之前会先更新索引，未 sync 的数据不会丢失
+    engine.put(get_test_key(5), get_test_value(5)).unwrap();
+    // 写入较大的 value 使活跃文件写满，合并时存在旧文件
+    engine.put(get_test_key(6), Bytes::from(vec![0u8; 4 * 1024])).unwrap();
+    engine.merge().unwrap();
+    assert_eq!(get_test_value(5), engine.get(get_test_key(5)).unwrap());
+    assert_eq!(5, engine.list_keys().unwrap().len());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_keys_by_value_prefix() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-value-prefix"),
+        data_file_size: 64 * 1024 * 1024,
+        value_index_prefix_len: Some(4),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let put = |engine: &Engine, key: &'static str, value: &'static str| {
+        engine.put(Bytes::from(key), Bytes::from(value)).unwrap();
+    };
+    put(&engine, "k1", "user:alice");
+    put(&engine, "k2", "user:bob");
+    put(&engine, "k3", "item:apple");
+    let wb = engine.new_write_batch(WriteBatchOptions::default()).unwrap();
+    wb.put(Bytes::from("k4"), Bytes::from("item:pear")).unwrap();
+    wb.commit().unwrap();
+
+    // 1.共享前缀和不同前缀
+    let keys = |v: Vec<&'static str>| v.into_iter().map(Bytes::from).collect::<Vec<_>>();
+    assert_eq!(keys(vec!["k1", "k2"]), engine.keys_by_value_prefix(b"user"));
+    assert_eq!(keys(vec!["k3", "k4"]), engine.keys_by_value_prefix(b"item"));
+    assert_eq!(keys(vec!["k3", "k4"]), engine.keys_by_value_prefix(b"it"));
+    assert!(engine.keys_by_value_prefix(b"none").is_empty());
+    // 超过前缀长度的部分通过读取 value 过滤
+    assert_eq!(keys(vec!["k2"]), engine.keys_by_value_prefix(b"user:b"));
+
+    // 2.覆盖写入和删除
+    put(&engine, "k1", "item:grape");
+    engine.delete(Bytes::from("k3")).unwrap();
+    assert_eq!(keys(vec!["k2"]), engine.keys_by_value_prefix(b"user"));
+    assert_eq!(keys(vec!["k1", "k4"]), engine.keys_by_value_prefix(b"item"));
+
+    // 3.重启之后从数据文件中重建
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(keys(vec!["k2"]), engine2.keys_by_value_prefix(b"user"));
+    assert_eq!(keys(vec!["k1", "k4"]), engine2.keys_by_value_prefix(b"item"));
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_pending_txn_records() {
+    use crate::{
+        batch::log_record_key_with_seq,
+        data::log_record::{LogRecord, LogRecordType},
+    };
+    use std::io::Write;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-max-pending-txn"),
+        data_file_size: 64 * 1024 * 1024,
+        max_pending_txn_records: 10,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(0), get_test_value(0)).unwrap();
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 写入 10 个没有提交标记的事务，每个事务 3 条记录，最后写入一个完整的事务
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(opts.dir_path.join("000000000.data"))
+        .unwrap();
+    let txn_record = |key: i32, seq_no: usize| LogRecord {
+        key: log_record_key_with_seq(get_test_key(key).to_vec(), seq_no),
+        value: get_test_value(key as usize).to_vec(),
+        rec_type: LogRecordType::NORMAL,
+        flags: 0,
+    };
+    for seq_no in 1..=10 {
+        for i in 0..3 {
+            let record = txn_record(seq_no as i32 * 10 + i, seq_no);
+            file.write_all(&record.encode()).unwrap();
+        }
+    }
+    file.write_all(&txn_record(200, 11).encode()).unwrap();
+    let finish_record = LogRecord {
+        key: log_record_key_with_seq("txn_finish".as_bytes().to_vec(), 11),
+        value: Default::default(),
+        rec_type: LogRecordType::TxnFinish,
+        flags: 0,
+    };
+    file.write_all(&finish_record.encode()).unwrap();
+    file.sync_all().unwrap();
+
+    // 1.严格模式下超过上限时打开失败
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::TooManyPendingTransactions, res1.err().unwrap());
+
+    // 2.非严格模式下丢弃最早的事务，缓存的记录数不超过上限
+    let engine2 = Engine::open(Options {
+        recover_mode: RecoverMode::SkipCorrupted,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    let dropped = &engine2.open_report().dropped_transactions;
+    assert_eq!((1..=7).collect::<Vec<usize>>(), *dropped);
+    assert_eq!(vec![11], engine2.transactions().unwrap());
+    assert_eq!(get_test_value(200), engine2.get(get_test_key(200)).unwrap());
+    assert_eq!(2, engine2.list_keys().unwrap().len());
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+
+    // 3.上限足够大时可以正常打开
+    let engine3 = Engine::open(Options {
+        max_pending_txn_records: 100,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    assert!(engine3.open_report().dropped_transactions.is_empty());
+    assert_eq!(2, engine3.list_keys().unwrap().len());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_put_at() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-put-at"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.返回的位置可以读回写入的 value
+    let pos1 = engine.put_at(get_test_key(1), get_test_value(1)).unwrap();
+    let pos2 = engine.put_at(get_test_key(2), get_test_value(2)).unwrap();
+    assert_eq!(pos1.file_id(), pos2.file_id());
+    assert!(pos2.offset() > pos1.offset());
+    assert_eq!(get_test_value(1), engine.read_at(&pos1).unwrap());
+    assert_eq!(get_test_value(2), engine.read_at(&pos2).unwrap());
+
+    // 2.覆盖写入之后旧的位置仍然指向旧的 value
+    let pos3 = engine.put_at(get_test_key(1), get_test_value(3)).unwrap();
+    assert_eq!(get_test_value(1), engine.read_at(&pos1).unwrap());
+    assert_eq!(get_test_value(3), engine.read_at(&pos3).unwrap());
+    assert_eq!(get_test_value(3), engine.get(get_test_key(1)).unwrap());
+
+    // 3.重启之后位置依然有效
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(2), engine2.read_at(&pos2).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_open_files() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-max-open-files"),
+        data_file_size: 4 * 1024,
+        max_open_files: Some(3),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..2000 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    let open_handles = |engine: &Engine| {
+        let older_files = engine.older_files.read();
+        assert!(older_files.len() > 10);
+        older_files.values().filter(|f| f.is_handle_open()).count()
+    };
+    assert!(open_handles(&engine) <= 3);
+
+    // 1.随机读取所有文件中的数据，打开的句柄数量不超过上限
+    for i in (0..2000).rev().step_by(7) {
+        assert_eq!(get_test_value(i as usize), engine.get(get_test_key(i)).unwrap());
+        assert!(open_handles(&engine) <= 3);
+    }
+
+    // 2.重启之后加载索引时句柄数量同样受到限制
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(open_handles(&engine2) <= 3);
+    for i in 0..2000 {
+        assert_eq!(get_test_value(i as usize), engine2.get(get_test_key(i)).unwrap());
+    }
+    assert!(open_handles(&engine2) <= 3);
+
+    // 3.合并之后同样可以读取
+    engine2.merge().unwrap();
+    assert!(open_handles(&engine2) <= 3);
+    assert_eq!(get_test_value(1000), engine2.get(get_test_key(1000)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_try_get() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-try-get"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+
+    // 1.没有被持有的锁时和 get 的结果一致
+    assert_eq!(Some(get_test_value(1)), engine.try_get(get_test_key(1)).unwrap());
+    assert_eq!(None, engine.try_get(get_test_key(2)).unwrap());
+    assert_eq!(Errors::KeyIsEmpty, engine.try_get(Bytes::new()).err().unwrap());
+
+    // 2.其他线程持有活跃文件的写锁时立即返回 WouldBlock
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    std::thread::scope(|s| {
+        let engine = &engine;
+        s.spawn(move || {
+            let _active_file = engine.active_file.write();
+            locked_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        locked_rx.recv().unwrap();
+        assert_eq!(Errors::WouldBlock, engine.try_get(get_test_key(1)).err().unwrap());
+        // 不存在的 key 不需要读取数据文件
+        assert_eq!(None, engine.try_get(get_test_key(2)).unwrap());
+        release_tx.send(()).unwrap();
+    });
+    assert_eq!(Some(get_test_value(1)), engine.try_get(get_test_key(1)).unwrap());
+
+    // 3.删除之后返回 None
+    engine.delete(get_test_key(1)).unwrap();
+    assert_eq!(None, engine.try_get(get_test_key(1)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_skip_identical_writes() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-skip-identical-writes"),
+        data_file_size: 64 * 1024 * 1024,
+        skip_identical_writes: true,
+        inline_value_threshold: Some(64),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.相同的 value 只写入一次
+    let pos1 = engine.put_at(get_test_key(1), get_test_value(1)).unwrap();
+    let write_off = engine.checkpoint().unwrap().write_offset;
+    let pos2 = engine.put_at(get_test_key(1), get_test_value(1)).unwrap();
+    assert_eq!(pos1, pos2);
+    assert_eq!(write_off, engine.checkpoint().unwrap().write_offset);
+
+    // 2.长度相同但内容不同的 value 正常写入
+    let value = get_test_value(1);
+    let mut changed = value.to_vec();
+    changed[0] ^= 1;
+    engine.put(get_test_key(1), Bytes::from(changed.clone())).unwrap();
+    assert!(engine.checkpoint().unwrap().write_offset > write_off);
+    assert_eq!(changed, engine.get(get_test_key(1)).unwrap());
+
+    // 3.保存在 blob 文件中的 value 同样可以跳过
+    let large_value = Bytes::from(vec![7u8; 1024]);
+    engine.put(get_test_key(2), large_value.clone()).unwrap();
+    let write_off = engine.checkpoint().unwrap().write_offset;
+    engine.put(get_test_key(2), large_value.clone()).unwrap();
+    assert_eq!(write_off, engine.checkpoint().unwrap().write_offset);
+
+    // 4.删除之后再次写入相同的 value 不会被跳过
+    engine.delete(get_test_key(2)).unwrap();
+    engine.put(get_test_key(2), large_value.clone()).unwrap();
+    assert_eq!(large_value, engine.get(get_test_key(2)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_close_with_report() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-close-with-report"),
+        data_file_size: 64 * 1024 * 1024,
+        sync_writes: false,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.未 sync 的写入全部计入
+    let mut written = 0;
+    for i in 0..10 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        written += Engine::record_size(&get_test_key(i), &get_test_value(i as usize));
+    }
+    let report = engine.close_with_report().unwrap();
+    assert_eq!(written as u64, report.unsynced_bytes);
+    assert!(report.synced);
+
+    // 2.sync 之后只计入新的写入
+    engine.put(get_test_key(10), get_test_value(10)).unwrap();
+    engine.sync().unwrap();
+    let report = engine.close_with_report().unwrap();
+    assert_eq!(0, report.unsynced_bytes);
+    assert!(!report.synced);
+
+    engine.put(get_test_key(11), get_test_value(11)).unwrap();
+    let report = engine.close_with_report().unwrap();
+    let expected = Engine::record_size(&get_test_key(11), &get_test_value(11));
+    assert_eq!(expected as u64, report.unsynced_bytes);
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(12, engine2.list_keys().unwrap().len());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_orphaned_batches() {
+    use crate::{
+        batch::log_record_key_with_seq,
+        data::log_record::{LogRecord, LogRecordType},
+        db::OrphanedBatch,
+    };
+    use std::io::Write;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-orphaned-batches"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.put(get_test_key(0), get_test_value(0)).unwrap();
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    wb.put(get_test_key(1), get_test_value(1)).unwrap();
+    wb.commit().unwrap();
+    engine.close().unwrap();
+    let seq_no = engine.checkpoint().unwrap().seq_no + 1;
+    std::mem::drop(engine);
+
+    // 写入开始标记和批次中的记录，但是没有结束标记，模拟提交过程中崩溃
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(opts.dir_path.join("000000000.data"))
+        .unwrap();
+    let begin_record = LogRecord {
+        key: log_record_key_with_seq("txn_begin".as_bytes().to_vec(), seq_no),
+        value: Default::default(),
+        rec_type: LogRecordType::TxnBegin,
+        flags: 0,
+    };
+    let mut orphaned_bytes = begin_record.encode().len() as u64;
+    file.write_all(&begin_record.encode()).unwrap();
+    for i in 2..4 {
+        let record = LogRecord {
+            key: log_record_key_with_seq(get_test_key(i).to_vec(), seq_no),
+            value: get_test_value(i as usize).to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        };
+        orphaned_bytes += record.encode().len() as u64;
+        file.write_all(&record.encode()).unwrap();
+    }
+    file.sync_all().unwrap();
+
+    // 1.打开时报告没有完成的批次，批次中的数据不可见
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        vec![OrphanedBatch {
+            seq_no,
+            bytes: orphaned_bytes,
+        }],
+        engine2.open_report().orphaned_batches
+    );
+    assert_eq!(2, engine2.list_keys().unwrap().len());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(2)).err().unwrap());
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+
+    // 2.修复时同样报告没有完成的批次
+    let dest = PathBuf::from("./tmp/bitcask-rs-orphaned-batches-repair");
+    let report = Engine::repair(opts.dir_path.clone(), dest.clone()).unwrap();
+    assert_eq!(1, report.orphaned_batches);
+    assert_eq!(orphaned_bytes, report.orphaned_bytes);
+    assert_eq!(2, report.live_keys);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    std::fs::remove_dir_all(dest).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_fixed_width_layout() {
+    use crate::{
+        data::data_file::get_data_file_name,
+        fio::counting_io::{CountingIO, IOCounters},
+        options::RecordLayout,
+    };
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-fixed-width-layout"),
+        data_file_size: 64 * 1024 * 1024,
+        record_layout: RecordLayout::FixedWidth,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let large_value = Bytes::from(vec![b'v'; 4096]);
+    engine.put(get_test_key(0), large_value.clone()).unwrap();
+    for i in 1..100 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.delete(get_test_key(99)).unwrap();
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    wb.put(get_test_key(100), get_test_value(100)).unwrap();
+    wb.commit().unwrap();
+
+    // 1.记录头部使用固定长度的 key 和 value 长度
+    let mut header = [0u8; 13];
+    std::fs::File::open(get_data_file_name(&opts, 0))
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+        .unwrap();
+    let key_len = u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+    assert_eq!(get_test_key(0).len() + 1, key_len);
+    assert_eq!(4096u64.to_be_bytes(), header[5..13]);
+
+    // 2.value_len 只需要读取头部
+    let counters = IOCounters::default();
+    {
+        let mut active_file = engine.active_file.write();
+        let file_name = get_data_file_name(&opts, 0);
+        active_file.set_io_manager(Box::new(CountingIO::new(file_name, counters.clone()).unwrap()));
+    }
+    assert_eq!(4096, engine.value_len(get_test_key(0)).unwrap());
+    assert!(counters.read_bytes() < 4096);
+    assert_eq!(Bytes::from("vvvv"), engine.get_range(get_test_key(0), 100, 4).unwrap());
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 3.重启时按照目录中保存的布局恢复，忽略选项中的布局
+    let engine2 = Engine::open(Options {
+        record_layout: RecordLayout::Varint,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    assert_eq!(RecordLayout::FixedWidth, engine2.options().record_layout);
+    assert!(engine2.open_report().corrupted_records.is_empty());
+    assert_eq!(100, engine2.list_keys().unwrap().len());
+    assert_eq!(large_value, engine2.get(get_test_key(0)).unwrap());
+    assert_eq!(get_test_value(100), engine2.get(get_test_key(100)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(99)).err().unwrap());
+
+    // 4.合并之后的文件使用同样的布局
+    engine2.merge().unwrap();
+    assert_eq!(get_test_value(50), engine2.get(get_test_key(50)).unwrap());
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(100, engine3.list_keys().unwrap().len());
+    assert_eq!(large_value, engine3.get(get_test_key(0)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_record_size_out_of_bounds() {
+    use crate::options::RecordLayout;
+    use std::io::Write;
+
+    for layout in [RecordLayout::Varint, RecordLayout::FixedWidth] {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-record-size-out-of-bounds"),
+            data_file_size: 64 * 1024 * 1024,
+            record_layout: layout,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3 {
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        let file_size = engine.checkpoint().unwrap().write_offset;
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        // 追加一个 value 长度非常大的头部
+        let mut header = vec![1u8];
+        match layout {
+            RecordLayout::Varint => {
+                prost::encode_length_delimiter(3, &mut header).unwrap();
+                prost::encode_length_delimiter(u32::MAX as usize, &mut header).unwrap();
+            }
+            RecordLayout::FixedWidth => {
+                header.extend_from_slice(&3u32.to_be_bytes());
+                header.extend_from_slice(&u64::MAX.to_be_bytes());
+            }
+        }
+        header.extend_from_slice(b"key");
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(opts.dir_path.join("000000000.data"))
+            .unwrap();
+        file.write_all(&header).unwrap();
+        file.sync_all().unwrap();
+
+        // 1.严格模式下返回错误，而不是分配内存或者越界读取
+        let res1 = Engine::open(opts.clone());
+        assert_eq!(Errors::DataDirectoryCorrupted, res1.err().unwrap());
+
+        // 2.非严格模式下跳过文件剩余的部分
+        let engine2 = Engine::open(Options {
+            recover_mode: RecoverMode::SkipCorrupted,
+            ..opts.clone()
+        })
+        .expect("failed to open engine");
+        let report = engine2.open_report();
+        assert_eq!(1, report.corrupted_records.len());
+        assert_eq!(file_size, report.corrupted_records[0].offset);
+        assert_eq!(header.len(), report.corrupted_records[0].size);
+        for i in 0..3 {
+            assert_eq!(get_test_value(i as usize), engine2.get(get_test_key(i)).unwrap());
+        }
+        engine2.close().unwrap();
+        std::mem::drop(engine2);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
+#[test]
+fn test_engine_preallocate() {
+    use crate::data::data_file::get_data_file_name;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-preallocate"),
+        data_file_size: 64 * 1024,
+        preallocate: true,
+        ..Default::default()
+    };
+    let file_len = |file_id| std::fs::metadata(get_data_file_name(&opts, file_id)).unwrap().len();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(opts.data_file_size, file_len(0));
+
+    // 1.写入的数据从文件的开头开始，而不是预分配空间的末尾
+    for i in 0..100 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.delete(get_test_key(99)).unwrap();
+    let write_off = engine.checkpoint().unwrap().write_offset;
+    assert!(write_off < opts.data_file_size);
+    assert_eq!(opts.data_file_size, file_len(0));
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 2.重启时根据记录的边界恢复，文件末尾的空白空间不影响恢复
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine2.open_report().corrupted_records.is_empty());
+    assert_eq!(write_off, engine2.checkpoint().unwrap().write_offset);
+    assert_eq!(99, engine2.list_keys().unwrap().len());
+    assert_eq!(get_test_value(50), engine2.get(get_test_key(50)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(99)).err().unwrap());
+
+    // 3.写满之后新建的活跃文件同样预分配
+    let large_value = Bytes::from(vec![b'v'; 1024]);
+    for i in 100..200 {
+        engine2.put(get_test_key(i), large_value.clone()).unwrap();
+    }
+    let file_ids = engine2.data_file_ids();
+    assert!(file_ids.len() > 1);
+    for file_id in file_ids.iter() {
+        assert_eq!(opts.data_file_size, file_len(*file_id));
+    }
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+
+    // 4.关闭 preallocate 之后仍然可以正常读写预分配的文件
+    let engine3 = Engine::open(Options {
+        preallocate: false,
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    engine3.put(get_test_key(200), get_test_value(200)).unwrap();
+    engine3.close().unwrap();
+    std::mem::drop(engine3);
+    let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(200, engine4.list_keys().unwrap().len());
+    assert_eq!(large_value, engine4.get(get_test_key(150)).unwrap());
+    assert_eq!(get_test_value(200), engine4.get(get_test_key(200)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_close_drains_in_flight_writes() {
+    use parking_lot::Mutex;
+    use std::sync::mpsc::channel;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-close-drains-in-flight-writes"),
+        data_file_size: 64 * 1024 * 1024,
+        sync_writes: false,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    // 已经返回的写入及其位置
+    let completed = Mutex::new(Vec::new());
+    let (started_tx, started_rx) = channel();
+
+    let (accepted, synced_off) = std::thread::scope(|s| {
+        for t in 0..8 {
+            let (engine, completed, started_tx) = (&engine, &completed, started_tx.clone());
+            s.spawn(move || {
+                for i in 0..200 {
+                    let key = get_test_key(t * 1000 + i);
+                    let pos = engine.put_at(key.clone(), get_test_value(i as usize)).unwrap();
+                    completed.lock().push((key, pos));
+                    if i == 10 {
+                        started_tx.send(()).unwrap();
+                    }
+                }
+            });
+        }
+        for _ in 0..8 {
+            started_rx.recv().unwrap();
+        }
+        // 持有 completed 的锁，之后返回的写入在 close 返回之前无法记录
+        let completed = completed.lock();
+        let report = engine.close_with_report().unwrap();
+        assert!(report.synced);
+        (completed.len(), engine.active_file.read().get_synced_off())
+    });
+    let completed = completed.into_inner();
+    assert_eq!(8 * 200, completed.len());
+    assert_eq!(8 * 200, engine.list_keys().unwrap().len());
+    std::mem::drop(engine);
+
+    // close 之前返回的写入全部已经持久化，截断到持久化的位置之后没有不完整的记录
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(opts.dir_path.join("000000000.data"))
+        .unwrap();
+    file.set_len(synced_off).unwrap();
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine2.open_report().corrupted_records.is_empty());
+    for (key, pos) in completed.iter().take(accepted) {
+        assert!(pos.offset() < synced_off);
+        assert_eq!(Some(*pos), engine2.index.get(key.to_vec()));
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_put_with_flags() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-put-with-flags"),
+        data_file_size: 64 * 1024 * 1024,
+        skip_identical_writes: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    const COMPRESSED: u8 = 0b01;
+
+    // 1.flags 不影响 value 的读写
+    engine.put_with_flags(get_test_key(1), get_test_value(1), COMPRESSED).unwrap();
+    engine.put(get_test_key(2), get_test_value(2)).unwrap();
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    let metadata = engine.metadata(get_test_key(1)).unwrap();
+    assert_eq!(COMPRESSED, metadata.flags);
+    assert_eq!(get_test_value(1).len() as u64, metadata.value_len);
+    assert_eq!(0, engine.metadata(get_test_key(2)).unwrap().flags);
+    assert_eq!(Errors::KeyNotFound, engine.metadata(get_test_key(3)).err().unwrap());
+
+    // 2.value 相同但是 flags 不同时仍然会写入
+    let pos = engine.metadata(get_test_key(2)).unwrap().pos;
+    engine.put_with_flags(get_test_key(2), get_test_value(2), COMPRESSED).unwrap();
+    assert_ne!(pos, engine.metadata(get_test_key(2)).unwrap().pos);
+    let pos = engine.metadata(get_test_key(2)).unwrap().pos;
+    engine.put_with_flags(get_test_key(2), get_test_value(2), COMPRESSED).unwrap();
+    assert_eq!(pos, engine.metadata(get_test_key(2)).unwrap().pos);
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 3.重启以及合并之后 flags 保持不变
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine2.open_report().corrupted_records.is_empty());
+    assert_eq!(COMPRESSED, engine2.metadata(get_test_key(1)).unwrap().flags);
+    engine2.merge().unwrap();
+    assert_eq!(COMPRESSED, engine2.metadata(get_test_key(1)).unwrap().flags);
+    assert_eq!(COMPRESSED, engine2.metadata(get_test_key(2)).unwrap().flags);
+    assert_eq!(get_test_value(2), engine2.get(get_test_key(2)).unwrap());
+
+    // 4.没有 flags 的写入会覆盖之前的 flags
+    engine2.put(get_test_key(1), get_test_value(1)).unwrap();
+    assert_eq!(0, engine2.metadata(get_test_key(1)).unwrap().flags);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_after_merge_with_stale_position() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-get-after-merge-stale-position"),
+        data_file_size: 4 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let mut positions = Vec::new();
+    for i in 0..200 {
+        positions.push(engine.put_at(get_test_key(i), get_test_value(i as usize)).unwrap());
+    }
+    engine.delete(get_test_key(1)).unwrap();
+    engine.merge().unwrap();
+
+    // 1.外部保存的位置所在的文件已经被合并删除
+    assert_eq!(Errors::DataFileNotFound, engine.read_at(&positions[0]).err().unwrap());
+
+    // 2.get 在读取之前位置已经失效时重新从索引中查找
+    let key = get_test_key(0);
+    assert_eq!(get_test_value(0), engine.get_value_resolving(&key, positions[0]).unwrap());
+    assert_eq!(
+        Errors::KeyNotFound,
+        engine.get_value_resolving(&get_test_key(1), positions[1]).err().unwrap()
+    );
+
+    // 3.get 与 merge 并发执行
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..5 {
+                engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+                engine.merge().unwrap();
+            }
+        });
+        for _ in 0..20 {
+            for i in 100..200 {
+                assert_eq!(get_test_value(i as usize), engine.get(get_test_key(i)).unwrap());
+            }
+        }
+    });
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_file_footer() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-file-footer"),
+        data_file_size: 4 * 1024,
+        file_footer: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    assert!(opts.dir_path.join("000000000.footer").is_file());
+
+    // 1.文件没有变化时校验通过
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine2.open_report().footer_mismatches.is_empty());
+    // 找到第一个文件中最后一条记录的位置
+    let last_offset = engine2
+        .raw_iter()
+        .map(|entry| entry.unwrap().pos)
+        .filter(|pos| pos.file_id == 0)
+        .map(|pos| pos.offset)
+        .max()
+        .unwrap();
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+
+    // 2.在记录的边界截断文件，每条记录的 crc 都正确，但是与摘要不一致
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(opts.dir_path.join("000000000.data"))
+        .unwrap();
+    file.set_len(last_offset).unwrap();
+    file.sync_all().unwrap();
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(vec![0], engine3.open_report().footer_mismatches);
+
+    // 3.合并之后重新生成摘要文件
+    engine3.merge().unwrap();
+    engine3.close().unwrap();
+    std::mem::drop(engine3);
+    let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine4.open_report().footer_mismatches.is_empty());
+    assert_eq!(get_test_value(99), engine4.get(get_test_key(99)).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_index_sidecar() {
+    use crate::data::index_sidecar::INDEX_SIDECAR_FILE_NAME;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-index-sidecar"),
+        data_file_size: 16 * 1024,
+        index_sidecar: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    for i in 0..100 {
+        engine.delete(get_test_key(i)).unwrap();
+    }
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    wb.put(get_test_key(2000), get_test_value(2000)).unwrap();
+    wb.delete(get_test_key(100)).unwrap();
+    wb.commit().unwrap();
+    assert!(engine.data_file_ids().len() > 1);
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    let check = |engine: &Engine| {
+        assert_eq!(900, engine.list_keys().unwrap().len());
+        assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(100)).err().unwrap());
+        assert_eq!(get_test_value(500), engine.get(get_test_key(500)).unwrap());
+        assert_eq!(get_test_value(2000), engine.get(get_test_key(2000)).unwrap());
+    };
+
+    // 1.按照索引文件恢复，不需要读取数据文件
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(0, engine2.open_report().scanned_records);
+    check(&engine2);
+    // 恢复之后可以继续写入
+    engine2.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(0, engine3.open_report().scanned_records);
+    assert_eq!(get_test_value(1), engine3.get(get_test_key(1)).unwrap());
+    engine3.delete(get_test_key(1)).unwrap();
+    engine3.close().unwrap();
+    std::mem::drop(engine3);
+
+    // 2.索引文件末尾的部分丢失时只读取之后的记录
+    let sidecar_path = opts.dir_path.join(INDEX_SIDECAR_FILE_NAME);
+    let sidecar_len = std::fs::metadata(&sidecar_path).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&sidecar_path).unwrap();
+    file.set_len(sidecar_len - 10).unwrap();
+    std::mem::drop(file);
+    let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1, engine4.open_report().scanned_records);
+    check(&engine4);
+    engine4.close().unwrap();
+    std::mem::drop(engine4);
+
+    // 3.索引文件损坏时扫描所有的数据文件，并重新生成索引文件
+    let mut buf = std::fs::read(&sidecar_path).unwrap();
+    buf[0] ^= 0xff;
+    std::fs::write(&sidecar_path, buf).unwrap();
+    let engine5 = Engine::open(opts.clone()).expect("failed to open engine");
+    let total_records = engine5.raw_iter().count();
+    assert_eq!(total_records, engine5.open_report().scanned_records);
+    check(&engine5);
+    engine5.close().unwrap();
+    std::mem::drop(engine5);
+    let engine6 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(0, engine6.open_report().scanned_records);
+    check(&engine6);
+
+    // 4.合并之后删除索引文件，下一次启动时重新生成
+    engine6.merge().unwrap();
+    assert!(!sidecar_path.exists());
+    engine6.close().unwrap();
+    std::mem::drop(engine6);
+    let engine7 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine7.open_report().scanned_records > 0);
+    check(&engine7);
+    engine7.close().unwrap();
+    std::mem::drop(engine7);
+    let engine8 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(0, engine8.open_report().scanned_records);
+    check(&engine8);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_relocate() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-relocate"),
+        data_file_size: 16 * 1024,
+        ..Default::default()
+    };
+    let new_dir = PathBuf::from("./tmp/bitcask-rs-relocate-moved");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..500 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    assert!(engine.data_file_ids().len() > 1);
+
+    // 1.目标目录已经存在时失败，数据库保持不变
+    std::fs::create_dir_all(&new_dir).unwrap();
+    let res1 = engine.relocate(new_dir.clone());
+    assert_eq!(Errors::DatabaseAlreadyExists, res1.err().unwrap());
+    std::fs::remove_dir_all(&new_dir).unwrap();
+    assert_eq!(opts.dir_path, engine.options().dir_path);
+
+    // 2.移动之后可以继续读写
+    engine.relocate(new_dir.clone()).unwrap();
+    assert!(!opts.dir_path.exists());
+    assert_eq!(new_dir, engine.options().dir_path);
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    for i in 500..1000 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.delete(get_test_key(2)).unwrap();
+    assert_eq!(999, engine.list_keys().unwrap().len());
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 3.从新的位置重新打开
+    let engine2 = Engine::open(Options {
+        dir_path: new_dir.clone(),
+        ..opts.clone()
+    })
+    .expect("failed to open engine");
+    assert_eq!(999, engine2.list_keys().unwrap().len());
+    assert_eq!(get_test_value(999), engine2.get(get_test_key(999)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(2)).err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(new_dir).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_string() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-get-string"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.字符串写入之后可以原样读出
+    engine.put_string(Bytes::from("name"), "bitcask-kv 数据库").unwrap();
+    engine.put_string(Bytes::from("empty"), "").unwrap();
+    assert_eq!("bitcask-kv 数据库", engine.get_string(Bytes::from("name")).unwrap());
+    assert_eq!("", engine.get_string(Bytes::from("empty")).unwrap());
+
+    // 2.不是合法 UTF-8 的 value 返回错误，get 仍然可以读取
+    let invalid = Bytes::from(vec![0xff, 0xfe, 0x61]);
+    engine.put(Bytes::from("binary"), invalid.clone()).unwrap();
+    let res = engine.get_string(Bytes::from("binary"));
+    assert_eq!(Errors::InvalidUtf8, res.err().unwrap());
+    assert_eq!(invalid, engine.get(Bytes::from("binary")).unwrap());
+
+    // 3.key 不存在时返回 KeyNotFound
+    let res = engine.get_string(Bytes::from("missing"));
+    assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_io_factory() {
+    use crate::fio::IOManager;
+    use std::{collections::HashMap, sync::Arc};
+
+    type MemoryFile = Arc<parking_lot::Mutex<Vec<u8>>>;
+    /// 数据保存在内存中的 IOManager，同一个路径重新打开之后读到相同的数据
+    struct MemoryIO {
+        data: MemoryFile,
+    }
+    impl IOManager for MemoryIO {
+        fn write(&self, buf: &[u8]) -> crate::errors::Result<usize> {
+            self.data.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn write_at(&self, buf: &[u8], offset: u64) -> crate::errors::Result<usize> {
+            let mut data = self.data.lock();
+            let end = offset as usize + buf.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[offset as usize..end].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn read(&self, buf: &mut [u8], offset: u64) -> crate::errors::Result<usize> {
+            let data = self.data.lock();
+            let start = (offset as usize).min(data.len());
+            let n_bytes = buf.len().min(data.len() - start);
+            buf[..n_bytes].copy_from_slice(&data[start..start + n_bytes]);
+            Ok(n_bytes)
+        }
+        fn sync(&self) -> crate::errors::Result<()> {
+            Ok(())
+        }
+        fn truncate(&self, size: u64) -> crate::errors::Result<()> {
+            self.data.lock().truncate(size as usize);
+            Ok(())
+        }
+        fn size(&self) -> crate::errors::Result<u64> {
+            Ok(self.data.lock().len() as u64)
+        }
+    }
+
+    let files: Arc<parking_lot::Mutex<HashMap<PathBuf, MemoryFile>>> =
+        Arc::new(parking_lot::Mutex::new(HashMap::new()));
+    let factory_files = files.clone();
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-io-factory"),
+        data_file_size: 16 * 1024,
+        io_factory: Some(Arc::new(move |file_name: PathBuf| {
+            // 文件列表仍然从本地目录中读取，只在本地创建空文件
+            std::fs::File::create(&file_name).map_err(|_| Errors::FailedToOpenDataFile)?;
+            let data = factory_files.lock().entry(file_name).or_default().clone();
+            Ok(Box::new(MemoryIO { data }) as Box<dyn IOManager>)
+        })),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..500 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.delete(get_test_key(1)).unwrap();
+    assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 数据全部写入了 io_factory 创建的 IOManager，本地文件为空
+    assert!(files.lock().len() > 1);
+    for file_name in files.lock().keys() {
+        assert_eq!(0, std::fs::metadata(file_name).unwrap().len());
+    }
+
+    // 重新打开之后通过 io_factory 读取数据
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(499, engine2.list_keys().unwrap().len());
+    assert_eq!(get_test_value(499), engine2.get(get_test_key(499)).unwrap());
+    assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(1)).err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_sync_interval_writes() {
+    use crate::fio::{
+        counting_io::{CountingIO, IOCounters},
+        IOManager,
+    };
+    use std::sync::Arc;
+
+    let counters = IOCounters::default();
+    let factory_counters = counters.clone();
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-sync-interval-writes"),
+        data_file_size: 64 * 1024 * 1024,
+        sync_writes: false,
+        sync_interval_writes: Some(5),
+        io_factory: Some(Arc::new(move |file_name: PathBuf| {
+            let io_manager = CountingIO::new(file_name, factory_counters.clone())?;
+            Ok(Box::new(io_manager) as Box<dyn IOManager>)
+        })),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let base = counters.syncs();
+
+    // 1.每 5 次写入 sync 一次，删除同样计数
+    for i in 1..=12 {
+        match i % 4 {
+            0 => engine.delete(get_test_key(i - 1)).unwrap(),
+            _ => engine.put(get_test_key(i), get_test_value(i as usize)).unwrap(),
+        }
+        assert_eq!(i as usize / 5, counters.syncs() - base);
+    }
+
+    // 2.批量写入中的记录和标记都计入写入次数
+    let wb = engine
+        .new_write_batch(WriteBatchOptions {
+            sync_writes: false,
+            ..Default::default()
+        })
+        .expect("failed to create write batch");
+    wb.put(get_test_key(100), get_test_value(100)).unwrap();
+    // 开始标记 + 一条记录 + 结束标记，一共 15 条记录
+    wb.commit().unwrap();
+    assert_eq!(3, counters.syncs() - base);
+
+    // 3.sync_interval_writes 不能为 0
+    let res = Engine::open(Options {
+        sync_interval_writes: Some(0),
+        ..opts.clone()
+    });
+    assert_eq!(Errors::InvalidSyncInterval, res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_read_position_out_of_range() {
+    use crate::data::log_record::LogRecordPos;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-position-out-of-range"),
+        data_file_size: 16 * 1024,
+        preallocate: true,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let mut last_pos = engine.put_at(get_test_key(0), get_test_value(0)).unwrap();
+    for i in 1..500 {
+        last_pos = engine.put_at(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    let older_file_id = engine.data_file_ids()[0];
+    assert_ne!(older_file_id, last_pos.file_id);
+
+    // 1.活跃文件写入位置之后是预分配的空间
+    let write_off = engine.active_file.read().get_write_off();
+    let beyond_active = LogRecordPos {
+        file_id: last_pos.file_id,
+        offset: write_off,
+    };
+    let res1 = engine.read_at(&beyond_active);
+    assert_eq!(Errors::InvalidLogRecordPos, res1.err().unwrap());
+
+    // 2.超出旧文件大小的位置
+    let beyond_older = LogRecordPos {
+        file_id: older_file_id,
+        offset: 64 * 1024,
+    };
+    let res2 = engine.read_at(&beyond_older);
+    assert_eq!(Errors::InvalidLogRecordPos, res2.err().unwrap());
+
+    // 3.索引中的位置超出范围时 get 返回错误而不是读取无效的数据
+    engine.index.put(get_test_key(1).to_vec(), beyond_active);
+    let res3 = engine.get(get_test_key(1));
+    assert_eq!(Errors::InvalidLogRecordPos, res3.err().unwrap());
+
+    // 范围内的位置仍然可以读取
+    assert_eq!(get_test_value(499), engine.read_at(&last_pos).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_key_normalizer() {
+    use std::sync::Arc;
+
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-key-normalizer"),
+        data_file_size: 64 * 1024 * 1024,
+        key_normalizer: Some(Arc::new(|key: &[u8]| key.to_ascii_lowercase())),
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.不同大小写的 key 指向同一条数据
+    engine.put(Bytes::from("key"), Bytes::from("value-1")).unwrap();
+    assert_eq!(Bytes::from("value-1"), engine.get(Bytes::from("KEY")).unwrap());
+    engine.put(Bytes::from("Key"), Bytes::from("value-2")).unwrap();
+    assert_eq!(Bytes::from("value-2"), engine.get(Bytes::from("key")).unwrap());
+    assert_eq!(vec![Bytes::from("key")], engine.list_keys().unwrap());
+
+    // 2.批量写入同样转换 key
+    let wb = engine.new_write_batch(WriteBatchOptions::default()).unwrap();
+    wb.put(Bytes::from("OTHER"), Bytes::from("value-3")).unwrap();
+    wb.commit().unwrap();
+    assert_eq!(Bytes::from("value-3"), engine.get(Bytes::from("other")).unwrap());
+
+    // 3.删除之后任何大小写都读不到
+    engine.delete(Bytes::from("KEY")).unwrap();
+    assert_eq!(Errors::KeyNotFound, engine.get(Bytes::from("key")).err().unwrap());
+
+    // 4.重启之后数据文件中保存的是转换之后的 key
+    engine.close().unwrap();
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(vec![Bytes::from("other")], engine2.list_keys().unwrap());
+    assert_eq!(Bytes::from("value-3"), engine2.get(Bytes::from("Other")).unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_load_data_files_with_irrelevant_entries() {
+    let opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-load-irrelevant-entries"),
+        data_file_size: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..10 {
+        engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+    }
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 1.与数据文件无关的文件、目录以及不是 UTF-8 的文件名被跳过，
+    // 名称不是文件 id 的子目录中的 .data 文件同样不属于数据库
+    std::fs::write(opts.dir_path.join("notes.txt"), b"notes").unwrap();
+    std::fs::create_dir(opts.dir_path.join("misc")).unwrap();
+    std::fs::write(opts.dir_path.join("misc").join("other.bin"), b"other").unwrap();
+    std::fs::write(opts.dir_path.join("misc").join("export.data"), b"export").unwrap();
+    std::fs::create_dir(opts.dir_path.join("12345678")).unwrap();
+    std::fs::write(opts.dir_path.join("12345678").join("000000007.data"), b"").unwrap();
+    #[cfg(unix)]
+    let non_utf8 = {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let path = opts.dir_path.join(OsStr::from_bytes(b"\xff\xfe.log"));
+        std::fs::write(&path, b"log").unwrap();
+        path
+    };
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(vec![0], engine2.data_file_ids());
+    for i in 0..10 {
+        assert_eq!(get_test_value(i as usize), engine2.get(get_test_key(i)).unwrap());
+    }
+    engine2.close().unwrap();
+    std::mem::drop(engine2);
+
+    // 2.无法解析 id 的数据文件仍然视为目录损坏
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let path = opts.dir_path.join(OsStr::from_bytes(b"\xff.data"));
+        std::fs::write(&path, b"").unwrap();
+        let res = Engine::open(opts.clone());
+        assert_eq!(Errors::DataDirectoryCorrupted, res.err().unwrap());
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(non_utf8).unwrap();
+    }
+    std::fs::write(opts.dir_path.join("abc.data"), b"").unwrap();
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::DataDirectoryCorrupted, res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_bulk_load() {
+    use crate::data::data_file::get_data_file_name;
+
+    let bulk_opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-bulk-load"),
+        data_file_size: 16 * 1024,
+        ..Default::default()
+    };
+    let put_opts = Options {
+        dir_path: PathBuf::from("./tmp/bitcask-rs-bulk-load-put"),
+        ..bulk_opts.clone()
+    };
+    // 包含重复的 key，后写入的 value 生效
+    let pairs: Vec<(Bytes, Bytes)> = (0..1000)
+        .chain(0..100)
+        .enumerate()
+        .map(|(n, i)| (get_test_key(i), Bytes::from(format!("value-{}", n))))
+        .collect();
+
+    // 1.导入之后所有的 key 都可以读取
+    let engine = Engine::open(bulk_opts.clone()).expect("failed to open engine");
+    engine.bulk_load(pairs.clone().into_iter()).unwrap();
+    assert_eq!(1000, engine.list_keys().unwrap().len());
+    assert_eq!(Bytes::from("value-1000"), engine.get(get_test_key(0)).unwrap());
+    assert_eq!(Bytes::from("value-999"), engine.get(get_test_key(999)).unwrap());
+    let bulk_file_ids = engine.data_file_ids();
+    assert!(bulk_file_ids.len() > 1);
+    engine.close().unwrap();
+    std::mem::drop(engine);
+
+    // 2.数据文件与逐条 put 的结果相同
+    let engine2 = Engine::open(put_opts.clone()).expect("failed to open engine");
+    for (key, value) in pairs.iter() {
+        engine2.put(key.clone(), value.clone()).unwrap();
+    }
+    assert_eq!(bulk_file_ids, engine2.data_file_ids());
+    for file_id in bulk_file_ids {
+        let bulk = std::fs::read(get_data_file_name(&bulk_opts, file_id)).unwrap();
+        let put = std::fs::read(get_data_file_name(&put_opts, file_id)).unwrap();
+        assert_eq!(bulk, put);
+    }
+    engine2.close().unwrap();
+
+    // 3.重启之后数据依然正确
+    let engine3 = Engine::open(bulk_opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let n = if i < 100 { 1000 + i } else { i };
+        let expected = Bytes::from(format!("value-{}", n));
+        assert_eq!(expected, engine3.get(get_test_key(i)).unwrap());
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(bulk_opts.dir_path).expect("failed to remove path");
+    std::fs::remove_dir_all(put_opts.dir_path).expect("failed to remove path");
+}
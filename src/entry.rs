@@ -0,0 +1,137 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use bytes::Bytes;
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::{
+    db::Engine,
+    errors::{Errors, Result},
+};
+
+/// key 锁的数量，不同的 key 可能共用同一把锁
+pub(crate) const KEY_LOCK_STRIPES: usize = 64;
+
+/// 创建 entry 使用的 key 锁
+pub(crate) fn new_key_locks() -> Vec<Mutex<()>> {
+    (0..KEY_LOCK_STRIPES).map(|_| Mutex::new(())).collect()
+}
+
+/// 数据库中一个 key 的读改写入口，存在期间持有 key 锁
+///
+/// 只和其他 entry 互斥，直接调用 put、delete 修改同一个 key 不会等待 key 锁
+pub struct Entry<'a> {
+    engine: &'a Engine,
+    key: Bytes,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl Engine {
+    /// 获取 key 的读改写入口，同一个 key 的 entry 之间串行执行
+    pub fn entry(&self, key: Bytes) -> Entry<'_> {
+        let key = self.normalize_key(key);
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let stripe = hasher.finish() as usize % KEY_LOCK_STRIPES;
+        Entry {
+            engine: self,
+            _guard: self.key_locks[stripe].lock(),
+            key,
+        }
+    }
+}
+
+impl Entry<'_> {
+    pub fn key(&self) -> &Bytes {
+        &self.key
+    }
+    /// key 存在时用 f 根据当前的 value 计算新的 value 并写入
+    pub fn and_modify<F>(self, f: F) -> Result<Self>
+    where
+        F: FnOnce(&[u8]) -> Bytes,
+    {
+        if let Some(value) = self.get()? {
+            self.engine.put(self.key.clone(), f(&value))?;
+        }
+        Ok(self)
+    }
+    /// key 不存在时写入 default，返回 key 当前的 value
+    pub fn or_insert(self, default: Bytes) -> Result<Bytes> {
+        self.or_insert_with(|| default)
+    }
+    /// key 不存在时写入 f 返回的 value，返回 key 当前的 value
+    pub fn or_insert_with<F>(self, f: F) -> Result<Bytes>
+    where
+        F: FnOnce() -> Bytes,
+    {
+        if let Some(value) = self.get()? {
+            return Ok(value);
+        }
+        let value = f();
+        self.engine.put(self.key.clone(), value.clone())?;
+        Ok(value)
+    }
+    fn get(&self) -> Result<Option<Bytes>> {
+        match self.engine.get(self.key.clone()) {
+            Ok(value) => Ok(Some(value)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, thread};
+
+    use crate::options::Options;
+
+    use super::*;
+    #[test]
+    fn test_entry() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-entry"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let append = |value: &[u8]| {
+            let mut new_value = value.to_vec();
+            new_value.push(b'x');
+            Bytes::from(new_value)
+        };
+
+        // 1.key 不存在时写入默认值，存在时修改
+        let key = Bytes::from("entry-key");
+        let entry = engine.entry(key.clone()).and_modify(append).unwrap();
+        assert_eq!(Bytes::from("x"), entry.or_insert(Bytes::from("x")).unwrap());
+        let value = engine
+            .entry(key.clone())
+            .and_modify(append)
+            .unwrap()
+            .or_insert(Bytes::from("x"))
+            .unwrap();
+        assert_eq!(Bytes::from("xx"), value);
+        let value = engine.entry(key.clone()).or_insert_with(|| unreachable!()).unwrap();
+        assert_eq!(Bytes::from("xx"), value);
+
+        // 2.多个线程并发修改同一个 key，修改不会丢失
+        let counter = Bytes::from("entry-counter");
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        engine
+                            .entry(counter.clone())
+                            .and_modify(append)
+                            .unwrap()
+                            .or_insert(Bytes::from("x"))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(400, engine.get(counter).unwrap().len());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+}
@@ -17,12 +17,24 @@ pub enum Errors {
     #[error("key is empty")]
     KeyIsEmpty,
 
+    #[error("key is too large")]
+    KeyTooLarge,
+
     #[error("key not found")]
     KeyNotFound,
 
+    #[error("key already exists")]
+    KeyAlreadyExists,
+
+    #[error("deletes are disabled in append only mode")]
+    DeletesDisabled,
+
     #[error("index update failed")]
     IndexUpdateFailed,
 
+    #[error("index points to a deleted record")]
+    IndexInconsistent,
+
     #[error("data file not found")]
     DataFileNotFound,
 
@@ -31,10 +43,18 @@ pub enum Errors {
 
     #[error("dir file size too small")]
     DirFileSizeTooSmall,
+    #[error("files per dir must be greater than zero")]
+    InvalidFilesPerDir,
+    #[error("sync interval writes must be greater than zero")]
+    InvalidSyncInterval,
     #[error("failed to create database dir")]
     FailedToCreateDataBaseDir,
     #[error("failed to read database dir")]
     FailedToReadDataBaseDir,
+    #[error("database not found")]
+    DatabaseNotFound,
+    #[error("database already exists")]
+    DatabaseAlreadyExists,
     #[error("database dir was corruprted")]
     DataDirectoryCorrupted,
 
@@ -44,7 +64,46 @@ pub enum Errors {
     #[error("invalid log record crc")]
     InvalidLogRecordCrc,
 
+    #[error("invalid log record key")]
+    InvalidLogRecordKey,
+
+    #[error("log record position is out of range")]
+    InvalidLogRecordPos,
+
+    #[error("too many pending transactions during recovery")]
+    TooManyPendingTransactions,
+
+    #[error("invalid value range")]
+    InvalidValueRange,
+
+    #[error("operation would block")]
+    WouldBlock,
+
+    #[error("offset is not on a log record boundary")]
+    InvalidTruncateOffset,
+
      #[error("exceed the max batch num")]
     ExceedMaxBatchNum,
+
+    #[error("merge is in progress")]
+    MergeInProgress,
+
+    #[error("the active file cannot be merged")]
+    InvalidMergeFileId,
+
+    #[error("failed to merge data files")]
+    FailedToMergeDataFiles,
+
+    #[error("failed to relocate database directory")]
+    FailedToRelocateDatabase,
+
+    #[error("failed to repair database")]
+    FailedToRepairDatabase,
+
+    #[error("changes after the sequence number have been discarded")]
+    ChangesTrimmed,
+
+    #[error("value is not valid utf-8")]
+    InvalidUtf8,
 }
 pub type Result<T> = result::Result<T, Errors>;
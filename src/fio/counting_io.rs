@@ -0,0 +1,75 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::errors::Result;
+
+use super::{file_io::FileIO, IOManager};
+
+/// CountingIO 的计数，clone 之后共享同一组计数
+#[derive(Clone, Default)]
+pub(crate) struct IOCounters {
+    reads: Arc<AtomicUsize>,
+    read_bytes: Arc<AtomicUsize>,
+    syncs: Arc<AtomicUsize>,
+    size_calls: Arc<AtomicUsize>,
+}
+
+impl IOCounters {
+    pub(crate) fn reads(&self) -> usize {
+        self.reads.load(Ordering::SeqCst)
+    }
+    pub(crate) fn read_bytes(&self) -> usize {
+        self.read_bytes.load(Ordering::SeqCst)
+    }
+    pub(crate) fn syncs(&self) -> usize {
+        self.syncs.load(Ordering::SeqCst)
+    }
+    pub(crate) fn size_calls(&self) -> usize {
+        self.size_calls.load(Ordering::SeqCst)
+    }
+}
+
+/// 统计读取、sync 以及获取文件大小次数的 IOManager，用于测试
+pub(crate) struct CountingIO {
+    inner: FileIO,
+    counters: IOCounters,
+}
+
+impl CountingIO {
+    pub(crate) fn new(file_name: PathBuf, counters: IOCounters) -> Result<Self> {
+        Ok(CountingIO {
+            inner: FileIO::new(file_name, 3)?,
+            counters,
+        })
+    }
+}
+
+impl IOManager for CountingIO {
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.inner.write(buf)
+    }
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.inner.write_at(buf, offset)
+    }
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.counters.reads.fetch_add(1, Ordering::SeqCst);
+        self.counters.read_bytes.fetch_add(buf.len(), Ordering::SeqCst);
+        self.inner.read(buf, offset)
+    }
+    fn sync(&self) -> Result<()> {
+        self.counters.syncs.fetch_add(1, Ordering::SeqCst);
+        self.inner.sync()
+    }
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.inner.truncate(size)
+    }
+    fn size(&self) -> Result<u64> {
+        self.counters.size_calls.fetch_add(1, Ordering::SeqCst);
+        self.inner.size()
+    }
+}
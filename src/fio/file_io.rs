@@ -3,69 +3,117 @@ use log::error;
 use parking_lot::RwLock;
 use std::{
     fs::{File, OpenOptions},
-    io::{Read, Seek, SeekFrom, Write},
+    io::{self, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt,
     path::PathBuf,
     sync::Arc,
 };
 
-use super::IOManager;
-pub struct FileIO {
-    fd: Arc<RwLock<File>>,
+use super::{retry_io, IOManager};
+
+/// FileIO 对文件的底层操作，测试时可以替换为模拟的文件
+pub trait FileOps: Send + Sync + 'static {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+    /// 写入到文件的末尾
+    fn append(&mut self, buf: &[u8]) -> io::Result<usize>;
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+    fn set_len(&self, size: u64) -> io::Result<()>;
+    fn sync_all(&self) -> io::Result<()>;
+    fn size(&self) -> io::Result<u64>;
+}
+
+impl FileOps for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        FileExt::read_at(self, buf, offset)
+    }
+    fn append(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.seek(SeekFrom::End(0))?;
+        self.write(buf)
+    }
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        FileExt::write_all_at(self, buf, offset)
+    }
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        File::set_len(self, size)
+    }
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+pub struct FileIO<F = File> {
+    fd: Arc<RwLock<F>>,
+    /// WouldBlock 时的最大重试次数
+    io_retries: u32,
+}
+impl<F: FileOps> FileIO<F> {
+    /// 使用已经打开的文件创建 FileIO
+    pub fn from_file(file: F, io_retries: u32) -> Self {
+        FileIO {
+            fd: Arc::new(RwLock::new(file)),
+            io_retries,
+        }
+    }
 }
 impl FileIO {
-    pub fn new(file_name: PathBuf) -> Result<Self> {
+    pub fn new(file_name: PathBuf, io_retries: u32) -> Result<Self> {
         match OpenOptions::new()
             .create(true)
             .read(true)
             .write(true)
-            .append(true)
+            .truncate(false)
             .open(file_name)
         {
-            Ok(file) => {
-                return Ok(FileIO {
-                    fd: Arc::new(RwLock::new(file)),
-                });
-            }
+            Ok(file) => Ok(FileIO::from_file(file, io_retries)),
             Err(e) => {
                 error!("Failed to open data file: {}", e);
-                return Err(Errors::FailedToOpenDataFile);
+                Err(Errors::FailedToOpenDataFile)
             }
         }
     }
 }
 
-impl IOManager for FileIO {
+impl<F: FileOps> IOManager for FileIO<F> {
     fn read(&self, buf: &mut [u8], offset: u64) -> crate::errors::Result<usize> {
+        // 使用 pread 读取，不会修改共享的文件偏移，多个线程可以并发读取
         let read_guard = self.fd.read();
-        // match read_guard.read_at(buf,offset){
-        //     Ok(n)=>return Ok(n),
-        //     Err(e)=>{
-        //         error!("read from datat file err:{}",e);
-        //     return Err(Errors::FailedToReadFromDataFile);
-        //     }
-        // };
-        let mut file = read_guard
-            .try_clone()
-            .map_err(|_| Errors::FailedToReadFromDataFile)?;
-
-        if let Err(e) = file.seek(SeekFrom::Start(offset)) {
-            error!("Failed to seek to offset {}: {}", offset, e);
-            return Err(Errors::FailedToReadFromDataFile);
+        let mut n_bytes = 0;
+        while n_bytes < buf.len() {
+            let res = retry_io(self.io_retries, || {
+                read_guard.read_at(&mut buf[n_bytes..], offset + n_bytes as u64)
+            });
+            match res {
+                Ok(0) => break,
+                Ok(n) => n_bytes += n,
+                Err(e) => {
+                    error!("Read from data file error: {}", e);
+                    return Err(Errors::FailedToReadFromDataFile);
+                }
+            }
         }
+        Ok(n_bytes)
+    }
 
-        match file.read(buf) {
+    fn write(&self, buf: &[u8]) -> crate::errors::Result<usize> {
+        // 文件没有使用 append 模式打开，否则 write_at 也会写到文件末尾
+        let mut write_guard = self.fd.write();
+        let res = retry_io(self.io_retries, || write_guard.append(buf));
+        match res {
             Ok(n) => Ok(n),
             Err(e) => {
-                error!("Read from data file error: {}", e);
-                Err(Errors::FailedToReadFromDataFile)
+                error!("Writer to data file error: {}", e);
+                Err(Errors::FailedToWriteToDataFile)
             }
         }
     }
 
-    fn write(&self, buf: &[u8]) -> crate::errors::Result<usize> {
-        let mut write_guard = self.fd.write();
-        match write_guard.write(buf) {
-            Ok(n) => Ok(n),
+    fn write_at(&self, buf: &[u8], offset: u64) -> crate::errors::Result<usize> {
+        let write_guard = self.fd.write();
+        match retry_io(self.io_retries, || write_guard.write_all_at(buf, offset)) {
+            Ok(_) => Ok(buf.len()),
             Err(e) => {
                 error!("Writer to data file error: {}", e);
                 Err(Errors::FailedToWriteToDataFile)
@@ -73,9 +121,20 @@ impl IOManager for FileIO {
         }
     }
 
+    fn truncate(&self, size: u64) -> crate::errors::Result<()> {
+        let write_guard = self.fd.write();
+        match retry_io(self.io_retries, || write_guard.set_len(size)) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Failed to truncate data file: {}", e);
+                Err(Errors::FailedToWriteToDataFile)
+            }
+        }
+    }
+
     fn sync(&self) -> crate::errors::Result<()> {
         let read_guard = self.fd.read();
-        match read_guard.sync_all() {
+        match retry_io(self.io_retries, || read_guard.sync_all()) {
             Ok(_) => Ok(()),
             Err(e) => {
                 error!("Failed to sync data file: {}", e);
@@ -83,18 +142,154 @@ impl IOManager for FileIO {
             }
         }
     }
+
+    fn size(&self) -> crate::errors::Result<u64> {
+        let read_guard = self.fd.read();
+        match retry_io(self.io_retries, || read_guard.size()) {
+            Ok(size) => Ok(size),
+            Err(e) => {
+                error!("Failed to get data file size: {}", e);
+                Err(Errors::FailedToReadFromDataFile)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs;
+    use std::{
+        fs,
+        io::ErrorKind,
+        sync::atomic::{AtomicU32, Ordering},
+    };
 
     use super::*;
 
+    /// 前 failures 次操作返回指定错误的文件
+    struct FlakyFile {
+        inner: File,
+        kind: ErrorKind,
+        failures: AtomicU32,
+        calls: Arc<AtomicU32>,
+    }
+    impl FlakyFile {
+        fn check(&self) -> io::Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let remaining = self.failures.load(Ordering::SeqCst);
+            if remaining > 0 {
+                self.failures.store(remaining - 1, Ordering::SeqCst);
+                return Err(io::Error::from(self.kind));
+            }
+            Ok(())
+        }
+    }
+    impl FileOps for FlakyFile {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.check()?;
+            FileOps::read_at(&self.inner, buf, offset)
+        }
+        fn append(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.check()?;
+            self.inner.append(buf)
+        }
+        fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+            self.check()?;
+            FileOps::write_all_at(&self.inner, buf, offset)
+        }
+        fn set_len(&self, size: u64) -> io::Result<()> {
+            self.check()?;
+            FileOps::set_len(&self.inner, size)
+        }
+        fn sync_all(&self) -> io::Result<()> {
+            self.check()?;
+            FileOps::sync_all(&self.inner)
+        }
+        fn size(&self) -> io::Result<u64> {
+            self.check()?;
+            self.inner.size()
+        }
+    }
+
+    /// 创建一个前 failures 次操作失败的 IOManager，同时返回操作次数
+    fn flaky_io(
+        path: &PathBuf,
+        kind: ErrorKind,
+        failures: u32,
+        io_retries: u32,
+    ) -> (Box<dyn IOManager>, Arc<AtomicU32>) {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        let calls = Arc::new(AtomicU32::new(0));
+        let flaky_file = FlakyFile {
+            inner: file,
+            kind,
+            failures: AtomicU32::new(failures),
+            calls: calls.clone(),
+        };
+        (Box::new(FileIO::from_file(flaky_file, io_retries)), calls)
+    }
+
+    #[test]
+    fn test_file_io_retry_interrupted() {
+        let path = PathBuf::from("./tmp/retry-interrupted.data");
+        fs::create_dir_all("./tmp").unwrap();
+
+        // Interrupted 不受 io_retries 限制，总是重试
+        let (io, calls) = flaky_io(&path, ErrorKind::Interrupted, 1, 0);
+        assert_eq!(8, io.write("key-sang".as_bytes()).unwrap());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        let (io, calls) = flaky_io(&path, ErrorKind::Interrupted, 1, 0);
+        io.write("key-sang".as_bytes()).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(8, io.read(&mut buf, 0).unwrap());
+        assert_eq!("key-sang".as_bytes(), buf);
+        io.sync().unwrap();
+        assert_eq!(8, io.size().unwrap());
+        assert_eq!(5, calls.load(Ordering::SeqCst));
+
+        fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_file_io_retry_would_block() {
+        let path = PathBuf::from("./tmp/retry-would-block.data");
+        fs::create_dir_all("./tmp").unwrap();
+
+        // 重试次数足够时最终成功
+        let (io, calls) = flaky_io(&path, ErrorKind::WouldBlock, 2, 3);
+        assert_eq!(8, io.write("key-sang".as_bytes()).unwrap());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // 超过重试次数后返回错误
+        let (io, calls) = flaky_io(&path, ErrorKind::WouldBlock, 5, 1);
+        let res = io.sync();
+        assert_eq!(Errors::FailedToSyncDataFile, res.err().unwrap());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_file_io_non_transient_error() {
+        let path = PathBuf::from("./tmp/non-transient.data");
+        fs::create_dir_all("./tmp").unwrap();
+
+        let (io, calls) = flaky_io(&path, ErrorKind::PermissionDenied, 1, 3);
+        let mut buf = [0u8; 8];
+        let res = io.read(&mut buf, 0);
+        assert_eq!(Errors::FailedToReadFromDataFile, res.err().unwrap());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_file_io_write() {
         let path = PathBuf::from("./tmp/a.data");
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), 0);
         assert!(fio_res.is_ok());
 
         let fio = fio_res.ok().unwrap();
@@ -111,7 +306,7 @@ mod tests {
     #[test]
     fn test_file_to_read() {
         let path = PathBuf::from("./tmp/b.data");
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), 0);
         assert!(fio_res.is_ok());
 
         let fio = fio_res.ok().unwrap();
@@ -138,7 +333,7 @@ mod tests {
     #[test]
     fn test_file_io_sync() {
         let path = PathBuf::from("./tmp/c.data");
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), 0);
         assert!(fio_res.is_ok());
 
         let fio = fio_res.ok().unwrap();
@@ -155,4 +350,27 @@ mod tests {
         let res3 = fs::remove_file(path.clone());
         assert!(res3.is_ok());
     }
+    #[test]
+    fn test_file_io_write_at() {
+        let path = PathBuf::from("./tmp/d.data");
+        let fio = FileIO::new(path.clone(), 0).unwrap();
+        fio.truncate(32).unwrap();
+
+        // 写入到指定的位置，而不是文件末尾
+        assert_eq!(8, fio.write_at("key-sang".as_bytes(), 0).unwrap());
+        assert_eq!(7, fio.write_at("key-xia".as_bytes(), 8).unwrap());
+        assert_eq!(32, fio.size().unwrap());
+        let mut buf = [0u8; 15];
+        fio.read(&mut buf, 0).unwrap();
+        assert_eq!("key-sangkey-xia".as_bytes(), buf);
+
+        // write 仍然追加到文件末尾
+        fio.write("end".as_bytes()).unwrap();
+        assert_eq!(35, fio.size().unwrap());
+        let mut buf = [0u8; 3];
+        fio.read(&mut buf, 32).unwrap();
+        assert_eq!("end".as_bytes(), buf);
+
+        fs::remove_file(path).unwrap();
+    }
 }
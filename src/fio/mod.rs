@@ -1,15 +1,55 @@
 pub mod file_io;
-use std::path::PathBuf;
+#[cfg(test)]
+pub(crate) mod counting_io;
+use std::{
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
 use file_io::FileIO;
 
-use crate::errors::Result;
+use crate::{errors::Result, options::Options};
 pub trait IOManager: Sync + Send {
+    /// 追加写入到文件的末尾
     fn write(&self, buf: &[u8]) -> Result<usize>;
+    /// 写入到指定的位置，用于预分配空间的文件
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize>;
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
     fn sync(&self) -> Result<()>;
+    /// 将文件截断到指定的长度
+    fn truncate(&self, size: u64) -> Result<()>;
+    /// 文件当前的大小
+    fn size(&self) -> Result<u64>;
 }
 
-pub fn new_io_manager(file_name: PathBuf) -> Result<impl IOManager> {
-    FileIO::new(file_name)
+/// 根据文件路径创建 IOManager，用于替换默认的本地文件实现
+pub type IOFactory = Arc<dyn Fn(PathBuf) -> Result<Box<dyn IOManager>> + Send + Sync>;
+
+/// 设置了 io_factory 时使用 io_factory 创建，否则使用本地文件
+pub fn new_io_manager(file_name: PathBuf, options: &Options) -> Result<Box<dyn IOManager>> {
+    match &options.io_factory {
+        Some(io_factory) => io_factory(file_name),
+        None => Ok(Box::new(FileIO::new(file_name, options.io_retries)?)),
+    }
+}
+
+/// 对临时性的 IO 错误进行重试
+///
+/// Interrupted 总是立即重试，WouldBlock 最多重试 io_retries 次，每次等待的时间翻倍，
+/// 其他错误直接返回
+pub(crate) fn retry_io<T>(io_retries: u32, mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempts = 0;
+    loop {
+        match f() {
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock && attempts < io_retries => {
+                thread::sleep(Duration::from_millis(1 << attempts.min(10)));
+                attempts += 1;
+            }
+            res => return res,
+        }
+    }
 }
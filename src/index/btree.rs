@@ -1,13 +1,13 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
 
 use crate::{data::log_record::LogRecordPos, errors::Result, options::IteratorOptions};
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use super::{IndexIterator, Indexer};
+use super::{is_empty_range, IndexIterator, IndexKey, Indexer};
 
 pub struct Btree {
-    tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+    tree: Arc<RwLock<BTreeMap<IndexKey, LogRecordPos>>>,
 }
 impl Btree {
     pub fn new() -> Self {
@@ -19,23 +19,39 @@ impl Btree {
 impl Indexer for Btree {
     fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool {
         let mut write_guard = self.tree.write();
-        write_guard.insert(key, pos);
+        write_guard.insert(IndexKey::from(key), pos);
         true
     }
+    fn put_batch(&self, items: Vec<(Vec<u8>, LogRecordPos)>) {
+        let mut write_guard = self.tree.write();
+        for (key, pos) in items {
+            write_guard.insert(IndexKey::from(key), pos);
+        }
+    }
+    fn put_if_pos_matches(&self, key: Vec<u8>, expected: LogRecordPos, new: LogRecordPos) -> bool {
+        let mut write_guard = self.tree.write();
+        match write_guard.get_mut(key.as_slice()) {
+            Some(pos) if *pos == expected => {
+                *pos = new;
+                true
+            }
+            _ => false,
+        }
+    }
     fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
         let read_guard = self.tree.read();
-        read_guard.get(&key).copied()
+        read_guard.get(key.as_slice()).copied()
     }
     fn delete(&self, key: Vec<u8>) -> bool {
         let mut write_guard = self.tree.write();
-        let remove_res = write_guard.remove(&key);
+        let remove_res = write_guard.remove(key.as_slice());
         remove_res.is_some()
     }
     fn list_keys(&self) -> Result<Vec<Bytes>> {
         let read_guard = self.tree.read();
         let mut keys = Vec::with_capacity(read_guard.len());
         for (k, _) in read_guard.iter() {
-            keys.push(Bytes::copy_from_slice(&k));
+            keys.push(Bytes::copy_from_slice(k));
         }
         Ok(keys)
     }
@@ -44,22 +60,57 @@ impl Indexer for Btree {
         let mut items = Vec::with_capacity(read_guard.len());
 
         for (key, val) in read_guard.iter() {
-            items.push((key.clone(), val.clone()));
+            items.push((key.to_vec(), *val));
         }
+        Box::new(BtreeIterator::new(items, options))
+    }
+    fn shrink(&self) {
+        // 重新构建一棵紧凑的树
+        let mut write_guard = self.tree.write();
+        let tree = std::mem::take(&mut *write_guard);
+        *write_guard = tree.into_iter().collect();
+    }
+    fn range(&self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Vec<(Vec<u8>, LogRecordPos)> {
+        if is_empty_range(&start, &end) {
+            return Vec::new();
+        }
+        let range = (start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice));
+        let read_guard = self.tree.read();
+        read_guard
+            .range::<[u8], _>(range)
+            .map(|(key, pos)| (key.to_vec(), *pos))
+            .collect()
+    }
+}
+pub struct BtreeIterator {
+    items: Vec<(Vec<u8>, LogRecordPos)>,
+    curr_index: usize,
+    options: IteratorOptions,
+}
+impl BtreeIterator {
+    /// items 需要按照 key 升序排列
+    pub(crate) fn new(mut items: Vec<(Vec<u8>, LogRecordPos)>, options: IteratorOptions) -> Self {
         if options.reverse {
             items.reverse();
         }
-        Box::new(BtreeIterator {
+        // seek 使用二分查找，顺序与 reverse 不一致时结果是错误的
+        debug_assert!(
+            is_sorted_for_seek(&items, options.reverse),
+            "iterator items are not sorted by key"
+        );
+        Self {
             items,
             curr_index: 0,
             options,
-        })
+        }
     }
 }
-pub struct BtreeIterator {
-    items: Vec<(Vec<u8>, LogRecordPos)>,
-    curr_index: usize,
-    options: IteratorOptions,
+/// 检查 items 是否按照 seek 使用的比较方式严格有序
+fn is_sorted_for_seek(items: &[(Vec<u8>, LogRecordPos)], reverse: bool) -> bool {
+    items.windows(2).all(|w| match reverse {
+        true => w[0].0 > w[1].0,
+        false => w[0].0 < w[1].0,
+    })
 }
 impl IndexIterator for BtreeIterator {
     fn rewind(&mut self) {
@@ -86,7 +137,10 @@ impl IndexIterator for BtreeIterator {
         while let Some(item) = self.items.get(self.curr_index) {
             self.curr_index += 1;
             let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
+            let suffix = &self.options.suffix;
+            if (prefix.is_empty() || item.0.starts_with(prefix))
+                && (suffix.is_empty() || item.0.ends_with(suffix))
+            {
                 return Some((&item.0, &item.1));
             }
         }
@@ -119,6 +173,38 @@ mod tests {
         assert_eq!(res2, true);
     }
     #[test]
+    fn test_btree_put_batch() {
+        let bt = Btree::new();
+        let pos = |file_id, offset| LogRecordPos { file_id, offset };
+        bt.put("a".as_bytes().to_vec(), pos(1, 0));
+        bt.put_batch(vec![
+            ("b".as_bytes().to_vec(), pos(1, 10)),
+            ("a".as_bytes().to_vec(), pos(1, 20)),
+            ("b".as_bytes().to_vec(), pos(1, 30)),
+        ]);
+        assert_eq!(Some(pos(1, 20)), bt.get("a".as_bytes().to_vec()));
+        assert_eq!(Some(pos(1, 30)), bt.get("b".as_bytes().to_vec()));
+    }
+    #[test]
+    fn test_btree_put_if_pos_matches() {
+        let bt = Btree::new();
+        let pos = |file_id, offset| LogRecordPos { file_id, offset };
+        let key = "sang".as_bytes().to_vec();
+
+        // key 不存在时不会写入
+        assert!(!bt.put_if_pos_matches(key.clone(), pos(1, 10), pos(2, 0)));
+        assert!(bt.get(key.clone()).is_none());
+
+        bt.put(key.clone(), pos(1, 10));
+        assert!(bt.put_if_pos_matches(key.clone(), pos(1, 10), pos(2, 0)));
+        assert_eq!(Some(pos(2, 0)), bt.get(key.clone()));
+
+        // 位置已经被修改时不会覆盖
+        bt.put(key.clone(), pos(3, 20));
+        assert!(!bt.put_if_pos_matches(key.clone(), pos(2, 0), pos(4, 0)));
+        assert_eq!(Some(pos(3, 20)), bt.get(key));
+    }
+    #[test]
     fn test_btree_get() {
         let bt = Btree::new();
         let res1 = bt.put(
@@ -251,4 +337,50 @@ mod tests {
         println!("{:?}", res5);
         assert!(res5.is_none());
     }
+    #[test]
+    fn test_btree_iterator_items_order() {
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+        };
+        let items: Vec<(Vec<u8>, LogRecordPos)> =
+            ["1", "2", "3"].iter().map(|k| (k.as_bytes().to_vec(), pos)).collect();
+        assert!(is_sorted_for_seek(&items, false));
+        assert!(!is_sorted_for_seek(&items, true));
+
+        let mut reversed = items.clone();
+        reversed.reverse();
+        assert!(is_sorted_for_seek(&reversed, true));
+        assert!(!is_sorted_for_seek(&reversed, false));
+
+        // 升序的 items 在 reverse 时可以正常 seek
+        let mut iter = BtreeIterator::new(
+            items,
+            IteratorOptions {
+                reverse: true,
+                ..Default::default()
+            },
+        );
+        iter.seek("2".as_bytes().to_vec());
+        assert_eq!("2".as_bytes(), iter.next().unwrap().0.as_slice());
+        assert_eq!("1".as_bytes(), iter.next().unwrap().0.as_slice());
+    }
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "iterator items are not sorted by key")]
+    fn test_btree_iterator_mis_sorted_items() {
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+        };
+        // 已经倒序的 items 再次按照 reverse 构建时顺序与比较方式不一致
+        let items = ["3", "2", "1"].iter().map(|k| (k.as_bytes().to_vec(), pos)).collect();
+        BtreeIterator::new(
+            items,
+            IteratorOptions {
+                reverse: true,
+                ..Default::default()
+            },
+        );
+    }
 }
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+use crate::{data::log_record::LogRecordPos, errors::Result, options::IteratorOptions};
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use super::{btree::BtreeIterator, IndexIterator, IndexKey, Indexer};
+
+/// 基于 HashMap 的索引，put/get/delete 都是 O(1)
+///
+/// list_keys 返回的 key 是无序的，iterator 会先对快照排序，开销为 O(nlogn)
+pub struct HashMapIndex {
+    map: Arc<RwLock<HashMap<IndexKey, LogRecordPos>>>,
+}
+impl HashMapIndex {
+    pub fn new() -> Self {
+        Self {
+            map: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+impl Indexer for HashMapIndex {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool {
+        let mut write_guard = self.map.write();
+        write_guard.insert(IndexKey::from(key), pos);
+        true
+    }
+    fn put_batch(&self, items: Vec<(Vec<u8>, LogRecordPos)>) {
+        let mut write_guard = self.map.write();
+        write_guard.reserve(items.len());
+        for (key, pos) in items {
+            write_guard.insert(IndexKey::from(key), pos);
+        }
+    }
+    fn put_if_pos_matches(&self, key: Vec<u8>, expected: LogRecordPos, new: LogRecordPos) -> bool {
+        let mut write_guard = self.map.write();
+        match write_guard.get_mut(key.as_slice()) {
+            Some(pos) if *pos == expected => {
+                *pos = new;
+                true
+            }
+            _ => false,
+        }
+    }
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+        let read_guard = self.map.read();
+        read_guard.get(key.as_slice()).copied()
+    }
+    fn delete(&self, key: Vec<u8>) -> bool {
+        let mut write_guard = self.map.write();
+        let remove_res = write_guard.remove(key.as_slice());
+        remove_res.is_some()
+    }
+    fn list_keys(&self) -> Result<Vec<Bytes>> {
+        let read_guard = self.map.read();
+        let mut keys = Vec::with_capacity(read_guard.len());
+        for k in read_guard.keys() {
+            keys.push(Bytes::copy_from_slice(k));
+        }
+        Ok(keys)
+    }
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        let read_guard = self.map.read();
+        let mut items = Vec::with_capacity(read_guard.len());
+
+        for (key, val) in read_guard.iter() {
+            items.push((key.to_vec(), *val));
+        }
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        Box::new(BtreeIterator::new(items, options))
+    }
+    fn shrink(&self) {
+        let mut write_guard = self.map.write();
+        write_guard.shrink_to_fit();
+    }
+    fn range(&self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Vec<(Vec<u8>, LogRecordPos)> {
+        let range = (start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice));
+        let read_guard = self.map.read();
+        let mut items: Vec<(Vec<u8>, LogRecordPos)> = read_guard
+            .iter()
+            .filter(|(key, _)| RangeBounds::<[u8]>::contains(&range, key.as_slice()))
+            .map(|(key, pos)| (key.to_vec(), *pos))
+            .collect();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_hashmap_put_get_delete() {
+        let hm = HashMapIndex::new();
+        let res1 = hm.put(
+            "xia".as_bytes().to_vec(),
+            LogRecordPos {
+                file_id: 1,
+                offset: 10,
+            },
+        );
+        assert!(res1);
+        let res2 = hm.put(
+            "xia".as_bytes().to_vec(),
+            LogRecordPos {
+                file_id: 2,
+                offset: 20,
+            },
+        );
+        assert!(res2);
+
+        let pos1 = hm.get("xia".as_bytes().to_vec());
+        assert!(pos1.is_some());
+        assert_eq!(pos1.unwrap().file_id, 2);
+        assert_eq!(pos1.unwrap().offset, 20);
+        assert!(hm.get("sang".as_bytes().to_vec()).is_none());
+
+        let del1 = hm.delete("xia".as_bytes().to_vec());
+        assert!(del1);
+        let del2 = hm.delete("xia".as_bytes().to_vec());
+        assert!(!del2);
+        assert!(hm.get("xia".as_bytes().to_vec()).is_none());
+    }
+    #[test]
+    fn test_hashmap_put_if_pos_matches() {
+        let hm = HashMapIndex::new();
+        let pos = |file_id, offset| LogRecordPos { file_id, offset };
+        let key = "xia".as_bytes().to_vec();
+
+        hm.put(key.clone(), pos(1, 10));
+        assert!(hm.put_if_pos_matches(key.clone(), pos(1, 10), pos(2, 0)));
+        assert!(!hm.put_if_pos_matches(key.clone(), pos(1, 10), pos(3, 0)));
+        assert_eq!(Some(pos(2, 0)), hm.get(key));
+    }
+    #[test]
+    fn test_hashmap_iterator() {
+        let hm = HashMapIndex::new();
+        for key in ["3", "1", "2"] {
+            hm.put(
+                key.as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 10,
+                },
+            );
+        }
+        assert_eq!(3, hm.list_keys().unwrap().len());
+
+        let mut iter = hm.iterator(IteratorOptions::default());
+        iter.seek("2".as_bytes().to_vec());
+        assert_eq!("2".as_bytes(), iter.next().unwrap().0.as_slice());
+        assert_eq!("3".as_bytes(), iter.next().unwrap().0.as_slice());
+        assert!(iter.next().is_none());
+    }
+}
@@ -1,4 +1,8 @@
 pub mod btree;
+pub mod hashmap;
+pub mod value_prefix;
+
+use std::ops::Bound;
 
 use bytes::Bytes;
 
@@ -8,9 +12,21 @@ use crate::{
     options::{IndexType, IteratorOptions},
 };
 
+/// 索引中保存的 key，开启 small-keys 之后不超过 24 字节的 key 不会分配堆内存
+///
+/// small-keys 只影响索引内部保存的 key，Indexer 的接口和 LogRecord 仍然使用 Vec<u8>
+#[cfg(feature = "small-keys")]
+pub(crate) type IndexKey = smallvec::SmallVec<[u8; 24]>;
+#[cfg(not(feature = "small-keys"))]
+pub(crate) type IndexKey = Vec<u8>;
+
 pub trait Indexer: Sync + Send {
     /// 实现put方法
     fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool;
+    /// 一次写入多个位置，只获取一次写锁，同一个 key 出现多次时后面的位置生效
+    fn put_batch(&self, items: Vec<(Vec<u8>, LogRecordPos)>);
+    /// 只有当前位置等于 expected 时才更新为 new，返回是否更新成功
+    fn put_if_pos_matches(&self, key: Vec<u8>, expected: LogRecordPos, new: LogRecordPos) -> bool;
     /// 实现get方法
     fn get(&self, key: Vec<u8>) -> Option<LogRecordPos>;
     /// 实现delete方法
@@ -18,15 +34,72 @@ pub trait Indexer: Sync + Send {
     fn list_keys(&self) -> Result<Vec<Bytes>>;
     /// 实现iterator方法
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
+    /// 释放索引中多余的内存
+    fn shrink(&self);
+    /// 按照 key 升序返回位于 start 和 end 之间的数据
+    fn range(&self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Vec<(Vec<u8>, LogRecordPos)>;
 }
-pub fn new_index(index_type: IndexType) -> impl Indexer {
+pub fn new_index(index_type: IndexType) -> Box<dyn Indexer> {
     match index_type {
-        IndexType::BTree => btree::Btree::new(),
+        IndexType::BTree => Box::new(btree::Btree::new()),
+        IndexType::HashMap => Box::new(hashmap::HashMapIndex::new()),
         IndexType::SkipList => todo!(),
     }
 }
+/// 判断区间是否为空，BTreeMap::range 在 start 大于 end 或者两端相等且都不包含时会 panic
+pub(crate) fn is_empty_range(start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e))
+        | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+        _ => false,
+    }
+}
 pub trait IndexIterator: Sync + Send {
     fn rewind(&mut self);
     fn seek(&mut self, key: Vec<u8>);
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_index_short_and_long_keys() {
+        let short_key = "sang".as_bytes().to_vec();
+        let long_key = vec![b'k'; 100];
+        let pos = |offset| LogRecordPos { file_id: 1, offset };
+
+        // 开启 small-keys 时短 key 保存在栈上，长 key 仍然分配在堆上
+        #[cfg(feature = "small-keys")]
+        {
+            assert!(!IndexKey::from(short_key.clone()).spilled());
+            assert!(IndexKey::from(long_key.clone()).spilled());
+        }
+
+        for index_type in [IndexType::BTree, IndexType::HashMap] {
+            let index = new_index(index_type);
+            assert!(index.put(short_key.clone(), pos(10)));
+            assert!(index.put(long_key.clone(), pos(20)));
+            assert_eq!(Some(pos(10)), index.get(short_key.clone()));
+            assert_eq!(Some(pos(20)), index.get(long_key.clone()));
+            assert!(index.put_if_pos_matches(long_key.clone(), pos(20), pos(30)));
+            assert_eq!(Some(pos(30)), index.get(long_key.clone()));
+
+            let mut keys = index.list_keys().unwrap();
+            keys.sort();
+            assert_eq!(vec![Bytes::from(long_key.clone()), Bytes::from(short_key.clone())], keys);
+            let items = index.range(Bound::Unbounded, Bound::Excluded(short_key.clone()));
+            assert_eq!(vec![(long_key.clone(), pos(30))], items);
+            let mut iter = index.iterator(IteratorOptions::default());
+            iter.seek(short_key.clone());
+            assert_eq!(Some((&short_key, &pos(10))), iter.next());
+
+            assert!(index.delete(short_key.clone()));
+            assert!(index.delete(long_key.clone()));
+            assert!(index.get(short_key.clone()).is_none());
+            assert!(index.list_keys().unwrap().is_empty());
+        }
+    }
+}
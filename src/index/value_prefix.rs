@@ -0,0 +1,101 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+/// value 前缀到 key 的二级索引
+pub struct ValuePrefixIndex {
+    prefix_len: usize,
+    inner: RwLock<ValuePrefixMap>,
+}
+
+#[derive(Default)]
+struct ValuePrefixMap {
+    /// value 前缀 -> 拥有该前缀的 key
+    keys: BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    /// key -> 当前 value 的前缀，覆盖写入时用来删除旧的前缀
+    prefixes: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl ValuePrefixIndex {
+    pub fn new(prefix_len: usize) -> Self {
+        Self {
+            prefix_len,
+            inner: RwLock::new(ValuePrefixMap::default()),
+        }
+    }
+    pub fn prefix_len(&self) -> usize {
+        self.prefix_len
+    }
+    pub fn put(&self, key: &[u8], value: &[u8]) {
+        let prefix = value[..value.len().min(self.prefix_len)].to_vec();
+        let mut inner = self.inner.write();
+        if let Some(old_prefix) = inner.prefixes.insert(key.to_vec(), prefix.clone()) {
+            inner.remove_key(&old_prefix, key);
+        }
+        inner.keys.entry(prefix).or_default().insert(key.to_vec());
+    }
+    pub fn delete(&self, key: &[u8]) {
+        let mut inner = self.inner.write();
+        if let Some(old_prefix) = inner.prefixes.remove(key) {
+            inner.remove_key(&old_prefix, key);
+        }
+    }
+    pub fn clear(&self) {
+        *self.inner.write() = ValuePrefixMap::default();
+    }
+    /// 返回 value 前缀以 prefix 开头的所有 key，按照 key 排序
+    ///
+    /// prefix 的长度超过 prefix_len 时，只按照前 prefix_len 个字节匹配
+    pub fn keys(&self, prefix: &[u8]) -> Vec<Bytes> {
+        let prefix = &prefix[..prefix.len().min(self.prefix_len)];
+        let inner = self.inner.read();
+        let mut keys = BTreeSet::new();
+        for (value_prefix, prefix_keys) in inner.keys.range(prefix.to_vec()..) {
+            if !value_prefix.starts_with(prefix) {
+                break;
+            }
+            keys.extend(prefix_keys.iter());
+        }
+        keys.into_iter().map(|key| Bytes::copy_from_slice(key)).collect()
+    }
+}
+
+impl ValuePrefixMap {
+    fn remove_key(&mut self, prefix: &[u8], key: &[u8]) {
+        if let Some(keys) = self.keys.get_mut(prefix) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.keys.remove(prefix);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_value_prefix_index() {
+        let index = ValuePrefixIndex::new(3);
+        index.put(b"k1", b"red-apple");
+        index.put(b"k2", b"red-cherry");
+        index.put(b"k3", b"green-apple");
+        index.put(b"k4", b"re");
+
+        assert_eq!(vec![Bytes::from("k1"), Bytes::from("k2")], index.keys(b"red"));
+        assert_eq!(
+            vec![Bytes::from("k1"), Bytes::from("k2"), Bytes::from("k4")],
+            index.keys(b"re")
+        );
+
+        // 覆盖写入之后旧的前缀被删除
+        index.put(b"k1", b"green-pear");
+        assert_eq!(vec![Bytes::from("k2")], index.keys(b"red"));
+        assert_eq!(vec![Bytes::from("k1"), Bytes::from("k3")], index.keys(b"gre"));
+
+        index.delete(b"k2");
+        assert!(index.keys(b"red").is_empty());
+        assert_eq!(3, index.keys(b"").len());
+    }
+}
@@ -1,24 +1,197 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    ops::Bound,
+    sync::{atomic::Ordering, Arc},
+    thread,
+};
 
 use bytes::Bytes;
+use log::warn;
 use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
 
-use crate::{db::Engine, errors::Result, index::IndexIterator, options::IteratorOptions};
+use crate::{
+    batch::parse_log_record_key,
+    data::{
+        blob_file::BlobPointer,
+        log_record::{LogRecordPos, LogRecordType},
+    },
+    db::Engine,
+    errors::{Errors, Result},
+    index::IndexIterator,
+    options::IteratorOptions,
+};
+
+/// 一个数据文件 id 以及该文件中需要读取的 key 和偏移
+type FilePositions = (u32, Vec<(Vec<u8>, u64)>);
+
+/// 一段连续的 key 区间，可以直接传给 Engine::range
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyRange {
+    pub start: Bound<Bytes>,
+    pub end: Bound<Bytes>,
+}
 
 pub struct Iterator<'a> {
     index_iter: Arc<RwLock<Box<dyn IndexIterator>>>,
     engine: &'a Engine,
 }
+/// 按照文件 id 和偏移的顺序读取数据文件中 put 写入的数据
+pub struct FileIterator<'a> {
+    raw: RawIterator<'a>,
+}
+/// 按照文件 id 和偏移的顺序读取数据文件中的所有记录
+struct RawIterator<'a> {
+    engine: &'a Engine,
+    file_ids: Vec<u32>,
+    curr_index: usize,
+    offset: u64,
+}
+/// 数据文件中的一条原始记录
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawEntry {
+    pub pos: LogRecordPos,
+    pub rec_type: LogRecordType,
+    /// 去掉序列号之后的 key，批次的标记为内部使用的 key
+    pub key: Bytes,
+    /// 批量写入的序列号，单独写入的记录为 0
+    pub seq_no: usize,
+    /// 记录中保存的 value，BlobPointer 记录为编码后的指针
+    pub value: Bytes,
+    pub flags: u8,
+}
 impl Engine {
-    pub fn iter(&self, options: IteratorOptions) -> Iterator {
+    pub fn iter(&self, options: IteratorOptions) -> Iterator<'_> {
         Iterator {
             index_iter: Arc::new(RwLock::new(self.index.iterator(options))),
             engine: self,
         }
     }
+    /// 按照写入顺序读取 id 大于等于 from_file_id 的数据文件中所有写入的数据
+    ///
+    /// 只返回 put 写入的数据（包括批量写入的数据），删除记录会被跳过，
+    /// 读取的文件为调用时已经存在的数据文件，可用于增量备份或者副本追赶
+    pub fn iter_files(&self, from_file_id: u32) -> FileIterator<'_> {
+        let mut file_ids = self.data_file_ids();
+        file_ids.retain(|file_id| *file_id >= from_file_id);
+        FileIterator {
+            raw: RawIterator {
+                engine: self,
+                file_ids,
+                curr_index: 0,
+                offset: 0,
+            },
+        }
+    }
+    /// 按照文件 id 和偏移的顺序读取所有的记录，包括删除记录和批次的标记，即物理上的日志
+    ///
+    /// 与按照 key 排序、只返回有效数据的 iter 不同，可以用于复制；
+    /// 读取的文件为调用时已经存在的数据文件
+    pub fn raw_iter(&self) -> impl std::iter::Iterator<Item = Result<RawEntry>> + '_ {
+        RawIterator {
+            engine: self,
+            file_ids: self.data_file_ids(),
+            curr_index: 0,
+            offset: 0,
+        }
+    }
+    /// 按照写入顺序返回数据文件中 key 的所有记录，包括被覆盖的 value 和删除记录
+    ///
+    /// 用于调试和审计，会扫描所有数据文件，开销与记录总数成正比，布隆过滤器排除的合并后的文件不会读取；
+    /// 合并之后旧的版本已经被清理，只能看到合并后保留的记录
+    pub fn history(&self, key: Bytes) -> Result<Vec<(LogRecordPos, LogRecordType, Bytes)>> {
+        let key = self.normalize_key(key);
+        let mut history = Vec::new();
+        for file_id in self.data_file_ids() {
+            // 布隆过滤器确定 key 不在文件中时跳过整个文件
+            if !self.file_may_contain(file_id, &key) {
+                continue;
+            }
+            let mut offset = 0;
+            loop {
+                let read_record = match self.read_log_record(file_id, offset) {
+                    Ok(read_record) => read_record,
+                    Err(Errors::ReadDataFileEOF) => break,
+                    Err(e) => return Err(e),
+                };
+                let pos = LogRecordPos { file_id, offset };
+                offset += read_record.size as u64;
+
+                let log_record = read_record.record;
+                let value = match log_record.rec_type {
+                    LogRecordType::NORMAL | LogRecordType::DElETED => log_record.value,
+                    LogRecordType::BlobPointer => self.read_blob_value(&log_record.value)?,
+                    // 批次的标记不属于任何 key
+                    LogRecordType::TxnBegin | LogRecordType::TxnFinish => continue,
+                };
+                let (real_key, _) = parse_log_record_key(log_record.key)?;
+                if real_key == key {
+                    history.push((pos, log_record.rec_type, value.into()));
+                }
+            }
+        }
+        Ok(history)
+    }
     pub fn list_keys(&self) -> Result<Vec<Bytes>> {
         self.index.list_keys()
     }
+    /// 按照 key 升序返回位于 start 和 end 之间的所有数据，两端可以分别设置为包含、不包含或者不限制
+    pub fn range(&self, start: Bound<Bytes>, end: Bound<Bytes>) -> Result<Vec<(Bytes, Bytes)>> {
+        let items = self
+            .index
+            .range(start.map(|key| key.to_vec()), end.map(|key| key.to_vec()));
+        let mut result = Vec::with_capacity(items.len());
+        for (key, pos) in items {
+            if let Some(value) = self.get_snapshot_value(&key, pos)? {
+                result.push((Bytes::from(key), value));
+            }
+        }
+        Ok(result)
+    }
+    /// 按照 key 升序返回所有以 suffix 结尾的数据
+    ///
+    /// 后缀无法利用 key 的顺序，会扫描索引中所有的 key
+    pub fn scan_suffix(&self, suffix: Bytes) -> Result<Vec<(Bytes, Bytes)>> {
+        let iter = self.iter(IteratorOptions {
+            suffix: suffix.to_vec(),
+            ..Default::default()
+        });
+        let mut result = Vec::new();
+        while let Some(item) = iter.next() {
+            result.push(item);
+        }
+        Ok(result)
+    }
+    /// 按照 key 的数量把整个 key 空间划分为最多 n 个连续且互不重叠的区间，用于并行处理
+    ///
+    /// 第一个区间没有下界，最后一个区间没有上界，划分之后写入的 key 也会落在某一个区间中；
+    /// key 的数量少于 n 时返回的区间数量等于 key 的数量，没有 key 时返回一个覆盖整个 key 空间的区间，
+    /// n 为 0 时返回空
+    pub fn partitions(&self, n: usize) -> Result<Vec<KeyRange>> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let mut keys = self.list_keys()?;
+        keys.sort();
+        let n = n.min(keys.len()).max(1);
+
+        // 第 i 个区间从第 i * len / n 个 key 开始
+        let boundaries: Vec<Bytes> = (1..n).map(|i| keys[i * keys.len() / n].clone()).collect();
+        let mut partitions = Vec::with_capacity(n);
+        let mut start = Bound::Unbounded;
+        for boundary in boundaries {
+            partitions.push(KeyRange {
+                start,
+                end: Bound::Excluded(boundary.clone()),
+            });
+            start = Bound::Included(boundary);
+        }
+        partitions.push(KeyRange {
+            start,
+            end: Bound::Unbounded,
+        });
+        Ok(partitions)
+    }
     pub fn fold<F>(&self, f: F) -> Result<()>
     where
         Self: Sized,
@@ -32,6 +205,210 @@ impl Engine {
         }
         Ok(())
     }
+    /// 按照 key 的顺序计算所有有效数据的 SHA-256 摘要，用于比较两个副本的数据是否一致
+    ///
+    /// 每一条数据按照 key 长度(u64) + key + value 长度(u64) + value 的格式参与计算
+    pub fn digest(&self) -> Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            let value = match self.get_snapshot_value(key, *pos)? {
+                Some(value) => value,
+                None => continue,
+            };
+            hasher.update((key.len() as u64).to_be_bytes());
+            hasher.update(key);
+            hasher.update((value.len() as u64).to_be_bytes());
+            hasher.update(&value);
+        }
+        Ok(hasher.finalize().into())
+    }
+    /// 按照 key 的顺序返回所有 key 以及对应 value 的长度
+    ///
+    /// 只读取记录的头部，不读取 value，同一个文件中的记录在一次加锁中读取
+    pub fn key_sizes(&self) -> Result<Vec<(Bytes, usize)>> {
+        let record_sizes = self.live_record_sizes()?;
+        Ok(record_sizes
+            .into_iter()
+            .map(|(key, value_size, _)| (key, value_size))
+            .collect())
+    }
+    /// 空间放大：数据文件和 blob 文件的总大小除以有效数据占用的大小
+    ///
+    /// 被覆盖或者删除的数据在合并之前仍然占用磁盘空间，因此比值随着覆盖写入升高，合并之后下降。
+    /// blob 文件中失效的 value 不会被合并回收，会一直计算在内。
+    /// 没有数据时返回 0，只有无效数据时返回无穷大，读取数据失败时返回 NaN
+    pub fn space_amplification(&self) -> f64 {
+        self.amplification(self.disk_bytes())
+    }
+    /// 写放大：写入数据文件和 blob 文件的总字节数除以有效数据占用的大小
+    ///
+    /// 写入的字节数包括合并重写的数据，打开时从已有文件的大小开始计算，因此合并之后比值不会下降。
+    /// 没有数据时返回 0，只有无效数据时返回无穷大，读取数据失败时返回 NaN
+    pub fn write_amplification(&self) -> f64 {
+        self.amplification(self.written_bytes.load(Ordering::SeqCst))
+    }
+    /// 数据文件和 blob 文件当前的总大小
+    pub(crate) fn disk_bytes(&self) -> u64 {
+        let mut total_bytes: u64 = self
+            .older_files
+            .read()
+            .values()
+            .map(|data_file| data_file.get_write_off())
+            .sum();
+        total_bytes += self.active_file.read().get_write_off();
+        if let Ok(blob_file) = self.get_blob_file() {
+            total_bytes += blob_file.get_write_off();
+        }
+        total_bytes
+    }
+    fn amplification(&self, total_bytes: u64) -> f64 {
+        if total_bytes == 0 {
+            return 0.0;
+        }
+        let live_bytes: u64 = match self.live_record_sizes() {
+            Ok(record_sizes) => record_sizes.iter().map(|(_, _, size)| size).sum(),
+            Err(e) => {
+                warn!("failed to read live record sizes: {}", e);
+                return f64::NAN;
+            }
+        };
+        total_bytes as f64 / live_bytes as f64
+    }
+    /// 读取最多 sample 条记录估计记录的平均大小，不需要扫描整个数据库
+    ///
+    /// 从活跃文件的开头开始读取，活跃文件中的记录不足时继续读取较新的旧文件，
+    /// 只读取记录的头部；包括删除记录和批次的标记，没有记录时返回 0
+    pub fn estimate_avg_record_size(&self, sample: usize) -> Result<usize> {
+        let mut total_size = 0;
+        let mut count = 0;
+        for file_id in self.data_file_ids().into_iter().rev() {
+            let mut offset = 0;
+            while count < sample {
+                let header = match self.with_data_file(file_id, |data_file| {
+                    data_file.read_log_record_header(offset)
+                }) {
+                    Ok(header) => header,
+                    Err(Errors::ReadDataFileEOF) => break,
+                    Err(e) => return Err(e),
+                };
+                let size = header.header_size + header.key_size + header.value_size + 4;
+                total_size += size;
+                count += 1;
+                offset += size as u64;
+            }
+            if count >= sample {
+                break;
+            }
+        }
+        match count {
+            0 => Ok(0),
+            _ => Ok(total_size / count),
+        }
+    }
+    /// 按照 key 的顺序返回每个 key 的 value 长度以及在磁盘上占用的字节数
+    fn live_record_sizes(&self) -> Result<Vec<(Bytes, usize, u64)>> {
+        let mut keys = Vec::new();
+        let mut file_positions: HashMap<u32, Vec<(usize, u64)>> = HashMap::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            file_positions
+                .entry(pos.file_id)
+                .or_default()
+                .push((keys.len(), pos.offset));
+            keys.push(Bytes::copy_from_slice(key));
+        }
+
+        let mut sizes = vec![(0, 0); keys.len()];
+        for (file_id, positions) in file_positions {
+            self.with_data_file(file_id, |data_file| {
+                for (i, offset) in positions {
+                    let header = data_file.read_log_record_header(offset)?;
+                    let record_size =
+                        (header.header_size + header.key_size + header.value_size + 4) as u64;
+                    sizes[i] = match header.rec_type {
+                        // 大 value 的长度保存在指针中
+                        LogRecordType::BlobPointer => {
+                            let log_record = data_file.read_log_record(offset)?.record;
+                            let pointer = BlobPointer::decode(&log_record.value)?;
+                            (pointer.size as usize, record_size + pointer.size)
+                        }
+                        _ => (header.value_size, record_size),
+                    };
+                }
+                Ok(())
+            })?;
+        }
+        Ok(keys
+            .into_iter()
+            .zip(sizes)
+            .map(|(key, (value_size, disk_size))| (key, value_size, disk_size))
+            .collect())
+    }
+    /// 使用多个线程并发读取所有的数据，对每一条数据调用 f，调用的顺序不确定
+    ///
+    /// 读取的是调用时索引的快照，同一个文件中的数据由同一个线程读取
+    pub fn for_each_parallel<F>(&self, f: F) -> Result<()>
+    where
+        F: Fn(Bytes, Bytes) + Sync,
+    {
+        // 按照文件对索引的快照分组
+        let mut file_positions: HashMap<u32, Vec<(Vec<u8>, u64)>> = HashMap::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            file_positions
+                .entry(pos.file_id)
+                .or_default()
+                .push((key.clone(), pos.offset));
+        }
+        let num_threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(file_positions.len());
+        if num_threads == 0 {
+            return Ok(());
+        }
+        let mut groups: Vec<Vec<FilePositions>> = (0..num_threads).map(|_| Vec::new()).collect();
+        for (i, item) in file_positions.into_iter().enumerate() {
+            groups[i % num_threads].push(item);
+        }
+
+        thread::scope(|s| {
+            let handles: Vec<_> = groups
+                .into_iter()
+                .map(|group| s.spawn(|| self.read_file_positions(group, &f)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("parallel read thread panicked"))
+                .collect::<Result<Vec<()>>>()
+        })?;
+        Ok(())
+    }
+    fn read_file_positions<F>(&self, group: Vec<FilePositions>, f: &F) -> Result<()>
+    where
+        F: Fn(Bytes, Bytes) + Sync,
+    {
+        for (file_id, positions) in group {
+            // 持有文件的读锁读取所有的记录，释放锁之后再调用 f，避免 f 中的写操作死锁
+            let records = self.with_data_file(file_id, |data_file| {
+                let mut records = Vec::with_capacity(positions.len());
+                for (key, offset) in positions {
+                    records.push((key, data_file.read_log_record(offset)?.record));
+                }
+                Ok(records)
+            })?;
+            for (key, log_record) in records {
+                let value = match log_record.rec_type {
+                    LogRecordType::NORMAL => log_record.value,
+                    LogRecordType::BlobPointer => self.read_blob_value(&log_record.value)?,
+                    _ => continue,
+                };
+                f(key.into(), value.into());
+            }
+        }
+        Ok(())
+    }
 }
 impl Iterator<'_> {
     pub fn rewind(&mut self) {
@@ -42,14 +419,81 @@ impl Iterator<'_> {
         let mut index_iter = self.index_iter.write();
         index_iter.seek(key)
     }
+    /// 返回下一条数据，创建迭代器之后被删除并且已经被合并清理的 key 会被跳过
+    ///
+    /// 读取数据文件失败时记录日志并结束遍历
     pub fn next(&self) -> Option<(Bytes, Bytes)> {
         let mut index_iter = self.index_iter.write();
-        if let Some(item) = index_iter.next() {
-            let value = self
-                .engine
-                .get_value_by_position(item.1)
-                .expect("faild to get value from data file");
-            return Some((Bytes::from(item.0.to_vec()), value));
+        while let Some((key, pos)) = index_iter.next() {
+            match self.engine.get_snapshot_value(key, *pos) {
+                Ok(Some(value)) => return Some((Bytes::from(key.to_vec()), value)),
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("failed to read value of key {:?}: {:?}", key, e);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+}
+impl std::iter::Iterator for RawIterator<'_> {
+    type Item = Result<RawEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(file_id) = self.file_ids.get(self.curr_index) {
+            let read_record = match self.engine.read_log_record(*file_id, self.offset) {
+                Ok(read_record) => read_record,
+                Err(Errors::ReadDataFileEOF) => {
+                    self.curr_index += 1;
+                    self.offset = 0;
+                    continue;
+                }
+                Err(e) => {
+                    // 出错之后不再继续读取
+                    self.curr_index = self.file_ids.len();
+                    return Some(Err(e));
+                }
+            };
+            let pos = LogRecordPos {
+                file_id: *file_id,
+                offset: self.offset,
+            };
+            self.offset += read_record.size as u64;
+
+            let log_record = read_record.record;
+            return Some(parse_log_record_key(log_record.key).map(|(real_key, seq_no)| {
+                RawEntry {
+                    pos,
+                    rec_type: log_record.rec_type,
+                    key: real_key.into(),
+                    seq_no,
+                    value: log_record.value.into(),
+                    flags: log_record.flags,
+                }
+            }));
+        }
+        None
+    }
+}
+impl std::iter::Iterator for FileIterator<'_> {
+    type Item = Result<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entry in self.raw.by_ref() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            let value = match entry.rec_type {
+                LogRecordType::NORMAL => entry.value,
+                LogRecordType::BlobPointer => match self.raw.engine.read_blob_value(&entry.value) {
+                    Ok(value) => value.into(),
+                    Err(e) => return Some(Err(e)),
+                },
+                _ => continue,
+            };
+            return Some(Ok((entry.key, value)));
         }
         None
     }
@@ -58,10 +502,384 @@ impl Iterator<'_> {
 mod tests {
     use std::path::PathBuf;
 
-    use crate::{options::Options, util::rand_kv};
+    use crate::{
+        options::{IndexType, Options},
+        util::rand_kv,
+    };
 
     use super::*;
     #[test]
+    fn test_for_each_parallel() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-for-each-parallel"),
+            data_file_size: 4 * 1024,
+            sync_writes: false,
+            inline_value_threshold: Some(64),
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            let value = if i % 10 == 0 {
+                Bytes::from(vec![b'v'; 100 + i as usize])
+            } else {
+                rand_kv::get_test_value(i as usize)
+            };
+            engine.put(rand_kv::get_test_key(i), value).unwrap();
+        }
+        for i in 0..100 {
+            engine.delete(rand_kv::get_test_key(i * 3)).unwrap();
+        }
+        assert!(engine.data_file_ids().len() > 1);
+
+        let results = RwLock::new(HashMap::new());
+        engine
+            .for_each_parallel(|key, value| {
+                assert!(results.write().insert(key, value).is_none());
+            })
+            .unwrap();
+
+        // 与 list_keys 以及 get 的结果一致
+        let results = results.into_inner();
+        let keys = engine.list_keys().unwrap();
+        assert_eq!(keys.len(), results.len());
+        for key in keys {
+            assert_eq!(engine.get(key.clone()).unwrap(), results[&key]);
+        }
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_digest() {
+        let opts1 = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-digest-1"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let opts2 = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-digest-2"),
+            index_type: crate::options::IndexType::HashMap,
+            ..opts1.clone()
+        };
+        let engine1 = Engine::open(opts1.clone()).expect("failed to open engine");
+        let engine2 = Engine::open(opts2.clone()).expect("failed to open engine");
+        assert_eq!(engine1.digest().unwrap(), engine2.digest().unwrap());
+
+        // 写入顺序和历史不同，但是最终的数据相同
+        for i in 0..100 {
+            engine1
+                .put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize))
+                .unwrap();
+        }
+        for i in (0..120).rev() {
+            engine2
+                .put(rand_kv::get_test_key(i), rand_kv::get_test_value(0))
+                .unwrap();
+            engine2
+                .put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize))
+                .unwrap();
+        }
+        for i in 100..120 {
+            engine2.delete(rand_kv::get_test_key(i)).unwrap();
+        }
+        assert_eq!(engine1.digest().unwrap(), engine2.digest().unwrap());
+
+        // 修改一条数据之后摘要不同
+        engine2
+            .put(rand_kv::get_test_key(1), rand_kv::get_test_value(2))
+            .unwrap();
+        assert_ne!(engine1.digest().unwrap(), engine2.digest().unwrap());
+
+        std::fs::remove_dir_all(opts1.dir_path.clone()).unwrap();
+        std::fs::remove_dir_all(opts2.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_key_sizes() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-key-sizes"),
+            data_file_size: 4 * 1024,
+            inline_value_threshold: Some(512),
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.key_sizes().unwrap().is_empty());
+
+        for i in 0..50 {
+            let value = Bytes::from(vec![b'v'; (i * 37) as usize]);
+            engine.put(rand_kv::get_test_key(i), value).unwrap();
+        }
+        engine.delete(rand_kv::get_test_key(10)).unwrap();
+        assert!(engine.data_file_ids().len() > 1);
+
+        let key_sizes = engine.key_sizes().unwrap();
+        assert_eq!(engine.list_keys().unwrap().len(), key_sizes.len());
+        for (key, size) in key_sizes {
+            assert_eq!(engine.get(key).unwrap().len(), size);
+        }
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_space_amplification() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-space-amplification"),
+            data_file_size: 4 * 1024,
+            sync_writes: false,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(0.0, engine.space_amplification());
+
+        for i in 0..100 {
+            engine
+                .put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize))
+                .unwrap();
+        }
+        let amp1 = engine.space_amplification();
+        assert!((amp1 - 1.0).abs() < 1e-9);
+
+        // 反复覆盖写入之后空间放大升高
+        for _ in 0..5 {
+            for i in 0..100 {
+                engine
+                    .put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize))
+                    .unwrap();
+            }
+        }
+        let amp2 = engine.space_amplification();
+        assert!(amp2 > 5.0);
+
+        // 合并之后空间放大下降，重启之后保持一致
+        engine.merge().unwrap();
+        let amp3 = engine.space_amplification();
+        assert!(amp3 < amp2);
+        assert!((amp3 - 1.0).abs() < 1e-9);
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!((engine2.space_amplification() - amp3).abs() < 1e-9);
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_write_amplification() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-write-amplification"),
+            data_file_size: 4 * 1024,
+            sync_writes: false,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(0.0, engine.write_amplification());
+
+        for i in 0..100 {
+            engine
+                .put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize))
+                .unwrap();
+        }
+        assert!((engine.write_amplification() - 1.0).abs() < 1e-9);
+
+        // 反复覆盖写入之后写放大升高
+        for _ in 0..5 {
+            for i in 0..100 {
+                engine
+                    .put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize))
+                    .unwrap();
+            }
+        }
+        let amp1 = engine.write_amplification();
+        assert!(amp1 > 5.0);
+
+        // 合并重写的数据同样计算在内，写放大继续升高，而空间放大下降
+        engine.merge().unwrap();
+        let amp2 = engine.write_amplification();
+        assert!(amp2 > amp1);
+        assert!((amp2 - 1.0 - amp1).abs() < 1e-9);
+        assert!(engine.space_amplification() < amp1);
+
+        // 重启之后从已有文件的大小开始计算
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!((engine2.write_amplification() - engine2.space_amplification()).abs() < 1e-9);
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_estimate_avg_record_size() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-estimate-avg-record-size"),
+            data_file_size: 64 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(0, engine.estimate_avg_record_size(10).unwrap());
+
+        // value 的长度在 100 到 300 之间交替
+        let mut sizes = Vec::new();
+        for i in 0..1000 {
+            let key = rand_kv::get_test_key(i);
+            let value = Bytes::from(vec![b'v'; 100 + (i as usize % 3) * 100]);
+            sizes.push(Engine::record_size(&key, &value));
+            engine.put(key, value).unwrap();
+        }
+        assert!(engine.data_file_ids().len() > 1);
+        let true_avg = sizes.iter().sum::<usize>() / sizes.len();
+
+        for sample in [30, 300, 3000] {
+            let estimate = engine.estimate_avg_record_size(sample).unwrap();
+            assert!(estimate.abs_diff(true_avg) * 20 < true_avg, "{} {}", estimate, true_avg);
+        }
+        assert_eq!(0, engine.estimate_avg_record_size(0).unwrap());
+        let first = engine.estimate_avg_record_size(1).unwrap();
+        assert!(sizes.contains(&first));
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_range() {
+        for (name, index_type) in [("btree", IndexType::BTree), ("hashmap", IndexType::HashMap)] {
+            let opts = Options {
+                dir_path: PathBuf::from(format!("./tmp/bitcask-rs-range-{}", name)),
+                data_file_size: 64 * 1024 * 1024,
+                index_type,
+                ..Default::default()
+            };
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+            for key in ["a", "b", "c", "d", "e"] {
+                engine.put(Bytes::from(key), Bytes::from(key.repeat(2))).unwrap();
+            }
+            let range_keys = |start: Bound<&'static str>, end: Bound<&'static str>| {
+                let res = engine.range(start.map(Bytes::from), end.map(Bytes::from)).unwrap();
+                for (key, value) in res.iter() {
+                    assert_eq!([&key[..], &key[..]].concat(), value.to_vec());
+                }
+                res.into_iter().map(|(key, _)| key).collect::<Vec<Bytes>>()
+            };
+            let keys = |keys: &[&'static str]| {
+                keys.iter().map(|k| Bytes::from(*k)).collect::<Vec<_>>()
+            };
+            use Bound::{Excluded, Included, Unbounded};
+
+            assert_eq!(keys(&["b", "c", "d"]), range_keys(Included("b"), Included("d")));
+            assert_eq!(keys(&["b", "c"]), range_keys(Included("b"), Excluded("d")));
+            assert_eq!(keys(&["c", "d"]), range_keys(Excluded("b"), Included("d")));
+            assert_eq!(keys(&["c"]), range_keys(Excluded("b"), Excluded("d")));
+            assert_eq!(keys(&["b", "c", "d", "e"]), range_keys(Included("b"), Unbounded));
+            assert_eq!(keys(&["c", "d", "e"]), range_keys(Excluded("b"), Unbounded));
+            assert_eq!(keys(&["a", "b", "c", "d"]), range_keys(Unbounded, Included("d")));
+            assert_eq!(keys(&["a", "b", "c"]), range_keys(Unbounded, Excluded("d")));
+            assert_eq!(5, range_keys(Unbounded, Unbounded).len());
+
+            // 不存在的 key 作为边界
+            assert_eq!(keys(&["c", "d"]), range_keys(Included("bb"), Excluded("dd")));
+            // 空区间不会 panic
+            assert_eq!(keys(&["c"]), range_keys(Included("c"), Included("c")));
+            assert!(range_keys(Excluded("c"), Excluded("c")).is_empty());
+            assert!(range_keys(Included("c"), Excluded("c")).is_empty());
+            assert!(range_keys(Included("d"), Included("b")).is_empty());
+
+            // 删除的数据不会返回
+            engine.delete(Bytes::from("c")).unwrap();
+            assert_eq!(keys(&["b", "d"]), range_keys(Included("b"), Included("d")));
+
+            std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+        }
+    }
+    #[test]
+    fn test_scan_suffix() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-scan-suffix"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for key in ["a.log", "b.txt", "c.log", "log", "d.log.bak", "e.log"] {
+            engine.put(Bytes::from(key), Bytes::from(key.to_uppercase())).unwrap();
+        }
+        engine.delete(Bytes::from("e.log")).unwrap();
+
+        let res = engine.scan_suffix(Bytes::from(".log")).unwrap();
+        assert_eq!(
+            vec![
+                (Bytes::from("a.log"), Bytes::from("A.LOG")),
+                (Bytes::from("c.log"), Bytes::from("C.LOG")),
+            ],
+            res
+        );
+        assert!(engine.scan_suffix(Bytes::from(".md")).unwrap().is_empty());
+        // 空后缀返回所有数据
+        assert_eq!(5, engine.scan_suffix(Bytes::new()).unwrap().len());
+
+        // 与前缀以及 reverse 同时使用
+        let iter = engine.iter(IteratorOptions {
+            prefix: "c".as_bytes().to_vec(),
+            suffix: ".log".as_bytes().to_vec(),
+            reverse: true,
+        });
+        assert_eq!(Bytes::from("c.log"), iter.next().unwrap().0);
+        assert!(iter.next().is_none());
+        let iter = engine.iter(IteratorOptions {
+            suffix: "log".as_bytes().to_vec(),
+            reverse: true,
+            ..Default::default()
+        });
+        let mut keys = Vec::new();
+        while let Some((key, _)) = iter.next() {
+            keys.push(key);
+        }
+        assert_eq!(vec![Bytes::from("log"), Bytes::from("c.log"), Bytes::from("a.log")], keys);
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_partitions() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-partitions"),
+            data_file_size: 64 * 1024 * 1024,
+            index_type: IndexType::HashMap,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        // 没有 key 时返回一个覆盖整个 key 空间的区间
+        let partitions = engine.partitions(4).unwrap();
+        assert_eq!(1, partitions.len());
+        assert_eq!(Bound::Unbounded, partitions[0].start);
+        assert_eq!(Bound::Unbounded, partitions[0].end);
+
+        for i in 0..103 {
+            engine.put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize)).unwrap();
+        }
+        let partitions = engine.partitions(4).unwrap();
+        assert_eq!(4, partitions.len());
+        assert_eq!(Bound::Unbounded, partitions[0].start);
+        assert_eq!(Bound::Unbounded, partitions[3].end);
+
+        // 相邻的区间首尾相接，没有重叠
+        for pair in partitions.windows(2) {
+            match (&pair[0].end, &pair[1].start) {
+                (Bound::Excluded(end), Bound::Included(start)) => assert_eq!(end, start),
+                _ => panic!("partitions are not contiguous"),
+            }
+        }
+
+        // 所有 key 恰好出现一次，每个区间的数量大致相等
+        let mut all_keys = Vec::new();
+        for partition in partitions.iter() {
+            let items = engine.range(partition.start.clone(), partition.end.clone()).unwrap();
+            assert!(items.len() == 25 || items.len() == 26);
+            all_keys.extend(items.into_iter().map(|(key, _)| key));
+        }
+        let mut expected = engine.list_keys().unwrap();
+        expected.sort();
+        assert_eq!(expected, all_keys);
+
+        // key 的数量少于 n 时每个区间只有一个 key
+        assert_eq!(103, engine.partitions(200).unwrap().len());
+        assert!(engine.partitions(0).unwrap().is_empty());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
     fn test_list_keys() {
         let mut opts = Options::default();
         opts.dir_path = PathBuf::from("./tmp/bitcask-rs-list_key");
@@ -154,4 +972,177 @@ mod tests {
         println!("{:?}", engine.list_keys());
         std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
     }
+    #[test]
+    fn test_history() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-history"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let key = rand_kv::get_test_key(1);
+        let mut positions = Vec::new();
+        positions.push(engine.put_at(key.clone(), Bytes::from("v1")).unwrap());
+        engine.put(rand_kv::get_test_key(2), Bytes::from("other")).unwrap();
+        positions.push(engine.put_at(key.clone(), Bytes::from("v2")).unwrap());
+        engine.delete(key.clone()).unwrap();
+        // 批量写入的记录同样会返回
+        let wb = engine
+            .new_write_batch(crate::options::WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        wb.put(key.clone(), Bytes::from("v3")).unwrap();
+        wb.commit().unwrap();
+
+        let history = engine.history(key.clone()).unwrap();
+        let types: Vec<LogRecordType> = history.iter().map(|(_, t, _)| *t).collect();
+        assert_eq!(
+            vec![
+                LogRecordType::NORMAL,
+                LogRecordType::NORMAL,
+                LogRecordType::DElETED,
+                LogRecordType::NORMAL
+            ],
+            types
+        );
+        let values: Vec<Bytes> = history.iter().map(|(_, _, v)| v.clone()).collect();
+        assert_eq!(
+            vec![Bytes::from("v1"), Bytes::from("v2"), Bytes::new(), Bytes::from("v3")],
+            values
+        );
+        assert_eq!(positions[0], history[0].0);
+        assert_eq!(positions[1], history[1].0);
+        assert!(history.windows(2).all(|w| w[0].0.offset() < w[1].0.offset()));
+        assert_eq!(Some(history[3].0), engine.index.get(key.to_vec()));
+
+        // 不存在的 key 没有任何记录
+        assert!(engine.history(rand_kv::get_test_key(3)).unwrap().is_empty());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_raw_iter() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-raw-iter"),
+            data_file_size: 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let mut positions = Vec::new();
+        for i in 0..50 {
+            let key = rand_kv::get_test_key(i);
+            positions.push(engine.put_at(key, rand_kv::get_test_value(i as usize)).unwrap());
+        }
+        engine.delete(rand_kv::get_test_key(10)).unwrap();
+        engine.put_with_flags(rand_kv::get_test_key(10), Bytes::from("v"), 1).unwrap();
+        let wb = engine
+            .new_write_batch(crate::options::WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        wb.put(rand_kv::get_test_key(60), Bytes::from("batch")).unwrap();
+        wb.commit().unwrap();
+
+        let entries: Vec<RawEntry> = engine.raw_iter().map(|entry| entry.unwrap()).collect();
+        assert!(engine.data_file_ids().len() > 1);
+        assert_eq!(50 + 2 + 3, entries.len());
+        // 按照写入的顺序返回，位置与写入时返回的位置一致
+        for (i, entry) in entries.iter().take(50).enumerate() {
+            assert_eq!(positions[i], entry.pos);
+            assert_eq!(LogRecordType::NORMAL, entry.rec_type);
+            assert_eq!(rand_kv::get_test_key(i as i32), entry.key);
+            assert_eq!(rand_kv::get_test_value(i), entry.value);
+        }
+        let pos = |e: &RawEntry| (e.pos.file_id(), e.pos.offset());
+        assert!(entries.windows(2).all(|w| pos(&w[0]) < pos(&w[1])));
+        let tail: Vec<(LogRecordType, u8)> =
+            entries[50..].iter().map(|e| (e.rec_type, e.flags)).collect();
+        assert_eq!(
+            vec![
+                (LogRecordType::DElETED, 0),
+                (LogRecordType::NORMAL, 1),
+                (LogRecordType::TxnBegin, 0),
+                (LogRecordType::NORMAL, 0),
+                (LogRecordType::TxnFinish, 0),
+            ],
+            tail
+        );
+        assert_eq!(rand_kv::get_test_key(10), entries[50].key);
+        // 批次中的记录带有相同的序列号
+        assert_eq!(0, entries[50].seq_no);
+        assert!(entries[52].seq_no > 0);
+        assert!(entries[52..].iter().all(|e| e.seq_no == entries[52].seq_no));
+        assert_eq!(rand_kv::get_test_key(60), entries[53].key);
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_iter_files() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-iter-files"),
+            data_file_size: 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            engine.put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize)).unwrap();
+        }
+        let from_file_id = engine.checkpoint().unwrap().active_file_id;
+        assert!(from_file_id > 0);
+        for i in 100..200 {
+            engine.put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize)).unwrap();
+        }
+        engine.delete(rand_kv::get_test_key(150)).unwrap();
+
+        let entries: Vec<(Bytes, Bytes)> = engine
+            .iter_files(from_file_id)
+            .map(|entry| entry.unwrap())
+            .collect();
+        // 之后写入的数据全部按顺序返回
+        let new_entries: Vec<&(Bytes, Bytes)> = entries
+            .iter()
+            .filter(|(key, _)| *key >= rand_kv::get_test_key(100))
+            .collect();
+        assert_eq!(100, new_entries.len());
+        for (i, (key, value)) in new_entries.iter().enumerate() {
+            assert_eq!(rand_kv::get_test_key(100 + i as i32), key);
+            assert_eq!(rand_kv::get_test_value(100 + i), value);
+        }
+        // 更早的文件中的数据被跳过
+        for (key, _) in entries.iter() {
+            let pos = engine.index.get(key.to_vec());
+            if let Some(pos) = pos {
+                assert!(pos.file_id >= from_file_id);
+            }
+        }
+        assert!(entries.len() < 200);
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_iterator_after_merge() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-iter-after-merge"),
+            data_file_size: 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            engine.put(rand_kv::get_test_key(i), rand_kv::get_test_value(i as usize)).unwrap();
+        }
+
+        // 创建迭代器之后删除一部分 key 并合并，迭代器中的位置所在的文件都被删除
+        let iter = engine.iter(IteratorOptions::default());
+        for i in 0..10 {
+            engine.delete(rand_kv::get_test_key(i)).unwrap();
+        }
+        engine.merge().unwrap();
+
+        // 仍然存在的 key 按照合并后的位置读取，已经删除的 key 被跳过
+        let entries: Vec<(Bytes, Bytes)> = std::iter::from_fn(|| iter.next()).collect();
+        assert_eq!(90, entries.len());
+        for (n, (key, value)) in entries.iter().enumerate() {
+            assert_eq!(rand_kv::get_test_key(n as i32 + 10), key);
+            assert_eq!(rand_kv::get_test_value(n + 10), value);
+        }
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
 }
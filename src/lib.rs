@@ -1,12 +1,28 @@
+// 保留原有测试的写法
+#![cfg_attr(
+    test,
+    allow(
+        clippy::field_reassign_with_default,
+        clippy::bool_assert_comparison,
+        clippy::len_zero
+    )
+)]
 mod data;
 #[cfg(test)]
 mod db_test;
-mod errors;
-mod fio;
+pub mod errors;
+pub mod fio;
 mod index;
 
 pub mod db;
+pub mod entry;
 pub mod iterator;
+pub mod metadata;
+mod merge;
+mod shutdown;
 pub mod options;
 pub mod util;
 pub mod batch;
+pub mod changes;
+pub mod repair;
+pub mod watch;
@@ -0,0 +1,718 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    ops::RangeInclusive,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    batch::{log_record_key_with_seq, parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    data::{
+        bloom_filter::{get_bloom_file_name, BloomFilter},
+        data_file::{get_data_file_dir, get_data_file_name, DataFile},
+        footer::get_footer_file_name,
+        index_sidecar::IndexSidecar,
+        log_record::{LogRecord, LogRecordPos, LogRecordType},
+    },
+    db::Engine,
+    errors::{Errors, Result},
+    options::Options,
+};
+use log::warn;
+
+pub(crate) const MERGE_DIR_NAME: &str = "merge";
+
+impl Engine {
+    /// 合并数据文件，清理已经失效的数据
+    ///
+    /// 活跃文件会先被封存，所有旧文件中仍然有效的数据按照 data_file_size 重新写入新的文件，
+    /// 新文件的 id 位于被合并的文件和新的活跃文件之间，因此重启后的恢复顺序保持不变。
+    /// 合并完成后被合并的文件会被删除。没有旧文件时不做任何操作
+    pub fn merge(&self) -> Result<()> {
+        let _merge_lock = match self.merge_lock.try_lock() {
+            Some(lock) => lock,
+            None => return Err(Errors::MergeInProgress),
+        };
+        let (merge_file_ids, output_ids) = match self.rotate_for_merge(None)? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        self.merge_data_files(&merge_file_ids, output_ids, false)
+    }
+    /// 只合并指定的旧文件，其他文件保持不变
+    ///
+    /// 活跃文件同样会被封存，但是不参与合并。其他文件中可能还有被删除的 key 的旧数据，
+    /// 因此已经删除的 key 的删除记录会被保留；结束标记被合并的批次如果还有记录位于没有合并的文件中，
+    /// 这些记录中仍然有效的数据也会被写入新的文件
+    pub fn merge_files(&self, file_ids: Vec<u32>) -> Result<()> {
+        let _merge_lock = match self.merge_lock.try_lock() {
+            Some(lock) => lock,
+            None => return Err(Errors::MergeInProgress),
+        };
+        if file_ids.is_empty() {
+            return Ok(());
+        }
+        let (merge_file_ids, output_ids) = match self.rotate_for_merge(Some(file_ids))? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        self.merge_data_files(&merge_file_ids, output_ids, true)
+    }
+    /// 删除记录占数据文件中所有数据记录的比例达到 threshold 时返回 true
+    ///
+    /// 删除较多时每条删除记录以及被删除的数据都很小，按字节计算的可回收空间会低估合并的收益
+    pub fn should_merge_by_deletes(&self, threshold: f32) -> bool {
+        let total = self.total_records.load(Ordering::SeqCst);
+        if total == 0 {
+            return false;
+        }
+        let deleted = self.deleted_records.load(Ordering::SeqCst);
+        deleted as f32 / total as f32 >= threshold
+    }
+
+    fn merge_data_files(
+        &self,
+        merge_file_ids: &[u32],
+        output_ids: RangeInclusive<u32>,
+        partial: bool,
+    ) -> Result<()> {
+        let merge_path = self.options().dir_path.join(MERGE_DIR_NAME);
+        if merge_path.is_dir() {
+            fs::remove_dir_all(&merge_path).map_err(|_| Errors::FailedToMergeDataFiles)?;
+        }
+        fs::create_dir_all(&merge_path).map_err(|_| Errors::FailedToMergeDataFiles)?;
+        let merge_opts = Options {
+            dir_path: merge_path.clone(),
+            ..(*self.options()).clone()
+        };
+        let mut unmerged_file_ids: Vec<u32> = self
+            .older_files
+            .read()
+            .keys()
+            .filter(|file_id| !merge_file_ids.contains(file_id))
+            .copied()
+            .collect();
+        unmerged_file_ids.sort();
+
+        let mut output = MergeOutput {
+            merge_file: DataFile::new(&merge_opts, *output_ids.start())?,
+            output_files: Vec::new(),
+            last_output_id: *output_ids.end(),
+            rewritten_records: Vec::new(),
+            deleted_keys: Vec::new(),
+        };
+        let mut moved_records = Vec::new();
+        // 被合并的文件中读到的记录类型，合并完成之后从计数中减去
+        let mut removed_records = Vec::new();
+        // 部分合并时记录批次开始标记所在的文件，以及有记录位于没有合并的文件中的批次
+        let mut txn_begin_files: HashMap<usize, u32> = HashMap::new();
+        let mut split_txns: HashSet<usize> = HashSet::new();
+        let mut split_txn_max_file_id = 0;
+        for file_id in merge_file_ids.iter() {
+            // 使用单独的文件句柄读取，避免长时间持有 older_files 的锁
+            let data_file = DataFile::new(&self.options(), *file_id)?;
+            let mut offset = 0;
+            loop {
+                let (log_record, size) = match data_file.read_log_record(offset) {
+                    Ok(result) => (result.record, result.size),
+                    Err(e) => {
+                        if e == Errors::ReadDataFileEOF {
+                            break;
+                        }
+                        return Err(e);
+                    }
+                };
+                let old_pos = LogRecordPos {
+                    file_id: *file_id,
+                    offset,
+                };
+                offset += size as u64;
+                removed_records.push(log_record.rec_type);
+
+                if partial {
+                    let (_, seq_no) = parse_log_record_key(log_record.key.clone())?;
+                    match log_record.rec_type {
+                        LogRecordType::TxnBegin => {
+                            txn_begin_files.insert(seq_no, *file_id);
+                            continue;
+                        }
+                        LogRecordType::TxnFinish => {
+                            let split = match txn_begin_files.get(&seq_no) {
+                                Some(begin_fid) => unmerged_file_ids
+                                    .iter()
+                                    .any(|fid| fid > begin_fid && fid < file_id),
+                                // 没有开始标记时无法确定批次的起点
+                                None => true,
+                            };
+                            if split {
+                                split_txns.insert(seq_no);
+                                split_txn_max_file_id = *file_id;
+                            }
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+                self.rewrite_live_record(
+                    &mut output,
+                    &merge_opts,
+                    log_record,
+                    old_pos,
+                    partial,
+                    &mut moved_records,
+                )?;
+            }
+        }
+
+        // 结束标记被合并之后，这些批次在没有合并的文件中的记录在恢复时会被忽略，
+        // 其中仍然有效的数据需要写入新的文件
+        if !split_txns.is_empty() {
+            for file_id in unmerged_file_ids
+                .iter()
+                .filter(|fid| **fid < split_txn_max_file_id)
+            {
+                let data_file = DataFile::new(&self.options(), *file_id)?;
+                let mut offset = 0;
+                loop {
+                    let (log_record, size) = match data_file.read_log_record(offset) {
+                        Ok(result) => (result.record, result.size),
+                        Err(e) => {
+                            if e == Errors::ReadDataFileEOF {
+                                break;
+                            }
+                            return Err(e);
+                        }
+                    };
+                    let old_pos = LogRecordPos {
+                        file_id: *file_id,
+                        offset,
+                    };
+                    offset += size as u64;
+
+                    let (_, seq_no) = parse_log_record_key(log_record.key.clone())?;
+                    if !split_txns.contains(&seq_no) {
+                        continue;
+                    }
+                    self.rewrite_live_record(
+                        &mut output,
+                        &merge_opts,
+                        log_record,
+                        old_pos,
+                        partial,
+                        &mut moved_records,
+                    )?;
+                }
+            }
+        }
+        output.merge_file.sync()?;
+        let rewritten_records = output.rewritten_records;
+        let deleted_keys = output.deleted_keys;
+        let mut output_files = output.output_files;
+        output_files.push(output.merge_file);
+
+        // 为每个合并后的文件生成布隆过滤器，包含文件中的数据和删除记录的 key
+        let mut file_keys: HashMap<u32, Vec<&[u8]>> = HashMap::new();
+        for output_file in output_files.iter() {
+            file_keys.entry(output_file.get_file_id()).or_default();
+        }
+        for (key, _, new_pos) in moved_records.iter() {
+            file_keys.entry(new_pos.file_id).or_default().push(key);
+        }
+        for (key, file_id) in deleted_keys.iter() {
+            file_keys.entry(*file_id).or_default().push(key);
+        }
+        for (file_id, keys) in file_keys {
+            let mut filter = BloomFilter::new(keys.len());
+            for key in keys {
+                filter.add(key);
+            }
+            filter.save(get_bloom_file_name(&merge_opts, file_id))?;
+        }
+
+        // 将合并后的文件移动到数据目录中
+        {
+            let mut older_files = self.older_files.write();
+            for output_file in output_files {
+                let file_id = output_file.get_file_id();
+                let write_off = output_file.get_write_off();
+                std::mem::drop(output_file);
+                if write_off == 0 {
+                    continue;
+                }
+                fs::create_dir_all(get_data_file_dir(&self.options(), file_id))
+                    .map_err(|_| Errors::FailedToMergeDataFiles)?;
+                let src = get_data_file_name(&merge_opts, file_id);
+                let dest = get_data_file_name(&self.options(), file_id);
+                fs::rename(src, dest).map_err(|_| Errors::FailedToMergeDataFiles)?;
+                let src = get_bloom_file_name(&merge_opts, file_id);
+                let dest = get_bloom_file_name(&self.options(), file_id);
+                fs::rename(src, dest).map_err(|_| Errors::FailedToMergeDataFiles)?;
+
+                let data_file = DataFile::new(&self.options(), file_id)?;
+                data_file.set_write_offset(write_off);
+                data_file.set_synced_offset(write_off);
+                data_file.save_footer()?;
+                older_files.insert(file_id, data_file);
+                self.touch_older_file(&older_files, file_id);
+            }
+        }
+
+        // 更新索引，前台在合并期间写入的新数据不会被覆盖
+        for (key, old_pos, new_pos) in moved_records {
+            self.index.put_if_pos_matches(key, old_pos, new_pos);
+        }
+
+        // 索引文件中被合并的文件的位置不再有效，下一次启动时重新生成
+        IndexSidecar::discard(&mut self.index_sidecar.lock());
+
+        for rec_type in rewritten_records {
+            self.count_records(rec_type, true);
+        }
+        for rec_type in removed_records {
+            self.count_records(rec_type, false);
+        }
+
+        // 删除被合并的文件
+        let mut older_files = self.older_files.write();
+        for file_id in merge_file_ids.iter() {
+            older_files.remove(file_id);
+            let file_name = get_data_file_name(&self.options(), *file_id);
+            if let Err(e) = fs::remove_file(file_name) {
+                warn!("failed to remove merged data file {}: {}", file_id, e);
+            }
+            let bloom_file_name = get_bloom_file_name(&self.options(), *file_id);
+            if bloom_file_name.is_file() {
+                if let Err(e) = fs::remove_file(bloom_file_name) {
+                    warn!("failed to remove bloom filter file {}: {}", file_id, e);
+                }
+            }
+            let footer_file_name = get_footer_file_name(&self.options(), *file_id);
+            if footer_file_name.is_file() {
+                if let Err(e) = fs::remove_file(footer_file_name) {
+                    warn!("failed to remove footer file {}: {}", file_id, e);
+                }
+            }
+        }
+        if let Err(e) = fs::remove_dir_all(&merge_path) {
+            warn!("failed to remove merge directory: {}", e);
+        }
+        Ok(())
+    }
+
+    /// 只保留索引中仍然指向该位置的数据，keep_tombstones 时同时保留已经删除的 key 的删除记录
+    fn rewrite_live_record(
+        &self,
+        output: &mut MergeOutput,
+        merge_opts: &Options,
+        log_record: LogRecord,
+        old_pos: LogRecordPos,
+        keep_tombstones: bool,
+        moved_records: &mut Vec<(Vec<u8>, LogRecordPos, LogRecordPos)>,
+    ) -> Result<()> {
+        let (real_key, _) = parse_log_record_key(log_record.key)?;
+        match self.index.get(real_key.clone()) {
+            Some(index_pos) if index_pos == old_pos => {}
+            None if keep_tombstones && log_record.rec_type == LogRecordType::DElETED => {}
+            _ => return Ok(()),
+        }
+        let rewrite_record = LogRecord {
+            key: log_record_key_with_seq(real_key.clone(), NON_TRANSACTION_SEQ_NO),
+            value: log_record.value,
+            rec_type: log_record.rec_type,
+            flags: log_record.flags,
+        };
+        let enc_record = rewrite_record.encode_with_layout(self.options().record_layout);
+
+        // 写满之后切换到下一个文件，预留的 id 之后是新的活跃文件，id 用完时不能继续写入
+        let merge_file = &mut output.merge_file;
+        if merge_file.get_write_off() > 0
+            && merge_file.get_write_off() + enc_record.len() as u64 > self.options().data_file_size
+        {
+            if merge_file.get_file_id() >= output.last_output_id {
+                warn!("no file id left for merged records");
+                return Err(Errors::FailedToMergeDataFiles);
+            }
+            merge_file.sync()?;
+            let next_id = merge_file.get_file_id() + 1;
+            let full_file = std::mem::replace(merge_file, DataFile::new(merge_opts, next_id)?);
+            output.output_files.push(full_file);
+        }
+        let write_off = merge_file.get_write_off();
+        merge_file.write(&enc_record)?;
+        self.written_bytes.fetch_add(enc_record.len() as u64, Ordering::SeqCst);
+        output.rewritten_records.push(rewrite_record.rec_type);
+        // 删除记录不在索引中，只需要加入布隆过滤器
+        if rewrite_record.rec_type == LogRecordType::DElETED {
+            output.deleted_keys.push((real_key, merge_file.get_file_id()));
+        } else {
+            moved_records.push((
+                real_key,
+                old_pos,
+                LogRecordPos {
+                    file_id: merge_file.get_file_id(),
+                    offset: write_off,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// 封存活跃文件，返回需要合并的文件 id 以及为合并后的文件预留的 id
+    ///
+    /// file_ids 为 None 时合并所有旧文件以及被封存的活跃文件，否则只合并指定的旧文件
+    fn rotate_for_merge(
+        &self,
+        file_ids: Option<Vec<u32>>,
+    ) -> Result<Option<(Vec<u32>, RangeInclusive<u32>)>> {
+        // 等待进行中的写入完成索引更新，避免刚写入的数据被当作无效数据
+        let _write_lock = self.write_lock.write();
+        let mut pending_index = self.pending_index.lock();
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+        let sealed_fid = active_file.get_file_id();
+        let mut merge_file_ids = match file_ids {
+            Some(mut file_ids) => {
+                file_ids.sort();
+                file_ids.dedup();
+                for file_id in file_ids.iter() {
+                    if *file_id == sealed_fid {
+                        return Err(Errors::InvalidMergeFileId);
+                    }
+                    if !older_files.contains_key(file_id) {
+                        return Err(Errors::DataFileNotFound);
+                    }
+                }
+                file_ids
+            }
+            None => {
+                // 只有活跃文件时没有可以合并的旧文件
+                if older_files.is_empty() {
+                    return Ok(None);
+                }
+                let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+                file_ids.push(sealed_fid);
+                file_ids
+            }
+        };
+        merge_file_ids.sort();
+        active_file.sync()?;
+        // 合并只保留索引指向的数据，需要先把尚未更新的位置写入索引
+        self.apply_pending_index(&mut pending_index);
+
+        // 为合并后的文件预留 id，data_file_size 变小之后合并后的文件可能比被合并的文件多。
+        // 相邻的两个合并后的文件大小之和一定超过 data_file_size，按照数据量计算文件数量的上限，
+        // 部分合并时被拆分的批次的记录可能来自更早的没有合并的文件，这些文件同样计算在内
+        let max_merge_fid = *merge_file_ids.last().unwrap();
+        let mut merge_bytes: u64 = older_files
+            .iter()
+            .filter(|(file_id, _)| **file_id <= max_merge_fid)
+            .map(|(_, data_file)| data_file.get_write_off())
+            .sum();
+        if max_merge_fid == sealed_fid {
+            merge_bytes += active_file.get_write_off();
+        }
+        let max_output_files = 2 * merge_bytes / self.options().data_file_size + 1;
+        let output_count = max_output_files.max(merge_file_ids.len() as u64) as u32;
+        let new_active_fid = sealed_fid + 1 + output_count;
+        let new_file = DataFile::new_active(&self.options(), new_active_fid)?;
+        let old_file = std::mem::replace(&mut *active_file, new_file);
+        older_files.insert(sealed_fid, old_file);
+        self.touch_older_file(&older_files, sealed_fid);
+        Ok(Some((merge_file_ids, sealed_fid + 1..=sealed_fid + output_count)))
+    }
+}
+
+/// 合并过程中正在写入的文件以及已经写满的文件
+struct MergeOutput {
+    merge_file: DataFile,
+    output_files: Vec<DataFile>,
+    last_output_id: u32,
+    /// 写入合并后的文件的记录类型
+    rewritten_records: Vec<LogRecordType>,
+    /// 保留的删除记录的 key 以及所在的文件
+    deleted_keys: Vec<(Vec<u8>, u32)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::Bytes;
+
+    use crate::util::rand_kv::{get_test_key, get_test_value};
+
+    use super::*;
+    #[test]
+    fn test_merge_size_balanced_files() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-merge-balanced"),
+            data_file_size: 4 * 1024,
+            sync_writes: false,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for round in 0..4 {
+            for i in 0..500 {
+                let value = Bytes::from(format!("{}-{:?}", round, get_test_value(i)));
+                engine.put(get_test_key(i as i32), value).unwrap();
+            }
+        }
+        for i in 0..100 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+        let files_before = fs::read_dir(&opts.dir_path).unwrap().count();
+
+        engine.merge().unwrap();
+
+        // 合并后的文件大小都不超过 data_file_size
+        let mut files_after = 0;
+        for entry in fs::read_dir(&opts.dir_path).unwrap() {
+            let entry = entry.unwrap();
+            assert!(entry.file_type().unwrap().is_file());
+            assert!(entry.metadata().unwrap().len() <= opts.data_file_size);
+            files_after += 1;
+        }
+        assert!(files_after < files_before);
+
+        // 合并之后数据依然正确，并且可以继续写入
+        let check = |engine: &Engine| {
+            for i in 0..100 {
+                let res = engine.get(get_test_key(i));
+                assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+            }
+            for i in 100..500 {
+                let value = Bytes::from(format!("3-{:?}", get_test_value(i as usize)));
+                assert_eq!(value, engine.get(get_test_key(i)).unwrap());
+            }
+        };
+        check(&engine);
+        engine.put(get_test_key(1000), get_test_value(1000)).unwrap();
+        assert_eq!(401, engine.list_keys().unwrap().len());
+
+        // 重启之后数据依然正确
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+        assert_eq!(get_test_value(1000), engine2.get(get_test_key(1000)).unwrap());
+        assert_eq!(401, engine2.list_keys().unwrap().len());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_merge_after_shrinking_file_size() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-merge-shrink"),
+            data_file_size: 16 * 1024,
+            sync_writes: false,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..500 {
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        // data_file_size 变小之后合并，合并后的文件比被合并的文件多
+        let small_opts = Options {
+            data_file_size: 1024,
+            ..opts.clone()
+        };
+        let engine2 = Engine::open(small_opts.clone()).expect("failed to open engine");
+        let files_before = engine2.data_file_ids().len();
+        engine2.merge().unwrap();
+        assert!(engine2.data_file_ids().len() > files_before);
+        for entry in fs::read_dir(&opts.dir_path).unwrap() {
+            let entry = entry.unwrap();
+            assert!(entry.metadata().unwrap().len() <= small_opts.data_file_size);
+        }
+
+        // 合并后的文件和新的活跃文件的 id 不冲突
+        engine2.put(get_test_key(1000), get_test_value(1000)).unwrap();
+        engine2.close().unwrap();
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(small_opts.clone()).expect("failed to open engine");
+        for i in 0..500 {
+            assert_eq!(get_test_value(i as usize), engine3.get(get_test_key(i)).unwrap());
+        }
+        assert_eq!(get_test_value(1000), engine3.get(get_test_key(1000)).unwrap());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_merge_files() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-merge-files"),
+            data_file_size: 4 * 1024,
+            sync_writes: false,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let active_fid = |engine: &Engine| engine.active_file.read().get_file_id();
+        let file_bytes = |file_id: u32| fs::read(get_data_file_name(&opts, file_id)).unwrap();
+
+        // 文件 0 中反复覆盖写入少量的 key
+        engine.put(Bytes::from("deleted"), get_test_value(0)).unwrap();
+        let mut i = 0;
+        while active_fid(&engine) == 0 {
+            engine.put(get_test_key(i % 10), get_test_value(i as usize)).unwrap();
+            i += 1;
+        }
+        // 文件 1 中删除文件 0 中写入的 key
+        engine.delete(Bytes::from("deleted")).unwrap();
+        let mut j = 100;
+        while active_fid(&engine) == 1 {
+            engine.put(get_test_key(j), get_test_value(j as usize)).unwrap();
+            j += 1;
+        }
+        // 跨越多个文件的批次
+        let wb = engine
+            .new_write_batch(crate::options::WriteBatchOptions {
+                max_batch_num: 1000,
+                sync_writes: false,
+            })
+            .unwrap();
+        for k in 10000..10150 {
+            wb.put(get_test_key(k), get_test_value(k as usize)).unwrap();
+        }
+        wb.commit().unwrap();
+        let finish_fid = active_fid(&engine);
+        assert!(finish_fid > 3);
+        engine.put(get_test_key(20000), get_test_value(20000)).unwrap();
+
+        // 1.不能合并活跃文件或者不存在的文件
+        let res1 = engine.merge_files(vec![active_fid(&engine)]);
+        assert_eq!(Errors::InvalidMergeFileId, res1.err().unwrap());
+        let res2 = engine.merge_files(vec![1000]);
+        assert_eq!(Errors::DataFileNotFound, res2.err().unwrap());
+        assert!(engine.merge_files(vec![]).is_ok());
+
+        // 2.只合并文件 1，删除记录需要保留，否则文件 0 中的旧数据会在重启后恢复
+        engine.merge_files(vec![1]).unwrap();
+        assert!(!get_data_file_name(&opts, 1).is_file());
+        assert_eq!(Errors::KeyNotFound, engine.get(Bytes::from("deleted")).err().unwrap());
+
+        // 3.只合并覆盖写入最多的文件 0，空间被回收，其他文件保持不变
+        let size_before = engine.older_files.read().get(&0).unwrap().get_write_off();
+        let others: Vec<(u32, Vec<u8>)> = engine
+            .data_file_ids()
+            .into_iter()
+            .filter(|fid| *fid != 0 && *fid != active_fid(&engine))
+            .map(|fid| (fid, file_bytes(fid)))
+            .collect();
+        let files_before = engine.data_file_ids();
+        engine.merge_files(vec![0]).unwrap();
+        assert!(!get_data_file_name(&opts, 0).is_file());
+        let new_files: Vec<u32> = engine
+            .data_file_ids()
+            .into_iter()
+            .filter(|fid| !files_before.contains(fid))
+            .collect();
+        let size_after: u64 = new_files
+            .iter()
+            .filter_map(|fid| engine.older_files.read().get(fid).map(|f| f.get_write_off()))
+            .sum();
+        assert!(size_after * 5 < size_before);
+        for (fid, bytes) in others.iter() {
+            assert_eq!(*bytes, file_bytes(*fid));
+        }
+
+        // 4.只合并批次结束标记所在的文件，批次中其他文件的数据依然有效
+        engine.merge_files(vec![finish_fid]).unwrap();
+
+        let check = |engine: &Engine| {
+            assert_eq!(Errors::KeyNotFound, engine.get(Bytes::from("deleted")).err().unwrap());
+            for k in 0..10 {
+                assert!(engine.get(get_test_key(k)).is_ok());
+            }
+            for k in 100..j {
+                assert_eq!(get_test_value(k as usize), engine.get(get_test_key(k)).unwrap());
+            }
+            for k in 10000..10150 {
+                assert_eq!(get_test_value(k as usize), engine.get(get_test_key(k)).unwrap());
+            }
+            assert_eq!((10 + (j - 100) + 150 + 1) as usize, engine.list_keys().unwrap().len());
+        };
+        check(&engine);
+
+        // 重启之后数据依然正确
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_merge_without_older_files() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-merge-without-older-files"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        // 1.空的数据库
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.merge().unwrap();
+        engine.merge().unwrap();
+        assert_eq!(vec![0], engine.data_file_ids());
+
+        // 2.只有活跃文件，文件不会被封存或者删除
+        engine.put(get_test_key(1), get_test_value(1)).unwrap();
+        let write_off = engine.active_file.read().get_write_off();
+        engine.merge().unwrap();
+        assert_eq!(vec![0], engine.data_file_ids());
+        assert_eq!(write_off, engine.active_file.read().get_write_off());
+        assert!(!opts.dir_path.join(MERGE_DIR_NAME).exists());
+        assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+        // 重启之后数据依然正确
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+    #[test]
+    fn test_merge_trigger_by_deletes() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-merge-trigger-by-deletes"),
+            data_file_size: 4 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!engine.should_merge_by_deletes(0.0));
+
+        // 1.写入 100 条数据之后删除其中的 60 条，删除记录占 60 / 160
+        for i in 0..100 {
+            engine.put(get_test_key(i), Bytes::from("v")).unwrap();
+        }
+        assert!(!engine.should_merge_by_deletes(0.1));
+        for i in 0..60 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+        assert!(engine.should_merge_by_deletes(0.3));
+        assert!(!engine.should_merge_by_deletes(0.4));
+
+        // 2.重启之后从数据文件中恢复计数
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(160, engine2.total_records.load(Ordering::SeqCst));
+        assert_eq!(60, engine2.deleted_records.load(Ordering::SeqCst));
+        assert!(engine2.should_merge_by_deletes(0.3));
+
+        // 3.合并之后删除记录被清理
+        engine2.merge().unwrap();
+        assert_eq!(40, engine2.total_records.load(Ordering::SeqCst));
+        assert!(!engine2.should_merge_by_deletes(0.01));
+        engine2.close().unwrap();
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(40, engine3.total_records.load(Ordering::SeqCst));
+        assert_eq!(0, engine3.deleted_records.load(Ordering::SeqCst));
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{
+    data::{blob_file::BlobFile, index_sidecar::INDEX_SIDECAR_FILE_NAME},
+    db::{find_data_files, read_record_layout, Engine},
+    errors::{Errors, Result},
+    options::RecordLayout,
+};
+
+/// 数据目录的元信息，只读取目录中的文件列表和元数据文件，不需要打开数据库
+#[derive(Clone, Debug, PartialEq)]
+pub struct DbMetadata {
+    /// 目录中保存的记录布局，没有保存布局的旧数据库为 None
+    pub record_layout: Option<RecordLayout>,
+    /// 数据文件的 id，从小到大排列
+    pub data_file_ids: Vec<u32>,
+    /// 是否存在保存大 value 的 blob 文件
+    pub blob_file: bool,
+    /// 是否存在 index_sidecar 的索引文件
+    pub index_sidecar: bool,
+}
+
+/// 读取 dir 中数据库的元信息，目录不存在时返回 DatabaseNotFound
+pub fn read_metadata(dir: PathBuf) -> Result<DbMetadata> {
+    if !dir.is_dir() {
+        return Err(Errors::DatabaseNotFound);
+    }
+    let mut data_file_paths = HashMap::new();
+    find_data_files(&dir, &mut data_file_paths)?;
+    let mut data_file_ids: Vec<u32> = data_file_paths.into_keys().collect();
+    data_file_ids.sort();
+    Ok(DbMetadata {
+        record_layout: read_record_layout(&dir)?,
+        data_file_ids,
+        blob_file: BlobFile::exists(&dir),
+        index_sidecar: dir.join(INDEX_SIDECAR_FILE_NAME).is_file(),
+    })
+}
+
+impl Engine {
+    /// 读取数据目录当前的元信息
+    pub fn metadata_info(&self) -> Result<DbMetadata> {
+        read_metadata(self.options().dir_path.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+    #[test]
+    fn test_read_metadata() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-read-metadata"),
+            data_file_size: 64 * 1024 * 1024,
+            record_layout: RecordLayout::FixedWidth,
+            index_sidecar: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Errors::DatabaseNotFound,
+            read_metadata(opts.dir_path.clone()).err().unwrap()
+        );
+
+        // 1.打开时写入元数据
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(get_test_key(1), get_test_value(1)).unwrap();
+        let expected = DbMetadata {
+            record_layout: Some(RecordLayout::FixedWidth),
+            data_file_ids: vec![0],
+            blob_file: false,
+            index_sidecar: true,
+        };
+        assert_eq!(expected, engine.metadata_info().unwrap());
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        // 2.关闭之后不打开数据库也可以读取
+        assert_eq!(expected, read_metadata(opts.dir_path.clone()).unwrap());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+}
@@ -1,15 +1,117 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
+
+use crate::fio::IOFactory;
+
+/// 写入和读取之前对 key 的转换，例如统一转换为小写
+pub type KeyNormalizer = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
 
 #[derive(Clone)]
 pub struct Options {
     pub dir_path: PathBuf,
     pub data_file_size: u64,
     pub sync_writes: bool,
+    /// 没有开启 sync_writes 时，每追加 n 条记录（包括批量写入中的记录和标记）持久化一次，
+    /// None 表示只在文件写满、显式 sync 或者关闭时持久化
+    pub sync_interval_writes: Option<u64>,
     pub index_type: IndexType,
+    /// key 的最大长度，None 表示不限制
+    pub max_key_size: Option<usize>,
+    /// 是否在内存中记录数据变更（包括删除），用于 changes_since
+    pub enable_change_log: bool,
+    /// 内存中保留的变更记录的最大条数，超过之后丢弃最早的变更
+    pub max_change_log_len: usize,
+    /// 启动时校验所有记录的 crc，并将校验失败的记录汇总到 OpenReport 中
+    pub verify_on_open: bool,
+    /// 启动时遇到损坏记录的处理方式
+    pub recover_mode: RecoverMode,
+    /// open_or_repair 遇到损坏的数据时的修复方式
+    pub repair_policy: RepairPolicy,
+    /// 超过该大小的 value 单独存放在 blob 文件中，None 表示全部存放在数据文件中
+    ///
+    /// blob 文件只追加写入，合并不会回收其中被覆盖或者删除的 value 占用的空间
+    pub inline_value_threshold: Option<usize>,
+    /// get 只返回已经持久化的数据，未 sync 的写入视为不存在
+    pub read_synced_only: bool,
+    /// IO 返回 WouldBlock 时的最大重试次数
+    pub io_retries: u32,
+    /// 数据目录不存在时是否自动创建
+    pub create_if_missing: bool,
+    /// 数据目录中已经存在数据文件时打开失败
+    pub error_if_exists: bool,
+    /// 每个子目录中存放的数据文件个数，文件按照 file_id / files_per_dir 分散到子目录中，
+    /// None 表示所有文件都存放在 dir_path 下
+    pub files_per_dir: Option<u32>,
+    /// 只追加模式，每个 key 只能写入一次，不允许覆盖和删除
+    pub append_only: bool,
+    /// 读写活跃文件发生竞争时的优先级
+    pub lock_priority: LockPriority,
+    /// put/delete 之后不立即更新索引，而是在下一次 sync 成功之后再更新，
+    /// 在此之前只有 get 可以读到这些写入，list_keys、迭代器等只能看到已经持久化的数据
+    pub defer_index_until_sync: bool,
+    /// 按照 value 的前 n 个字节建立二级索引，None 表示不建立
+    pub value_index_prefix_len: Option<usize>,
+    /// 启动时缓存的未提交事务记录的最大条数，超过之后按照 recover_mode 报错或者丢弃最早的事务
+    pub max_pending_txn_records: usize,
+    /// 旧数据文件同时打开的文件句柄的最大数量，超过之后关闭最久没有使用的句柄，None 表示不限制
+    pub max_open_files: Option<usize>,
+    /// put 的 value 与当前的 value 相同时跳过写入
+    pub skip_identical_writes: bool,
+    /// 新建数据库时使用的记录布局，已有的数据库按照目录中保存的布局读取
+    pub record_layout: RecordLayout,
+    /// 新建活跃文件时预先分配 data_file_size 大小的空间，减少文件碎片
+    pub preallocate: bool,
+    /// sync 时把数据文件的记录数和摘要写入单独的摘要文件，打开时校验每个文件是否与摘要一致，
+    /// 不一致的文件记录在 OpenReport 中
+    pub file_footer: bool,
+    /// 在单独的索引文件中追加记录每次写入的 key 和位置，启动时按照索引文件恢复索引，
+    /// 只扫描索引文件之后写入的记录。开启 enable_change_log、value_index_prefix_len
+    /// 或者 verify_on_open 时需要读取 value，仍然扫描所有的数据文件
+    pub index_sidecar: bool,
+    /// 自定义的存储后端，设置之后数据文件和 blob 文件的读写都通过它创建的 IOManager 进行，
+    /// 目录和文件列表仍然使用本地文件系统。None 表示使用本地文件
+    pub io_factory: Option<IOFactory>,
+    /// put、get、delete 等操作之前对 key 的转换，数据文件中保存的是转换之后的 key，
+    /// 迭代器和 list_keys 返回的也是转换之后的 key。转换需要是幂等的，
+    /// 已有的数据库修改转换方式之后，之前写入的 key 可能无法再读取
+    pub key_normalizer: Option<KeyNormalizer>,
+}
+/// 记录头部中 key 和 value 长度的编码方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordLayout {
+    /// varint 编码，占用的空间最小
+    Varint,
+    /// 固定 u32 的 key 长度和 u64 的 value 长度，不需要解析就可以计算 value 的偏移
+    FixedWidth,
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecoverMode {
+    /// 遇到损坏的记录时打开失败
+    Strict,
+    /// 跳过损坏的记录继续恢复
+    SkipCorrupted,
+}
+/// 打开数据库失败之后的修复方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepairPolicy {
+    /// 把可以读取的有效数据写入新的目录，再用新的目录替换原来的目录，
+    /// 原来的目录保留为备份，路径记录在 RepairReport::backup_dir 中
+    Salvage,
+    /// 以 RecoverMode::SkipCorrupted 并开启 verify_on_open 重新打开，数据文件保持不变
+    SkipCorrupted,
+}
+/// 活跃文件读写锁的优先级
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockPriority {
+    /// 有写操作在等待时，新的读操作需要排在写操作之后，避免写操作饥饿
+    WriterPreferred,
+    /// 只要已经有读操作持有锁，新的读操作就可以直接进入，读延迟更低，但是写操作可能饥饿
+    ReaderPreferred,
 }
 #[derive(Clone)]
 pub enum IndexType {
     BTree,
+    /// 只适合点查的场景，list_keys 返回的 key 是无序的
+    HashMap,
     SkipList,
 }
 impl Default for Options {
@@ -18,28 +120,49 @@ impl Default for Options {
             dir_path: std::env::temp_dir().join("bitcask-rs"),
             data_file_size: 1024 * 1024 * 8,
             sync_writes: true,
+            sync_interval_writes: None,
             index_type: IndexType::BTree,
+            max_key_size: None,
+            enable_change_log: false,
+            max_change_log_len: 1024 * 1024,
+            verify_on_open: false,
+            recover_mode: RecoverMode::Strict,
+            repair_policy: RepairPolicy::Salvage,
+            inline_value_threshold: None,
+            read_synced_only: false,
+            io_retries: 3,
+            create_if_missing: true,
+            error_if_exists: false,
+            files_per_dir: None,
+            append_only: false,
+            lock_priority: LockPriority::WriterPreferred,
+            defer_index_until_sync: false,
+            value_index_prefix_len: None,
+            max_pending_txn_records: 1024 * 1024,
+            max_open_files: None,
+            skip_identical_writes: false,
+            record_layout: RecordLayout::Varint,
+            preallocate: false,
+            file_footer: false,
+            index_sidecar: false,
+            io_factory: None,
+            key_normalizer: None,
         }
     }
 }
+#[derive(Default)]
 pub struct IteratorOptions {
     pub prefix: Vec<u8>,
+    /// 只返回以 suffix 结尾的 key，无法利用 key 的顺序，需要逐个检查所有 key
+    pub suffix: Vec<u8>,
     pub reverse: bool,
 }
-impl Default for IteratorOptions {
-    fn default() -> Self {
-        Self {
-            prefix: Default::default(),
-            reverse: Default::default(),
-        }
-    }
-}
 
 /// 批量写数据配置项 
 pub struct WriteBatchOptions {
     /// 一个批次当中的最大数据项 
     pub max_batch_num: usize,
-    /// 
+    /// 提交时是否持久化
     pub sync_writes: bool,
 }
 
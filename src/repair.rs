@@ -0,0 +1,542 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use bytes::Bytes;
+use log::warn;
+
+use crate::{
+    batch::{parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    data::{
+        blob_file::{BlobFile, BlobPointer},
+        data_file::DataFile,
+        log_record::{LogRecord, LogRecordType},
+    },
+    db::{check_options, find_data_files, read_record_layout, Engine},
+    errors::{Errors, Result},
+    options::{Options, RecordLayout, RecoverMode, RepairPolicy},
+};
+
+/// 修复数据库的结果
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepairReport {
+    /// 成功读取的记录数
+    pub recovered_records: usize,
+    /// 损坏或者被截断而丢弃的记录数
+    pub dropped_records: usize,
+    /// 写入新数据库的有效数据条数
+    pub live_keys: usize,
+    /// 没有结束标记、被丢弃的批次数量
+    pub orphaned_batches: usize,
+    /// 没有结束标记的批次占用的字节数
+    pub orphaned_bytes: u64,
+    /// open_or_repair 替换数据目录时保留的原来的目录，不会被自动删除
+    pub backup_dir: Option<PathBuf>,
+}
+
+impl Engine {
+    /// 从损坏的数据目录 src 中读取所有可以读取的记录，将有效的数据写入新的数据库 dest
+    ///
+    /// crc 校验失败的记录会被跳过，文件末尾被截断的记录会被丢弃，dest 中不能已经存在数据。
+    /// dest 使用默认的配置，需要指定配置时使用 repair_with_options
+    pub fn repair(src: PathBuf, dest: PathBuf) -> Result<RepairReport> {
+        Engine::repair_with_options(
+            src,
+            Options {
+                dir_path: dest,
+                ..Default::default()
+            },
+        )
+    }
+    /// 与 repair 相同，修复后的数据库按照 opts 写入 opts.dir_path
+    ///
+    /// src 只会被读取，其中的文件不会被移动或者修改；读取时同样使用 opts 中的 io_factory，
+    /// 记录的 flags 会被保留
+    pub fn repair_with_options(src: PathBuf, opts: Options) -> Result<RepairReport> {
+        if let Some(e) = check_options(&opts) {
+            return Err(e);
+        }
+        if !src.is_dir() {
+            return Err(Errors::DatabaseNotFound);
+        }
+        let src_opts = Options {
+            dir_path: src.clone(),
+            record_layout: read_record_layout(&src)?.unwrap_or(RecordLayout::Varint),
+            io_factory: opts.io_factory.clone(),
+            ..Default::default()
+        };
+        // 按照文件实际所在的位置读取，不按照 opts 的目录布局移动文件
+        let mut data_file_paths = HashMap::new();
+        find_data_files(&src, &mut data_file_paths)?;
+        let mut file_ids: Vec<u32> = data_file_paths.keys().copied().collect();
+        file_ids.sort();
+        let mut data_files = Vec::with_capacity(file_ids.len());
+        for file_id in file_ids {
+            let path = data_file_paths.remove(&file_id).unwrap();
+            data_files.push(DataFile::open_path(&src_opts, file_id, path)?);
+        }
+        let blob_file = match BlobFile::exists(&src) {
+            true => Some(BlobFile::new(&src_opts)?),
+            false => None,
+        };
+
+        let mut report = RepairReport::default();
+        let mut live_records: HashMap<Vec<u8>, LogRecord> = HashMap::new();
+        let mut transaction_records: HashMap<usize, Vec<LogRecord>> = HashMap::new();
+        let mut batch_bytes: HashMap<usize, u64> = HashMap::new();
+        for data_file in data_files.iter() {
+            let mut offset = 0;
+            loop {
+                let (log_record, size) = match data_file.read_log_record_unverified(offset) {
+                    Ok((result, true)) => (result.record, result.size),
+                    Ok((result, false)) => {
+                        warn!(
+                            "drop corrupted log record, file id:{}, offset:{}",
+                            data_file.get_file_id(),
+                            offset
+                        );
+                        report.dropped_records += 1;
+                        offset += result.size as u64;
+                        continue;
+                    }
+                    Err(Errors::ReadDataFileEOF) => break,
+                    Err(_) => {
+                        // 头部无法解析，文件剩余的部分都无法读取
+                        report.dropped_records += 1;
+                        break;
+                    }
+                };
+                offset += size as u64;
+                let (real_key, seq_no) = match parse_log_record_key(log_record.key) {
+                    Ok(result) => result,
+                    Err(_) => {
+                        report.dropped_records += 1;
+                        continue;
+                    }
+                };
+                report.recovered_records += 1;
+                let log_record = LogRecord {
+                    key: real_key,
+                    value: log_record.value,
+                    rec_type: log_record.rec_type,
+                    flags: log_record.flags,
+                };
+
+                if seq_no == NON_TRANSACTION_SEQ_NO {
+                    apply_record(&mut live_records, log_record);
+                } else if log_record.rec_type == LogRecordType::TxnFinish {
+                    for txn_record in transaction_records.remove(&seq_no).unwrap_or_default() {
+                        apply_record(&mut live_records, txn_record);
+                    }
+                    batch_bytes.remove(&seq_no);
+                } else {
+                    *batch_bytes.entry(seq_no).or_default() += size as u64;
+                    transaction_records
+                        .entry(seq_no)
+                        .or_default()
+                        .push(log_record);
+                }
+            }
+        }
+
+        report.orphaned_batches = batch_bytes.len();
+        report.orphaned_bytes = batch_bytes.values().sum();
+
+        let dest_opts = Options {
+            sync_writes: false,
+            create_if_missing: true,
+            error_if_exists: true,
+            ..opts
+        };
+        let engine = Engine::open(dest_opts)?;
+        for (key, log_record) in live_records {
+            let value = match log_record.rec_type {
+                LogRecordType::BlobPointer => {
+                    // blob 文件中的 value 无法读取时丢弃这条数据
+                    let value = BlobPointer::decode(&log_record.value).and_then(|pointer| {
+                        match &blob_file {
+                            Some(blob_file) => blob_file.read(&pointer),
+                            None => Err(Errors::DataFileNotFound),
+                        }
+                    });
+                    match value {
+                        Ok(value) => value,
+                        Err(_) => {
+                            report.dropped_records += 1;
+                            continue;
+                        }
+                    }
+                }
+                _ => log_record.value,
+            };
+            engine.put_with_flags(Bytes::from(key), Bytes::from(value), log_record.flags)?;
+            report.live_keys += 1;
+        }
+        engine.sync()?;
+        Ok(report)
+    }
+    /// 打开数据库，恢复时遇到损坏的数据导致失败时按照 opts.repair_policy 修复之后重新打开
+    ///
+    /// 修复的情况记录在 open_report 中：Salvage 时为 repair，SkipCorrupted 时为 corrupted_records
+    pub fn open_or_repair(opts: Options) -> Result<Self> {
+        let err = match Engine::open(opts.clone()) {
+            Ok(engine) => return Ok(engine),
+            Err(e) if is_corruption(&e) => e,
+            Err(e) => return Err(e),
+        };
+        warn!("failed to open database: {}, repair with {:?}", err, opts.repair_policy);
+        match opts.repair_policy {
+            RepairPolicy::SkipCorrupted => Engine::open(Options {
+                recover_mode: RecoverMode::SkipCorrupted,
+                // 不校验 crc 时读到损坏的记录直接返回错误
+                verify_on_open: true,
+                ..opts
+            }),
+            RepairPolicy::Salvage => {
+                let report = salvage_dir(&opts)?;
+                let mut engine = Engine::open(opts)?;
+                engine.open_report.repair = Some(report);
+                Ok(engine)
+            }
+        }
+    }
+}
+
+/// 恢复时由损坏的数据导致的错误
+fn is_corruption(e: &Errors) -> bool {
+    matches!(
+        e,
+        Errors::InvalidLogRecordCrc
+            | Errors::InvalidLogRecordKey
+            | Errors::DataDirectoryCorrupted
+            | Errors::TooManyPendingTransactions
+    )
+}
+
+/// 按照 opts 把 dir 中的有效数据修复到相邻的临时目录中，再用临时目录替换 dir
+///
+/// 原来的目录改名为 dir.corrupted 保留下来，已经存在时依次尝试 dir.corrupted-1、dir.corrupted-2 ...
+fn salvage_dir(opts: &Options) -> Result<RepairReport> {
+    let dir = opts.dir_path.as_path();
+    let sibling = |suffix: &str| {
+        let mut name = dir.as_os_str().to_os_string();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    // 临时目录只会是上一次没有完成的修复留下的
+    let repair_dir = sibling(".repair");
+    if repair_dir.exists() {
+        fs::remove_dir_all(&repair_dir).map_err(|_| Errors::FailedToRepairDatabase)?;
+    }
+    let corrupted_dir = (0..)
+        .map(|n| match n {
+            0 => sibling(".corrupted"),
+            n => sibling(&format!(".corrupted-{}", n)),
+        })
+        .find(|path| !path.exists())
+        .unwrap();
+    let repair_opts = Options {
+        dir_path: repair_dir.clone(),
+        ..opts.clone()
+    };
+    let mut report = Engine::repair_with_options(dir.to_path_buf(), repair_opts)?;
+
+    // 先把原来的目录移开，替换失败时恢复
+    fs::rename(dir, &corrupted_dir).map_err(|_| Errors::FailedToRepairDatabase)?;
+    if let Err(e) = fs::rename(&repair_dir, dir) {
+        warn!("failed to replace database dir: {}", e);
+        if let Err(e) = fs::rename(&corrupted_dir, dir) {
+            warn!("failed to restore database dir: {}", e);
+        }
+        return Err(Errors::FailedToRepairDatabase);
+    }
+    report.backup_dir = Some(corrupted_dir);
+    Ok(report)
+}
+
+fn apply_record(live_records: &mut HashMap<Vec<u8>, LogRecord>, log_record: LogRecord) {
+    match log_record.rec_type {
+        LogRecordType::NORMAL | LogRecordType::BlobPointer => {
+            live_records.insert(log_record.key.clone(), log_record);
+        }
+        LogRecordType::DElETED => {
+            live_records.remove(&log_record.key);
+        }
+        LogRecordType::TxnFinish | LogRecordType::TxnBegin => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Seek, SeekFrom, Write},
+    };
+
+    use crate::{
+        data::data_file::get_data_file_name,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+    #[test]
+    fn test_repair() {
+        let src = PathBuf::from("./tmp/bitcask-rs-repair-src");
+        let dest = PathBuf::from("./tmp/bitcask-rs-repair-dest");
+        let opts = Options {
+            dir_path: src.clone(),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let mut corrupt_offset = 0;
+        for i in 0..100 {
+            if i == 50 {
+                corrupt_offset = engine.checkpoint().unwrap().write_offset;
+            }
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        engine.delete(get_test_key(99)).unwrap();
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        // 破坏中间的一条记录，并在文件末尾追加一条不完整的记录
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(src.join("000000000.data"))
+            .unwrap();
+        file.seek(SeekFrom::Start(corrupt_offset + 20)).unwrap();
+        file.write_all(b"x").unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        let record = LogRecord {
+            key: get_test_key(200).to_vec(),
+            value: get_test_value(200).to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            flags: 0,
+        };
+        file.write_all(&record.encode()[..10]).unwrap();
+        file.sync_all().unwrap();
+        assert!(Engine::open(opts.clone()).is_err());
+
+        let report = Engine::repair(src.clone(), dest.clone()).unwrap();
+        assert_eq!(100, report.recovered_records);
+        assert_eq!(2, report.dropped_records);
+        assert_eq!(98, report.live_keys);
+
+        // 修复之后的数据库可以正常使用
+        let engine2 = Engine::open(Options {
+            dir_path: dest.clone(),
+            ..Default::default()
+        })
+        .expect("failed to open engine");
+        for i in 0..99 {
+            let res = engine2.get(get_test_key(i));
+            if i == 50 {
+                assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+            } else {
+                assert_eq!(get_test_value(i as usize), res.unwrap());
+            }
+        }
+        assert_eq!(Errors::KeyNotFound, engine2.get(get_test_key(99)).err().unwrap());
+        engine2.put(get_test_key(50), get_test_value(50)).unwrap();
+
+        // dest 中已经存在数据时修复失败
+        let res = Engine::repair(src.clone(), dest.clone());
+        assert_eq!(Errors::DatabaseAlreadyExists, res.err().unwrap());
+
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+    #[test]
+    fn test_repair_with_options() {
+        let src = PathBuf::from("./tmp/bitcask-rs-repair-options-src");
+        let dest = PathBuf::from("./tmp/bitcask-rs-repair-options-dest");
+        let opts = Options {
+            dir_path: src.clone(),
+            data_file_size: 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            let flags = (i % 4) as u8;
+            engine.put_with_flags(get_test_key(i), get_test_value(i as usize), flags).unwrap();
+        }
+        let src_file_ids = engine.data_file_ids();
+        assert!(src_file_ids.len() > 2);
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        let list_files = |dir: &PathBuf| {
+            let mut files: Vec<PathBuf> =
+                fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+            files.sort();
+            files
+        };
+        let src_files = list_files(&src);
+
+        // 1.不合法的配置在读取 src 之前返回错误
+        let res = Engine::repair_with_options(
+            src.clone(),
+            Options {
+                dir_path: dest.clone(),
+                files_per_dir: Some(0),
+                ..opts.clone()
+            },
+        );
+        assert_eq!(Errors::InvalidFilesPerDir, res.err().unwrap());
+        assert!(!dest.exists());
+
+        // 2.src 的目录布局与 dest 不同时，src 中的文件不会被移动
+        let dest_opts = Options {
+            dir_path: dest.clone(),
+            files_per_dir: Some(4),
+            ..opts.clone()
+        };
+        let report = Engine::repair_with_options(src.clone(), dest_opts.clone()).unwrap();
+        assert_eq!(100, report.live_keys);
+        assert_eq!(src_files, list_files(&src));
+        for file_id in src_file_ids {
+            assert!(get_data_file_name(&opts, file_id).is_file());
+        }
+
+        // 3.修复后的数据库使用指定的配置，并保留 flags
+        let engine2 = Engine::open(dest_opts.clone()).expect("failed to open engine");
+        let dest_file_ids = engine2.data_file_ids();
+        assert!(dest_file_ids.len() > 2);
+        for file_id in dest_file_ids {
+            let data_file = get_data_file_name(&dest_opts, file_id);
+            assert!(fs::metadata(data_file).unwrap().len() <= dest_opts.data_file_size);
+        }
+        for i in 0..100 {
+            assert_eq!(get_test_value(i as usize), engine2.get(get_test_key(i)).unwrap());
+            assert_eq!((i % 4) as u8, engine2.metadata(get_test_key(i)).unwrap().flags);
+        }
+
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+    #[test]
+    fn test_open_or_repair() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-open-or-repair"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let corrupt = |opts: &Options| {
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+            let mut corrupt_offset = 0;
+            for i in 0..10 {
+                if i == 5 {
+                    corrupt_offset = engine.checkpoint().unwrap().write_offset;
+                }
+                engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+            }
+            engine.close().unwrap();
+            std::mem::drop(engine);
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .open(opts.dir_path.join("000000000.data"))
+                .unwrap();
+            file.seek(SeekFrom::Start(corrupt_offset + 20)).unwrap();
+            file.write_all(b"x").unwrap();
+            file.sync_all().unwrap();
+        };
+        let check = |engine: &Engine| {
+            for i in 0..10 {
+                let res = engine.get(get_test_key(i));
+                if i == 5 {
+                    assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+                } else {
+                    assert_eq!(get_test_value(i as usize), res.unwrap());
+                }
+            }
+        };
+
+        // 1.把有效数据修复到新的目录中替换原来的目录
+        corrupt(&opts);
+        assert_eq!(Errors::InvalidLogRecordCrc, Engine::open(opts.clone()).err().unwrap());
+        let engine = Engine::open_or_repair(opts.clone()).expect("failed to repair engine");
+        let report = engine.open_report().repair.clone().unwrap();
+        assert_eq!(1, report.dropped_records);
+        assert_eq!(9, report.live_keys);
+        // 原来的数据文件保留在备份目录中
+        let backup_dir = report.backup_dir.unwrap();
+        assert_eq!(PathBuf::from("./tmp/bitcask-rs-open-or-repair.corrupted"), backup_dir);
+        assert!(backup_dir.join("000000000.data").is_file());
+        check(&engine);
+        engine.put(get_test_key(5), get_test_value(5)).unwrap();
+        engine.close().unwrap();
+        std::mem::drop(engine);
+        // 修复之后可以直接打开
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(get_test_value(5), engine2.get(get_test_key(5)).unwrap());
+        std::mem::drop(engine2);
+        fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+
+        // 再次修复时不会覆盖之前的备份
+        corrupt(&opts);
+        let engine = Engine::open_or_repair(opts.clone()).expect("failed to repair engine");
+        let report = engine.open_report().repair.clone().unwrap();
+        assert_eq!(
+            PathBuf::from("./tmp/bitcask-rs-open-or-repair.corrupted-1"),
+            report.backup_dir.unwrap()
+        );
+        assert!(backup_dir.join("000000000.data").is_file());
+        std::mem::drop(engine);
+        fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+        fs::remove_dir_all(backup_dir).unwrap();
+        fs::remove_dir_all("./tmp/bitcask-rs-open-or-repair.corrupted-1").unwrap();
+
+        // 2.跳过损坏的记录打开
+        let skip_opts = Options {
+            repair_policy: RepairPolicy::SkipCorrupted,
+            ..opts.clone()
+        };
+        corrupt(&skip_opts);
+        let engine3 = Engine::open_or_repair(skip_opts.clone()).expect("failed to repair engine");
+        assert!(engine3.open_report().repair.is_none());
+        assert_eq!(1, engine3.open_report().corrupted_records.len());
+        check(&engine3);
+
+        fs::remove_dir_all(opts.dir_path).unwrap();
+    }
+    #[test]
+    fn test_open_or_repair_invalid_record_type() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-open-or-repair-record-type"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let mut corrupt_offset = 0;
+        for i in 0..10 {
+            if i == 5 {
+                corrupt_offset = engine.checkpoint().unwrap().write_offset;
+            }
+            engine.put(get_test_key(i), get_test_value(i as usize)).unwrap();
+        }
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        // 把一条记录的类型改成未知的值
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(opts.dir_path.join("000000000.data"))
+            .unwrap();
+        file.seek(SeekFrom::Start(corrupt_offset)).unwrap();
+        file.write_all(&[9]).unwrap();
+        file.sync_all().unwrap();
+        assert_eq!(Errors::DataDirectoryCorrupted, Engine::open(opts.clone()).err().unwrap());
+
+        // 头部无法解析，文件中之后的记录都无法读取
+        let engine = Engine::open_or_repair(opts.clone()).expect("failed to repair engine");
+        let report = engine.open_report().repair.clone().unwrap();
+        assert_eq!(1, report.dropped_records);
+        assert_eq!(5, report.live_keys);
+        for i in 0..5 {
+            assert_eq!(get_test_value(i as usize), engine.get(get_test_key(i)).unwrap());
+        }
+        assert_eq!(Errors::KeyNotFound, engine.get(get_test_key(5)).err().unwrap());
+
+        fs::remove_dir_all(report.backup_dir.unwrap()).unwrap();
+        fs::remove_dir_all(opts.dir_path).unwrap();
+    }
+}
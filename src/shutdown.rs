@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::{Condvar, Mutex};
+
+/// 协调关闭与正在进行的写操作
+///
+/// 写操作开始前调用 enter，关闭时调用 drain：drain 期间新的写操作会等待，
+/// drain 会等到已经开始的写操作全部完成之后再执行关闭操作
+pub(crate) struct ShutdownGate {
+    closing: AtomicBool,
+    /// 正在进行的写操作数量
+    in_flight: Mutex<usize>,
+    changed: Condvar,
+}
+
+/// 正在进行的写操作，drop 时计数减一
+pub(crate) struct InFlightGuard<'a> {
+    gate: &'a ShutdownGate,
+}
+
+impl ShutdownGate {
+    pub(crate) fn new() -> Self {
+        Self {
+            closing: AtomicBool::new(false),
+            in_flight: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+    /// 开始一次写操作，正在关闭时等待关闭完成
+    ///
+    /// 同一个线程中不能嵌套调用，否则并发的 drain 会与外层的写操作相互等待
+    pub(crate) fn enter(&self) -> InFlightGuard<'_> {
+        let mut in_flight = self.in_flight.lock();
+        while self.closing.load(Ordering::SeqCst) {
+            self.changed.wait(&mut in_flight);
+        }
+        *in_flight += 1;
+        InFlightGuard { gate: self }
+    }
+    /// 阻止新的写操作，等待正在进行的写操作全部完成之后执行 f
+    pub(crate) fn drain<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut in_flight = self.in_flight.lock();
+        // 同时只能有一个关闭操作
+        while self.closing.load(Ordering::SeqCst) {
+            self.changed.wait(&mut in_flight);
+        }
+        self.closing.store(true, Ordering::SeqCst);
+        while *in_flight > 0 {
+            self.changed.wait(&mut in_flight);
+        }
+        drop(in_flight);
+
+        let res = f();
+
+        let _in_flight = self.in_flight.lock();
+        self.closing.store(false, Ordering::SeqCst);
+        self.changed.notify_all();
+        res
+    }
+    /// 是否正在关闭
+    #[cfg(test)]
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.gate.in_flight.lock();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.gate.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicUsize, mpsc::channel},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    #[test]
+    fn test_shutdown_gate_drain() {
+        let gate = ShutdownGate::new();
+        let finished = AtomicUsize::new(0);
+        let (entered_tx, entered_rx) = channel();
+
+        thread::scope(|s| {
+            // 1.drain 等待已经开始的写操作完成
+            s.spawn(|| {
+                let _guard = gate.enter();
+                entered_tx.send(()).unwrap();
+                thread::sleep(Duration::from_millis(100));
+                finished.fetch_add(1, Ordering::SeqCst);
+            });
+            entered_rx.recv().unwrap();
+            let drained = gate.drain(|| {
+                // 2.drain 期间新的写操作等待关闭完成
+                s.spawn(|| {
+                    let _guard = gate.enter();
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+                thread::sleep(Duration::from_millis(50));
+                assert!(gate.is_closing());
+                finished.load(Ordering::SeqCst)
+            });
+            assert_eq!(1, drained);
+        });
+        assert!(!gate.is_closing());
+        assert_eq!(2, finished.load(Ordering::SeqCst));
+        assert_eq!(0, *gate.in_flight.lock());
+    }
+}
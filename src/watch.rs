@@ -0,0 +1,94 @@
+use std::sync::mpsc::{channel, Receiver};
+
+use bytes::Bytes;
+
+use crate::db::Engine;
+
+/// key 的变更通知
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchEvent {
+    /// 写入了新的 value
+    Put(Bytes),
+    Delete,
+}
+
+impl Engine {
+    /// 监听 key 的变更，put、delete 以及批量提交修改这个 key 之后都会发送一条通知
+    ///
+    /// 通知在索引更新之后发送，Receiver 被丢弃之后自动取消监听
+    pub fn watch(&self, key: Bytes) -> Receiver<WatchEvent> {
+        let key = self.normalize_key(key);
+        let (sender, receiver) = channel();
+        let mut watchers = self.watchers.lock();
+        watchers.entry(key.to_vec()).or_default().push(sender);
+        receiver
+    }
+    /// 通知监听 key 的所有调用方，同时清理已经关闭的监听
+    pub(crate) fn notify_watchers(&self, key: &[u8], event: WatchEvent) {
+        let mut watchers = self.watchers.lock();
+        if let Some(senders) = watchers.get_mut(key) {
+            senders.retain(|sender| sender.send(event.clone()).is_ok());
+            if senders.is_empty() {
+                watchers.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::mpsc::TryRecvError, thread, time::Duration};
+
+    use crate::{
+        options::{Options, WriteBatchOptions},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+    #[test]
+    fn test_watch() {
+        let opts = Options {
+            dir_path: PathBuf::from("./tmp/bitcask-rs-watch"),
+            data_file_size: 64 * 1024 * 1024,
+            ..Default::default()
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let receiver = engine.watch(get_test_key(1));
+
+        // 1.在其他线程中修改 key
+        thread::scope(|s| {
+            s.spawn(|| {
+                engine.put(get_test_key(1), get_test_value(1)).unwrap();
+                engine.put(get_test_key(2), get_test_value(2)).unwrap();
+                engine.delete(get_test_key(1)).unwrap();
+            });
+            let timeout = Duration::from_secs(5);
+            assert_eq!(
+                WatchEvent::Put(get_test_value(1)),
+                receiver.recv_timeout(timeout).unwrap()
+            );
+            assert_eq!(WatchEvent::Delete, receiver.recv_timeout(timeout).unwrap());
+        });
+        // 其他 key 的修改以及不存在的 key 的删除不会通知
+        engine.delete(get_test_key(1)).unwrap();
+        assert_eq!(Err(TryRecvError::Empty), receiver.try_recv());
+
+        // 2.批量提交同样会通知
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        wb.put(get_test_key(1), get_test_value(3)).unwrap();
+        wb.put(get_test_key(2), get_test_value(4)).unwrap();
+        assert_eq!(Err(TryRecvError::Empty), receiver.try_recv());
+        wb.commit().unwrap();
+        assert_eq!(WatchEvent::Put(get_test_value(3)), receiver.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), receiver.try_recv());
+
+        // 3.Receiver 被丢弃之后取消监听
+        std::mem::drop(receiver);
+        engine.put(get_test_key(1), get_test_value(5)).unwrap();
+        assert!(engine.watchers.lock().is_empty());
+
+        std::fs::remove_dir_all(opts.dir_path.clone()).unwrap();
+    }
+}